@@ -0,0 +1,286 @@
+use super::geometry::{Aabb, BoundingSphere, Ray};
+use super::scene::{Hit, Traceable};
+use std::sync::Arc;
+
+/** Maximum number of objects stored in a single leaf node. */
+const LEAF_SIZE: usize = 4;
+
+enum BvhNode {
+	Leaf {
+		bounds: Aabb,
+		objects: Vec<Arc<dyn Traceable>>,
+
+		/** Bounding spheres of the objects, in the same order */
+		spheres: Vec<BoundingSphere>,
+	},
+	Interior {
+		bounds: Aabb,
+		left: Box<BvhNode>,
+		right: Box<BvhNode>,
+	},
+}
+
+/** A bounding volume hierarchy over a set of objects, used to quickly find the objects a ray may hit. */
+pub struct Bvh {
+	objects: Vec<Arc<dyn Traceable>>,
+	root: Option<BvhNode>,
+
+	/** Objects of infinite size (such as planes), which are kept out of the hierarchy and tested for every ray */
+	unbounded: Vec<Arc<dyn Traceable>>,
+}
+
+impl Bvh {
+	pub fn new(objects: Vec<Arc<dyn Traceable>>) -> Bvh {
+		let (bounded, unbounded): (Vec<_>, Vec<_>) =
+			objects.iter().cloned().partition(|o| o.aabb().is_finite());
+		let root = if bounded.is_empty() {
+			None
+		} else {
+			Some(BvhNode::build(bounded))
+		};
+
+		Bvh {
+			objects,
+			root,
+			unbounded,
+		}
+	}
+
+	/** All objects contained in this hierarchy. */
+	pub fn objects(&self) -> &[Arc<dyn Traceable>] {
+		&self.objects
+	}
+
+	/** Find the nearest hit of the ray with any of the visible objects, up to the maximum distance. */
+	pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Hit> {
+		self.intersect_counting(ray, false, max_distance, &mut 0)
+	}
+
+	/** Find the nearest hit of a shadow ray with any of the objects that cast shadows, up to the maximum distance. */
+	pub fn intersect_shadow(&self, ray: &Ray, max_distance: f64) -> Option<Hit> {
+		self.intersect_counting(ray, true, max_distance, &mut 0)
+	}
+
+	/** Find the nearest hit (of a shadow ray if `shadow` is set), adding the number of nodes visited to `visits`. */
+	pub fn intersect_counting(
+		&self,
+		ray: &Ray,
+		shadow: bool,
+		max_distance: f64,
+		visits: &mut u64,
+	) -> Option<Hit> {
+		let mut nearest: Option<Hit> = None;
+		for object in &self.unbounded {
+			test(object.as_ref(), ray, shadow, max_distance, &mut nearest);
+		}
+
+		if let Some(root) = &self.root {
+			root.intersect(ray, shadow, max_distance, &mut nearest, visits);
+		}
+
+		nearest
+	}
+}
+
+/** Test the ray against the object, replacing the nearest hit when a nearer one is found */
+fn test(
+	object: &dyn Traceable,
+	ray: &Ray,
+	shadow: bool,
+	max_distance: f64,
+	nearest: &mut Option<Hit>,
+) {
+	let included = if shadow {
+		object.casts_shadows()
+	} else {
+		object.visible()
+	};
+	if !included {
+		return;
+	}
+
+	if let Some(hit) = object.intersect(ray) {
+		if hit.distance <= max_distance
+			&& nearest.as_ref().is_none_or(|n| hit.distance < n.distance)
+		{
+			*nearest = Some(hit);
+		}
+	}
+}
+
+impl BvhNode {
+	fn build(mut objects: Vec<Arc<dyn Traceable>>) -> BvhNode {
+		let bounds = objects
+			.iter()
+			.map(|o| o.aabb())
+			.fold(objects[0].aabb(), |a, b| a.union(&b));
+
+		if objects.len() <= LEAF_SIZE {
+			let spheres = objects.iter().map(|o| o.bounding_sphere()).collect();
+			return BvhNode::Leaf {
+				bounds,
+				objects,
+				spheres,
+			};
+		}
+
+		// Split at the median along the longest axis of the box spanned by the object centers
+		let first_center = objects[0].aabb().center();
+		let centers = objects.iter().fold(
+			Aabb {
+				min: first_center,
+				max: first_center,
+			},
+			|a, o| {
+				let center = o.aabb().center();
+				a.union(&Aabb {
+					min: center,
+					max: center,
+				})
+			},
+		);
+		let extent = centers.max - centers.min;
+		let axis = if extent.x >= extent.y && extent.x >= extent.z {
+			0
+		} else if extent.y >= extent.z {
+			1
+		} else {
+			2
+		};
+
+		objects.sort_by(|a, b| {
+			let ca = a.aabb().center();
+			let cb = b.aabb().center();
+			let (ka, kb) = match axis {
+				0 => (ca.x, cb.x),
+				1 => (ca.y, cb.y),
+				_ => (ca.z, cb.z),
+			};
+			ka.total_cmp(&kb)
+		});
+
+		let right = objects.split_off(objects.len() / 2);
+		BvhNode::Interior {
+			bounds,
+			left: Box::new(BvhNode::build(objects)),
+			right: Box::new(BvhNode::build(right)),
+		}
+	}
+
+	fn intersect(
+		&self,
+		ray: &Ray,
+		shadow: bool,
+		max_distance: f64,
+		nearest: &mut Option<Hit>,
+		visits: &mut u64,
+	) {
+		*visits += 1;
+
+		// Nodes entered beyond the nearest hit found so far (or the maximum distance) cannot contain a nearer hit
+		let limit = nearest.as_ref().map_or(max_distance, |n| n.distance);
+		match self {
+			BvhNode::Leaf {
+				bounds,
+				objects,
+				spheres,
+			} => {
+				if !bounds
+					.intersect(ray)
+					.is_some_and(|(entry, _)| entry <= limit)
+				{
+					return;
+				}
+
+				for (object, sphere) in objects.iter().zip(spheres) {
+					if sphere.hit(ray) {
+						test(object.as_ref(), ray, shadow, max_distance, nearest);
+					}
+				}
+			}
+			BvhNode::Interior {
+				bounds,
+				left,
+				right,
+			} => {
+				if !bounds
+					.intersect(ray)
+					.is_some_and(|(entry, _)| entry <= limit)
+				{
+					return;
+				}
+
+				left.intersect(ray, shadow, max_distance, nearest, visits);
+				right.intersect(ray, shadow, max_distance, nearest, visits);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Vector;
+	use crate::primitives::{Plane, Sphere};
+	use crate::scene::tests::diffuse;
+
+	/** Spheres on a grid in the XY plane at z = -10, with a plane (which has an infinite box) after every `plane_every` spheres */
+	fn objects(count: usize, plane_every: usize) -> Vec<Arc<dyn Traceable>> {
+		let material = Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0)));
+		(0..count)
+			.map(|i| -> Arc<dyn Traceable> {
+				if i % plane_every == plane_every - 1 {
+					Arc::new(Plane {
+						point: Vector::new(0.0, -100.0 - i as f64, 0.0),
+						normal: Vector::new(0.0, 1.0, 0.0),
+						double_sided: false,
+						material: material.clone(),
+					})
+				} else {
+					Arc::new(Sphere {
+						center: Vector::new((i % 50) as f64, (i / 50) as f64, -10.0),
+						radius: 0.4,
+						material: material.clone(),
+					})
+				}
+			})
+			.collect()
+	}
+
+	/** Distance to the nearest hit found by testing every object */
+	fn nearest_distance(objects: &[Arc<dyn Traceable>], ray: &Ray) -> Option<f64> {
+		objects
+			.iter()
+			.filter_map(|o| o.intersect(ray))
+			.map(|hit| hit.distance)
+			.min_by(f64::total_cmp)
+	}
+
+	#[test]
+	fn planes_are_found_alongside_hierarchy() {
+		let objects = objects(2000, 7);
+		let bvh = Bvh::new(objects.clone());
+		let down = Vector::new(0.1, -1.0, -0.3).normalize();
+		for i in 0..40 {
+			// Offset from the sphere centers, so that no ray is (nearly) tangent to a sphere
+			let origin = Vector::new(i as f64 * 1.3 + 0.05, 20.0, 5.0);
+			for direction in [Vector::new(0.0, 0.0, -1.0), down] {
+				let ray = Ray::new(origin, direction);
+				let expected = nearest_distance(&objects, &ray);
+				let found = bvh.intersect(&ray, f64::INFINITY).map(|hit| hit.distance);
+				assert_eq!(found, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn traversal_visits_fewer_nodes_than_objects() {
+		let objects = objects(400, usize::MAX);
+		let bvh = Bvh::new(objects.clone());
+		let ray = Ray::new(Vector::new(10.0, 3.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		let mut visits = 0;
+		let hit = bvh.intersect_counting(&ray, false, f64::INFINITY, &mut visits);
+		assert_eq!(hit.map(|h| h.distance), nearest_distance(&objects, &ray));
+		assert!(visits > 0 && visits < objects.len() as u64 / 10);
+	}
+}
@@ -0,0 +1,297 @@
+use super::geometry::{Aabb, Differentials, Matrix4, Ray, Vector};
+use super::sampling::{self, SamplePattern};
+use std::str::FromStr;
+
+/** A camera positioned in the scene, looking towards a target point. With a zero aperture this is a pinhole camera;
+otherwise it is a thin lens camera that only renders objects at the focus distance sharply. */
+#[derive(Clone, Copy)]
+pub struct Camera {
+	pub position: Vector,
+	pub look_at: Vector,
+	pub up: Vector,
+
+	/** Field of view in radians, along the axis of the image given by fov_axis */
+	pub fov: f64,
+	pub fov_axis: FovAxis,
+
+	/** Diameter of the lens */
+	pub aperture: f64,
+
+	/** Distance from the camera to the plane that is in focus */
+	pub focus_distance: f64,
+
+	/** Fraction of the frame during which the shutter is open; rays are cast at random times in it (0 for no motion blur) */
+	pub shutter: f64,
+
+	pub projection: Projection,
+}
+
+/** How the camera maps points in the scene onto the image. */
+#[derive(Clone, Copy)]
+pub enum Projection {
+	/** Rays fan out from the camera position according to the field of view */
+	Perspective,
+
+	/** Parallel rays in the view direction from a rectangle of the given width (in scene units) around the camera */
+	Orthographic { width: f64 },
+}
+
+/** Axis of the image along which the field of view is measured; along the other axis it follows from the aspect ratio. */
+#[derive(Clone, Copy)]
+pub enum FovAxis {
+	Vertical,
+	Horizontal,
+}
+
+impl FromStr for FovAxis {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<FovAxis, String> {
+		match s {
+			"vertical" => Ok(FovAxis::Vertical),
+			"horizontal" => Ok(FovAxis::Horizontal),
+			_ => Err(format!("unknown field of view axis '{}'", s)),
+		}
+	}
+}
+
+impl Camera {
+	/** This camera moved around the Y axis through the point it focuses on by the angle (in radians), facing that point */
+	pub fn orbit(&self, angle: f64) -> Camera {
+		let forward = (self.look_at - self.position).normalize();
+		let center = self.position + forward * self.focus_distance;
+		let offset = Matrix4::rotation_y(angle).transform_vector(self.position - center);
+
+		Camera {
+			position: center + offset,
+			look_at: center,
+			..*self
+		}
+	}
+
+	/** This camera moved back along its view direction so that the box fits the image (of the given aspect ratio) */
+	pub fn framing(&self, bounds: &Aabb, aspect: f64) -> Camera {
+		// Fit the sphere around the box, so that the box fits regardless of the view direction
+		let center = bounds.center();
+		let radius = (bounds.max - bounds.min).norm() / 2.0;
+		let forward = (self.look_at - self.position).normalize();
+
+		let (distance, projection) = match self.projection {
+			Projection::Perspective => {
+				// The sphere needs to fit in the narrowest of the horizontal and vertical fields of view
+				let (tan_x, tan_y) = self.half_fov_tangents(aspect);
+				let half_angle = tan_x.min(tan_y).atan();
+				(radius / half_angle.sin(), Projection::Perspective)
+			}
+			Projection::Orthographic { .. } => (
+				2.0 * radius,
+				Projection::Orthographic {
+					width: 2.0 * radius * aspect.max(1.0),
+				},
+			),
+		};
+
+		Camera {
+			position: center - forward * distance,
+			look_at: center,
+			focus_distance: distance,
+			projection,
+			..*self
+		}
+	}
+
+	/** Tangents of half the horizontal and vertical field of view, for an image of the given aspect ratio (width / height) */
+	fn half_fov_tangents(&self, aspect: f64) -> (f64, f64) {
+		let tangent = (self.fov / 2.0).tan();
+		match self.fov_axis {
+			FovAxis::Vertical => (tangent * aspect, tangent),
+			FovAxis::Horizontal => (tangent, tangent / aspect),
+		}
+	}
+
+	/** Calculate the primary ray passing through the center of pixel (x, y) of an image of the given size. */
+	pub fn ray_for_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> Ray {
+		self.ray_through(f64::from(x) + 0.5, f64::from(y) + 0.5, width, height)
+	}
+
+	/** Calculate the primary rays for pixel (x, y), through samples x samples points placed according to the pattern. */
+	pub fn rays_for_pixel(
+		&self,
+		x: u32,
+		y: u32,
+		width: u32,
+		height: u32,
+		samples: u32,
+		pattern: SamplePattern,
+	) -> impl Iterator<Item = Ray> + '_ {
+		pattern.offsets(samples).into_iter().map(move |(dx, dy)| {
+			let ray = self.ray_through(f64::from(x) + dx, f64::from(y) + dy, width, height);
+
+			// Samples are spaced closer than pixels, so each covers a smaller area
+			match ray.differentials() {
+				Some(differentials) => {
+					ray.with_differentials(differentials.scaled(1.0 / f64::from(samples)))
+				}
+				None => ray,
+			}
+		})
+	}
+
+	/** Calculate a primary ray through a uniformly distributed random point in pixel (x, y). */
+	pub fn jittered_ray_for_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> Ray {
+		let (dx, dy) = (sampling::random(), sampling::random());
+		self.ray_through(f64::from(x) + dx, f64::from(y) + dy, width, height)
+	}
+
+	/** Calculate the primary ray through point (px, py) in pixel units, from the top left corner, with differentials */
+	fn ray_through(&self, px: f64, py: f64, width: u32, height: u32) -> Ray {
+		let time = if self.shutter > 0.0 {
+			self.shutter * sampling::random()
+		} else {
+			0.0
+		};
+		let lens = match self.projection {
+			Projection::Perspective if self.aperture > 0.0 => sampling::in_unit_disk(),
+			_ => (0.0, 0.0),
+		};
+
+		// The neighbouring rays start from the same point on the lens, so that only the pixel position differs
+		let ray = self.lens_ray(px, py, width, height, lens);
+		let right = self.lens_ray(px + 1.0, py, width, height, lens);
+		let down = self.lens_ray(px, py + 1.0, width, height, lens);
+		let differentials = Differentials {
+			origin_dx: right.origin() - ray.origin(),
+			origin_dy: down.origin() - ray.origin(),
+			direction_dx: right.direction() - ray.direction(),
+			direction_dy: down.direction() - ray.direction(),
+		};
+		ray.with_differentials(differentials).at_time(time)
+	}
+
+	/** Calculate the primary ray through point (px, py), from point (x, y) on the unit disk scaled to the lens if any */
+	fn lens_ray(&self, px: f64, py: f64, width: u32, height: u32, (x, y): (f64, f64)) -> Ray {
+		// Orthonormal basis for the camera
+		let forward = (self.look_at - self.position).normalize();
+		let right = forward.cross(&self.up).normalize();
+		let up = right.cross(&forward);
+
+		let w = f64::from(width);
+		let h = f64::from(height);
+
+		if let Projection::Orthographic { width: view_width } = self.projection {
+			let ox = (2.0 * px / w - 1.0) * view_width / 2.0;
+			let oy = (1.0 - 2.0 * py / h) * view_width * h / w / 2.0;
+			return Ray::new(self.position + (right * ox) + (up * oy), forward);
+		}
+
+		let (tan_x, tan_y) = self.half_fov_tangents(w / h);
+		let fx = (2.0 * px / w - 1.0) * tan_x;
+		let fy = (1.0 - 2.0 * py / h) * tan_y;
+
+		let direction = forward + (right * fx) + (up * fy);
+
+		if self.aperture <= 0.0 {
+			return Ray::new(self.position, direction);
+		}
+
+		// Thin lens: start from the point on the lens, aimed at the point on the focus plane
+		let focus_point = self.position + direction * (self.focus_distance / (direction ^ forward));
+		let radius = self.aperture / 2.0;
+		let lens_point = self.position + (right * (radius * x)) + (up * (radius * y));
+		Ray::new(lens_point, focus_point - lens_point)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::primitives::Sphere;
+	use crate::scene::tests::diffuse;
+	use crate::scene::Traceable;
+	use std::sync::Arc;
+
+	fn camera(fov_axis: FovAxis, projection: Projection) -> Camera {
+		Camera {
+			position: Vector::default(),
+			look_at: Vector::new(0.0, 0.0, -1.0),
+			up: Vector::new(0.0, 1.0, 0.0),
+			fov: 60.0_f64.to_radians(),
+			fov_axis,
+			aperture: 0.0,
+			focus_distance: 1.0,
+			shutter: 0.0,
+			projection,
+		}
+	}
+
+	/** Point where the ray through the center of the pixel crosses the plane at z = -1 (for orthographic cameras, its origin) */
+	fn image_point(camera: &Camera, x: u32, y: u32) -> (f64, f64) {
+		let ray = camera.ray_for_pixel(x, y, 1024, 512);
+		match camera.projection {
+			Projection::Perspective => {
+				let point = ray.direction() / -ray.direction().z;
+				(point.x, point.y)
+			}
+			Projection::Orthographic { .. } => (ray.origin().x, ray.origin().y),
+		}
+	}
+
+	#[test]
+	fn wide_image_is_not_stretched() {
+		for camera in [
+			camera(FovAxis::Vertical, Projection::Perspective),
+			camera(FovAxis::Horizontal, Projection::Perspective),
+			camera(FovAxis::Vertical, Projection::Orthographic { width: 4.0 }),
+		] {
+			// Neighbouring pixels are as far apart horizontally as vertically
+			let (x, y) = image_point(&camera, 512, 256);
+			let (right, _) = image_point(&camera, 513, 256);
+			let (_, down) = image_point(&camera, 512, 257);
+			assert!(((right - x) - (y - down)).abs() < 1e-12);
+
+			// The image is twice as wide as it is high; the centers of the corner pixels are 1023 by 511 pixels apart
+			let (left, top) = image_point(&camera, 0, 0);
+			let (right, bottom) = image_point(&camera, 1023, 511);
+			assert!(((right - left) / 1023.0 - (top - bottom) / 511.0).abs() < 1e-12);
+		}
+
+		// The field of view is measured along the given axis
+		let tangent = 30.0_f64.to_radians().tan();
+		let (_, top) = image_point(&camera(FovAxis::Vertical, Projection::Perspective), 512, 0);
+		assert!((top - tangent * 511.0 / 512.0).abs() < 1e-12);
+		let (left, _) = image_point(
+			&camera(FovAxis::Horizontal, Projection::Perspective),
+			0,
+			256,
+		);
+		assert!((left + tangent * 1023.0 / 1024.0).abs() < 1e-12);
+	}
+
+	#[test]
+	fn orthographic_size_does_not_depend_on_distance() {
+		// Number of pixels of the middle row of a 64x64 image in which a unit sphere at the distance is seen
+		let width_at = |camera: &Camera, distance: f64| {
+			let sphere = Sphere {
+				center: Vector::new(0.0, 0.0, -distance),
+				radius: 1.0,
+				material: Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0))),
+			};
+			(0..64)
+				.filter(|&x| {
+					sphere
+						.intersect(&camera.ray_for_pixel(x, 32, 64, 64))
+						.is_some()
+				})
+				.count()
+		};
+
+		// A quarter of the view is covered by the sphere wherever it is
+		let orthographic = camera(FovAxis::Vertical, Projection::Orthographic { width: 8.0 });
+		assert_eq!(width_at(&orthographic, 5.0), 16);
+		assert_eq!(width_at(&orthographic, 20.0), 16);
+
+		// With perspective, far objects look smaller
+		let perspective = camera(FovAxis::Vertical, Projection::Perspective);
+		assert!(width_at(&perspective, 5.0) > 2 * width_at(&perspective, 20.0));
+	}
+}
@@ -0,0 +1,552 @@
+use super::bvh::Bvh;
+use super::geometry::{Matrix4, Vector};
+use super::mesh::{self, MeshError};
+use super::primitives::{
+	AxisAlignedBox, Cone, Csg, CsgOperation, Cylinder, Disk, Identified, Moving, Plane, Quad,
+	Rectangle, Sphere, Torus, Transformed, Triangle, Visibility,
+};
+use super::scene::{
+	Background, EnvironmentMap, EnvironmentMapping, Light, Material, MaterialError,
+	MaterialLibrary, RadianceImage, Scene, Traceable,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+	Io(std::io::Error),
+	Json(serde_json::Error),
+	Mesh(MeshError),
+	Image(image::ImageError),
+	InvalidTransform,
+	InvalidCsgOperand,
+	InvalidMaterial { object: usize, error: MaterialError },
+	InvalidLibraryMaterial { name: String, error: MaterialError },
+	UnknownMaterial(String),
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SceneError::Io(e) => write!(f, "I/O error: {}", e),
+			SceneError::Json(e) => write!(f, "invalid scene description: {}", e),
+			SceneError::Mesh(e) => write!(f, "could not load mesh: {}", e),
+			SceneError::Image(e) => write!(f, "could not load environment map: {}", e),
+			SceneError::InvalidTransform => write!(f, "object transformation is not invertible"),
+			SceneError::InvalidCsgOperand => {
+				write!(
+					f,
+					"operands of CSG objects must be single objects (not meshes)"
+				)
+			}
+			SceneError::InvalidMaterial { object, error } => {
+				write!(f, "material of object {} is invalid: {}", object, error)
+			}
+			SceneError::InvalidLibraryMaterial { name, error } => {
+				write!(
+					f,
+					"material '{}' in the library is invalid: {}",
+					name, error
+				)
+			}
+			SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+		}
+	}
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+	fn from(e: std::io::Error) -> SceneError {
+		SceneError::Io(e)
+	}
+}
+
+impl From<serde_json::Error> for SceneError {
+	fn from(e: serde_json::Error) -> SceneError {
+		SceneError::Json(e)
+	}
+}
+
+impl From<MeshError> for SceneError {
+	fn from(e: MeshError) -> SceneError {
+		SceneError::Mesh(e)
+	}
+}
+
+impl From<image::ImageError> for SceneError {
+	fn from(e: image::ImageError) -> SceneError {
+		SceneError::Image(e)
+	}
+}
+
+/** An object in a scene description, tagged by its type (e.g. `{"type": "sphere", ...}`). */
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ObjectDescription {
+	Sphere(Sphere),
+	Plane(Plane),
+	Rectangle(Rectangle),
+	Quad(Quad),
+	Triangle(Triangle),
+	#[serde(rename = "box")]
+	AxisAlignedBox(AxisAlignedBox),
+	Cylinder(Cylinder),
+	Cone(Cone),
+	Torus(Torus),
+	Disk(Disk),
+	Mesh {
+		path: String,
+		material: Arc<Material>,
+	},
+
+	/** Combination of two solid objects (spheres, boxes or other CSG objects, optionally transformed) */
+	Csg {
+		operation: CsgOperation,
+		a: Box<ObjectDescription>,
+		b: Box<ObjectDescription>,
+	},
+
+	/** An object that is scaled, then rotated (in degrees around the X, Y and Z axes, in that order), then moved */
+	Transformed {
+		object: Box<ObjectDescription>,
+		#[serde(default)]
+		translate: Vector,
+		#[serde(default)]
+		rotate: Vector,
+		#[serde(default = "unit_scale")]
+		scale: Vector,
+	},
+}
+
+fn unit_scale() -> Vector {
+	Vector {
+		x: 1.0,
+		y: 1.0,
+		z: 1.0,
+	}
+}
+
+impl ObjectDescription {
+	/** The material of the described object */
+	pub fn material(&self) -> &Material {
+		match self {
+			ObjectDescription::Sphere(sphere) => &sphere.material,
+			ObjectDescription::Plane(plane) => &plane.material,
+			ObjectDescription::Rectangle(rectangle) => &rectangle.material,
+			ObjectDescription::Quad(quad) => &quad.material,
+			ObjectDescription::Triangle(triangle) => &triangle.material,
+			ObjectDescription::AxisAlignedBox(b) => &b.material,
+			ObjectDescription::Cylinder(cylinder) => &cylinder.material,
+			ObjectDescription::Cone(cone) => &cone.material,
+			ObjectDescription::Torus(torus) => &torus.material,
+			ObjectDescription::Disk(disk) => &disk.material,
+			ObjectDescription::Mesh { material, .. } => material,
+			ObjectDescription::Csg { a, .. } => a.material(),
+			ObjectDescription::Transformed { object, .. } => object.material(),
+		}
+	}
+
+	/** Check the materials of the described object, or of all objects it is made of */
+	fn validate_materials(&self) -> Result<(), MaterialError> {
+		match self {
+			ObjectDescription::Csg { a, b, .. } => {
+				a.validate_materials()?;
+				b.validate_materials()
+			}
+			ObjectDescription::Transformed { object, .. } => object.validate_materials(),
+			_ => self.material().validate(),
+		}
+	}
+
+	/** Create the object(s) described. A mesh yields multiple objects, with materials named in it taken from the library. */
+	pub fn into_objects(
+		self,
+		library: &MaterialLibrary,
+	) -> Result<Vec<Arc<dyn Traceable>>, SceneError> {
+		Ok(match self {
+			ObjectDescription::Sphere(sphere) => vec![Arc::new(sphere)],
+			ObjectDescription::Plane(plane) => vec![Arc::new(plane)],
+			ObjectDescription::Rectangle(rectangle) => vec![Arc::new(rectangle)],
+			ObjectDescription::Quad(quad) => vec![Arc::new(quad)],
+			ObjectDescription::Triangle(triangle) => vec![Arc::new(triangle)],
+			ObjectDescription::AxisAlignedBox(b) => vec![Arc::new(b)],
+			ObjectDescription::Cylinder(cylinder) => vec![Arc::new(cylinder)],
+			ObjectDescription::Cone(cone) => vec![Arc::new(cone)],
+			ObjectDescription::Torus(torus) => vec![Arc::new(torus)],
+			ObjectDescription::Disk(disk) => vec![Arc::new(disk)],
+			ObjectDescription::Mesh { path, material } => mesh::load_obj(&path, material, library)?,
+			ObjectDescription::Csg { operation, a, b } => {
+				let operand = |description: Box<ObjectDescription>| {
+					let mut objects = description.into_objects(library)?;
+					if objects.len() != 1 {
+						return Err(SceneError::InvalidCsgOperand);
+					}
+					Ok(objects.remove(0))
+				};
+				vec![Arc::new(Csg {
+					a: operand(a)?,
+					b: operand(b)?,
+					operation,
+				})]
+			}
+			ObjectDescription::Transformed {
+				object,
+				translate,
+				rotate,
+				scale,
+			} => {
+				let transform = Matrix4::translation(translate)
+					* Matrix4::rotation_z(rotate.z.to_radians())
+					* Matrix4::rotation_y(rotate.y.to_radians())
+					* Matrix4::rotation_x(rotate.x.to_radians())
+					* Matrix4::scaling(scale);
+
+				let mut objects: Vec<Arc<dyn Traceable>> = vec![];
+				for object in object.into_objects(library)? {
+					let transformed =
+						Transformed::new(object, transform).ok_or(SceneError::InvalidTransform)?;
+					objects.push(Arc::new(transformed));
+				}
+				objects
+			}
+		})
+	}
+}
+
+/** An object in a scene description, with flags that apply to it as a whole. */
+#[derive(Deserialize, Serialize)]
+pub struct ObjectEntry {
+	#[serde(flatten)]
+	pub object: ObjectDescription,
+
+	/** Whether the object is seen by camera rays (and in reflections and refractions) */
+	#[serde(default = "enabled")]
+	pub visible: bool,
+
+	#[serde(default = "enabled")]
+	pub casts_shadows: bool,
+
+	/** Distance the object moves during a frame, for motion blur (see the camera shutter) */
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub velocity: Option<Vector>,
+
+	/** Identifier reported with hits on the object (see the object-id debug mode) */
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<u32>,
+}
+
+fn enabled() -> bool {
+	true
+}
+
+/** Image files making up the environment map. */
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EnvironmentMapDescription {
+	Image(String),
+
+	/** Faces of a cube map, facing the positive and negative X, Y and Z axes */
+	CubeMap {
+		px: String,
+		nx: String,
+		py: String,
+		ny: String,
+		pz: String,
+		nz: String,
+	},
+}
+
+/** Camera settings in a scene file. Settings that are not specified are taken from the command line. */
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct CameraDescription {
+	pub position: Option<Vector>,
+	pub look_at: Option<Vector>,
+	pub up: Option<Vector>,
+
+	/** Field of view in degrees */
+	pub fov: Option<f64>,
+	pub aperture: Option<f64>,
+	pub focus_distance: Option<f64>,
+}
+
+/** Render settings in a scene file. Settings that are not specified are taken from the command line. */
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct RenderDescription {
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+
+	/** Number of samples per pixel along each axis */
+	pub samples: Option<u32>,
+
+	/** Maximum ray depth */
+	pub depth: Option<i32>,
+}
+
+/** A scene as described in a JSON scene file. */
+#[derive(Deserialize, Serialize)]
+pub struct SceneDescription {
+	pub objects: Vec<ObjectEntry>,
+	pub lights: Vec<Light>,
+	/** Background color or gradient (also accepted as `environment_color`) */
+	#[serde(alias = "environment_color")]
+	pub background: Background,
+
+	/** Path to an image file to use as environment map, or paths to the six faces of a cube map */
+	#[serde(default)]
+	pub environment_map: Option<EnvironmentMapDescription>,
+
+	#[serde(default)]
+	pub environment_mapping: EnvironmentMapping,
+
+	/** Whether low dynamic range images hold sRGB colors, which are converted to linear colors when loaded */
+	#[serde(default = "enabled")]
+	pub input_degamma: bool,
+
+	#[serde(default)]
+	pub ambient: Vector,
+
+	/** Color that distant objects fade to (see `fog_density`) */
+	#[serde(default)]
+	pub fog_color: Vector,
+
+	/** Fraction of light scattered by fog per unit of distance from the camera */
+	#[serde(default)]
+	pub fog_density: f64,
+
+	/** Settings for viewing the scene; options given on the command line take precedence over these */
+	#[serde(default)]
+	pub camera: CameraDescription,
+
+	#[serde(default)]
+	pub render: RenderDescription,
+
+	/** Named materials, which faces in meshes can refer to with `usemtl` */
+	#[serde(default)]
+	pub materials: MaterialLibrary,
+}
+
+impl SceneDescription {
+	pub fn into_scene(self) -> Result<Scene, SceneError> {
+		for (name, material) in self.materials.iter() {
+			material
+				.validate()
+				.map_err(|error| SceneError::InvalidLibraryMaterial {
+					name: name.to_string(),
+					error,
+				})?;
+		}
+
+		let mut objects: Vec<Arc<dyn Traceable>> = vec![];
+		for (index, entry) in self.objects.into_iter().enumerate() {
+			entry
+				.object
+				.validate_materials()
+				.map_err(|error| SceneError::InvalidMaterial {
+					object: index,
+					error,
+				})?;
+
+			let (visible, casts_shadows) = (entry.visible, entry.casts_shadows);
+			let (velocity, id) = (entry.velocity, entry.id);
+			for object in entry.object.into_objects(&self.materials)? {
+				let object: Arc<dyn Traceable> = match velocity {
+					Some(velocity) => Arc::new(Moving { object, velocity }),
+					None => object,
+				};
+				let object: Arc<dyn Traceable> = match id {
+					Some(id) => Arc::new(Identified { object, id }),
+					None => object,
+				};
+				if visible && casts_shadows {
+					objects.push(object);
+				} else {
+					objects.push(Arc::new(Visibility {
+						object,
+						visible,
+						casts_shadows,
+					}));
+				}
+			}
+		}
+
+		let environment_map = match self.environment_map {
+			Some(EnvironmentMapDescription::Image(path)) => Some(EnvironmentMap::Image(
+				RadianceImage::open(path, self.input_degamma)?,
+			)),
+			Some(EnvironmentMapDescription::CubeMap {
+				px,
+				nx,
+				py,
+				ny,
+				pz,
+				nz,
+			}) => Some(EnvironmentMap::CubeMap {
+				px: RadianceImage::open(px, self.input_degamma)?,
+				nx: RadianceImage::open(nx, self.input_degamma)?,
+				py: RadianceImage::open(py, self.input_degamma)?,
+				ny: RadianceImage::open(ny, self.input_degamma)?,
+				pz: RadianceImage::open(pz, self.input_degamma)?,
+				nz: RadianceImage::open(nz, self.input_degamma)?,
+			}),
+			None => None,
+		};
+
+		Ok(Scene {
+			objects: Bvh::new(objects),
+			grid: None,
+			lights: self.lights,
+			background: self.background,
+			environment_map,
+			environment_mapping: self.environment_mapping,
+			ambient: self.ambient,
+			russian_roulette: false,
+			environment_samples: 0,
+			global_illumination: false,
+			max_distance: f64::INFINITY,
+			fog_color: self.fog_color,
+			fog_density: self.fog_density,
+			statistics: None,
+		})
+	}
+}
+
+/** Load a library of named materials from a JSON file mapping names to materials. */
+pub fn load_material_library(path: &str) -> Result<MaterialLibrary, SceneError> {
+	let library: MaterialLibrary = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+	for (name, material) in library.iter() {
+		material
+			.validate()
+			.map_err(|error| SceneError::InvalidLibraryMaterial {
+				name: name.to_string(),
+				error,
+			})?;
+	}
+	Ok(library)
+}
+
+/** Replace each material given by name (a string instead of a material definition) by its definition. */
+fn resolve_material_names(
+	value: &mut Value,
+	definitions: &Map<String, Value>,
+) -> Result<(), SceneError> {
+	match value {
+		Value::Object(fields) => {
+			for (key, field) in fields.iter_mut() {
+				match field.as_str() {
+					Some(name) if key == "material" => {
+						*field = definitions
+							.get(name)
+							.cloned()
+							.ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))?;
+					}
+					_ => resolve_material_names(field, definitions)?,
+				}
+			}
+		}
+		Value::Array(values) => {
+			for value in values {
+				resolve_material_names(value, definitions)?;
+			}
+		}
+		_ => {}
+	}
+	Ok(())
+}
+
+/** Load a scene from a JSON scene description file (see `load_scene_description`). */
+pub fn load_scene(path: &str) -> Result<Scene, SceneError> {
+	load_scene_description(path)?.into_scene()
+}
+
+/** Read a JSON scene description file. Objects may give the name of a material instead of a material, which refers to
+a material in the `materials` object of the scene file, or in the library file at the path given by `material_library`
+(the scene file's own materials take precedence). */
+pub fn load_scene_description(path: &str) -> Result<SceneDescription, SceneError> {
+	let mut value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+	let mut definitions = Map::new();
+	if let Some(fields) = value.as_object_mut() {
+		if let Some(Value::String(library_path)) = fields.remove("material_library") {
+			let library: Map<String, Value> =
+				serde_json::from_reader(BufReader::new(File::open(library_path)?))?;
+			definitions.extend(library);
+		}
+		if let Some(Value::Object(materials)) = fields.get("materials") {
+			definitions.extend(materials.clone());
+		}
+		if !definitions.is_empty() {
+			fields.insert("materials".to_string(), Value::Object(definitions.clone()));
+		}
+		if let Some(objects) = fields.get_mut("objects") {
+			resolve_material_names(objects, &definitions)?;
+		}
+	}
+
+	Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Ray;
+	use crate::scene::tests::diffuse;
+
+	#[test]
+	fn material_library_round_trip() {
+		let mut library = MaterialLibrary::default();
+		library.insert(
+			"red".to_string(),
+			Arc::new(diffuse(Vector::new(1.0, 0.0, 0.0))),
+		);
+		library.insert(
+			"mirror".to_string(),
+			Arc::new(Material {
+				albedo_diffuse: 0.0,
+				albedo_reflect: 0.9,
+				..diffuse(Vector::new(0.5, 0.5, 0.5))
+			}),
+		);
+
+		let id = std::process::id();
+		let library_path = std::env::temp_dir().join(format!("tyray-library-{}.json", id));
+		let library_path = library_path.to_str().unwrap().to_string();
+		std::fs::write(&library_path, serde_json::to_string(&library).unwrap()).unwrap();
+		let loaded = load_material_library(&library_path);
+
+		// A scene referring to the materials in the library by name
+		let scene = serde_json::json!({
+			"material_library": library_path,
+			"background": { "x": 0, "y": 0, "z": 0 },
+			"ambient": { "x": 1, "y": 1, "z": 1 },
+			"lights": [],
+			"objects": [
+				{ "type": "sphere", "center": { "x": 0, "y": 0, "z": -5 }, "radius": 1, "material": "red" },
+				{ "type": "sphere", "center": { "x": 3, "y": 0, "z": -5 }, "radius": 1, "material": "mirror" }
+			]
+		});
+		let scene_path = std::env::temp_dir().join(format!("tyray-library-scene-{}.json", id));
+		let scene_path = scene_path.to_str().unwrap().to_string();
+		std::fs::write(&scene_path, scene.to_string()).unwrap();
+		let description = load_scene_description(&scene_path);
+		std::fs::remove_file(&library_path).unwrap();
+		std::fs::remove_file(&scene_path).unwrap();
+
+		let loaded = loaded.expect("could not load library");
+		assert_eq!(loaded.iter().count(), 2);
+		assert_eq!(
+			serde_json::to_value(&loaded).unwrap(),
+			serde_json::to_value(&library).unwrap()
+		);
+
+		let description = description.expect("could not load scene");
+		assert_eq!(description.materials.iter().count(), 2);
+		let scene = description.into_scene().expect("could not create scene");
+		let color = scene.cast_ray(&Ray::new(Vector::default(), Vector::new(0.0, 0.0, -1.0)), 1);
+		assert_eq!(color, Vector::new(1.0, 0.0, 0.0));
+	}
+}
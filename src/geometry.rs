@@ -1,17 +1,44 @@
-use std::ops::{Add, BitXor, Mul, Sub};
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign, BitXor, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
 /** A three-dimensional vector. */
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct Vector {
 	pub x: f64,
 	pub y: f64,
 	pub z: f64,
 }
 
-/** A ray consisting of an origin point and a direction vector (normalized). */
+/** A ray consisting of an origin point and a direction vector (normalized), cast at a point in time during the frame
+(from 0 at the start of the frame, for motion blur). */
 pub struct Ray {
 	origin: Vector,
 	direction: Vector,
+	time: f64,
+	differentials: Option<Differentials>,
+}
+
+/** How the origin and (normalized) direction of a camera ray change when moving across the image by one pixel (or
+sample) to the right (dx) and down (dy). This tells how large an area of a surface the ray covers where it hits it. */
+#[derive(Clone, Copy)]
+pub struct Differentials {
+	pub origin_dx: Vector,
+	pub origin_dy: Vector,
+	pub direction_dx: Vector,
+	pub direction_dy: Vector,
+}
+
+impl Differentials {
+	/** The differentials for a spacing of the given fraction of the original spacing */
+	pub fn scaled(self, factor: f64) -> Differentials {
+		Differentials {
+			origin_dx: self.origin_dx * factor,
+			origin_dy: self.origin_dy * factor,
+			direction_dx: self.direction_dx * factor,
+			direction_dy: self.direction_dy * factor,
+		}
+	}
 }
 
 impl Ray {
@@ -19,9 +46,45 @@ impl Ray {
 		Ray {
 			origin,
 			direction: direction.normalize(),
+			time: 0.0,
+			differentials: None,
+		}
+	}
+
+	/** This ray, cast at the specified time instead */
+	pub fn at_time(self, time: f64) -> Ray {
+		Ray { time, ..self }
+	}
+
+	/** This ray, with the specified differentials */
+	pub fn with_differentials(self, differentials: Differentials) -> Ray {
+		Ray {
+			differentials: Some(differentials),
+			..self
 		}
 	}
 
+	pub fn time(&self) -> f64 {
+		self.time
+	}
+
+	/** Differentials of the ray, which are only known for camera rays */
+	pub fn differentials(&self) -> Option<Differentials> {
+		self.differentials
+	}
+
+	/** The rays next to this ray (one pixel to the right and one down), when the differentials are known */
+	pub fn neighbours(&self) -> Option<(Ray, Ray)> {
+		self.differentials.map(|d| {
+			(
+				Ray::new(self.origin + d.origin_dx, self.direction + d.direction_dx)
+					.at_time(self.time),
+				Ray::new(self.origin + d.origin_dy, self.direction + d.direction_dy)
+					.at_time(self.time),
+			)
+		})
+	}
+
 	pub fn origin(&self) -> Vector {
 		self.origin
 	}
@@ -36,22 +99,203 @@ impl Ray {
 	}
 }
 
+/** An axis-aligned bounding box. */
+#[derive(Clone, Copy)]
+pub struct Aabb {
+	pub min: Vector,
+	pub max: Vector,
+}
+
+impl Aabb {
+	/** The smallest box containing both this box and the other box. */
+	pub fn union(&self, other: &Aabb) -> Aabb {
+		Aabb {
+			min: Vector {
+				x: self.min.x.min(other.min.x),
+				y: self.min.y.min(other.min.y),
+				z: self.min.z.min(other.min.z),
+			},
+			max: Vector {
+				x: self.max.x.max(other.max.x),
+				y: self.max.y.max(other.max.y),
+				z: self.max.z.max(other.max.z),
+			},
+		}
+	}
+
+	pub fn center(&self) -> Vector {
+		(self.min + self.max) * 0.5
+	}
+
+	/** Whether the box has a finite size (unbounded objects such as planes have an infinite box). */
+	pub fn is_finite(&self) -> bool {
+		self.min.is_finite() && self.max.is_finite()
+	}
+
+	/** Whether the ray passes through this box at a positive distance. */
+	pub fn hit(&self, ray: &Ray) -> bool {
+		self.intersect(ray).is_some()
+	}
+
+	/** Distances at which the ray enters and exits this box (slab test); entry is negative when starting inside. */
+	pub fn intersect(&self, ray: &Ray) -> Option<(f64, f64)> {
+		let origin = ray.origin();
+		let direction = ray.direction();
+		let mut t_min = f64::NEG_INFINITY;
+		let mut t_max = f64::INFINITY;
+
+		for (o, d, min, max) in &[
+			(origin.x, direction.x, self.min.x, self.max.x),
+			(origin.y, direction.y, self.min.y, self.max.y),
+			(origin.z, direction.z, self.min.z, self.max.z),
+		] {
+			let inv = 1.0 / d;
+			let t0 = (min - o) * inv;
+			let t1 = (max - o) * inv;
+
+			// Note: f64::min and f64::max ignore the NaN produced when the ray lies on a slab boundary
+			t_min = t_min.max(t0.min(t1));
+			t_max = t_max.min(t0.max(t1));
+		}
+
+		if t_min <= t_max && t_max >= 0.0 {
+			Some((t_min, t_max))
+		} else {
+			None
+		}
+	}
+}
+
+/** A sphere enclosing an object, used to cheaply reject rays that cannot hit it. */
+#[derive(Clone, Copy)]
+pub struct BoundingSphere {
+	pub center: Vector,
+	pub radius: f64,
+}
+
+impl BoundingSphere {
+	/** The sphere through the corners of the box. Unbounded boxes yield an infinite sphere. */
+	pub fn from_aabb(aabb: &Aabb) -> BoundingSphere {
+		BoundingSphere {
+			center: aabb.center(),
+			radius: (aabb.max - aabb.min).norm() / 2.0,
+		}
+	}
+
+	/** Whether the ray may hit something inside this sphere, i.e. starts inside it or passes through it ahead. */
+	pub fn hit(&self, ray: &Ray) -> bool {
+		if !self.radius.is_finite() {
+			return true;
+		}
+
+		let l = self.center - ray.origin();
+		let l2 = l.norm_squared();
+		let r2 = self.radius * self.radius;
+		if l2 <= r2 {
+			return true;
+		}
+
+		let tca = l ^ ray.direction();
+		tca >= 0.0 && l2 - tca * tca <= r2
+	}
+}
+
 impl Vector {
+	pub fn new(x: f64, y: f64, z: f64) -> Vector {
+		Vector { x, y, z }
+	}
+
+	/** Unit vector at polar angle theta from the Z axis, and azimuth phi from the X axis towards the Y axis (in radians) */
+	pub fn from_spherical(theta: f64, phi: f64) -> Vector {
+		let (sin_theta, cos_theta) = theta.sin_cos();
+		let (sin_phi, cos_phi) = phi.sin_cos();
+		Vector {
+			x: sin_theta * cos_phi,
+			y: sin_theta * sin_phi,
+			z: cos_theta,
+		}
+	}
+
 	pub fn dot(&self, other: &Vector) -> f64 {
 		self.x * other.x + self.y * other.y + self.z * other.z
 	}
 
+	/** Component-wise (Hadamard) product, e.g. to tint light by the color of a surface */
+	pub fn mul_components(&self, other: &Vector) -> Vector {
+		Vector {
+			x: self.x * other.x,
+			y: self.y * other.y,
+			z: self.z * other.z,
+		}
+	}
+
+	/** Cross product, using the right-hand rule (so the cross product of the X and Y unit vectors is the Z unit vector) */
+	pub fn cross(&self, other: &Vector) -> Vector {
+		Vector {
+			x: self.y * other.z - self.z * other.y,
+			y: self.z * other.x - self.x * other.z,
+			z: self.x * other.y - self.y * other.x,
+		}
+	}
+
 	/** Norm (length) of the vector in 3D space */
 	pub fn norm(&self) -> f64 {
-		(self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+		self.norm_squared().sqrt()
+	}
+
+	/** Whether all components are finite (not infinite and not NaN) */
+	pub fn is_finite(&self) -> bool {
+		self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
 	}
 
+	/** Squared norm, which avoids a square root when only comparing lengths */
+	pub fn norm_squared(&self) -> f64 {
+		self.x * self.x + self.y * self.y + self.z * self.z
+	}
+
+	/** Distance between this point and the other point */
+	pub fn distance(&self, other: &Vector) -> f64 {
+		(*self - *other).norm()
+	}
+
+	/** Squared distance between this point and the other point */
+	pub fn distance_squared(&self, other: &Vector) -> f64 {
+		(*self - *other).norm_squared()
+	}
+
+	/** Unit vector in the direction of this vector, or the zero vector when it has no direction (see `try_normalize`) */
 	pub fn normalize(&self) -> Vector {
-		let norm = self.norm();
+		self.try_normalize().unwrap_or_default()
+	}
+
+	/** Unit vector in the direction of this vector, or None for zero vectors and non-finite components */
+	pub fn try_normalize(&self) -> Option<Vector> {
+		let norm_squared = self.norm_squared();
+		if norm_squared >= f64::MIN_POSITIVE && norm_squared.is_finite() {
+			return Some(*self / norm_squared.sqrt());
+		}
+
+		// The squared norm of very small (or large) vectors underflows (or overflows), so scale them first
+		let max = self.x.abs().max(self.y.abs()).max(self.z.abs());
+		if max > 0.0 && max.is_finite() {
+			let scaled = *self / max;
+			Some(scaled / scaled.norm())
+		} else {
+			None
+		}
+	}
+
+	/** Linear interpolation between this vector (at t = 0) and the other vector (at t = 1) */
+	pub fn lerp(self, other: Vector, t: f64) -> Vector {
+		self * (1.0 - t) + other * t
+	}
+
+	/** Clamp each component to the range [min, max] */
+	pub fn clamp(self, min: f64, max: f64) -> Vector {
 		Vector {
-			x: self.x / norm,
-			y: self.y / norm,
-			z: self.z / norm,
+			x: self.x.clamp(min, max),
+			y: self.y.clamp(min, max),
+			z: self.z.clamp(min, max),
 		}
 	}
 
@@ -59,32 +303,209 @@ impl Vector {
 		*self - (normal * 2.0 * (*self ^ normal))
 	}
 
-	pub fn refract(self, normal: Vector, refractive_index: f64) -> Vector {
-		let mut cosi = (self ^ normal).min(1.0).max(-1.0);
-		let mut etai = 1.0;
-		let mut etat = refractive_index;
+	/** Refract this direction when passing from a medium with index eta_incident into one with index eta_transmitted */
+	pub fn refract(self, normal: Vector, eta_incident: f64, eta_transmitted: f64) -> Vector {
+		// The normal may face either side of the surface; orient it against the incident direction
+		let mut cosi = -(self ^ normal).clamp(-1.0, 1.0);
 		let mut n = normal;
 		if cosi < 0.0 {
-			// if the ray is inside the object, swap the indices and invert the normal to get the correct result
 			cosi = -cosi;
-			std::mem::swap(&mut etat, &mut etai);
-			n = n * -1.0;
+			n = -n;
 		}
-		let eta = etai / etat;
+		let eta = eta_incident / eta_transmitted;
 		let k = 1.0 - eta * eta * (1.0 - cosi * cosi);
 
 		if k < 0.0 {
-			Vector {
-				x: 1.0,
-				y: 0.0,
-				z: 0.0,
-			}
+			// Total internal reflection
+			self.reflect(n)
 		} else {
 			(self * eta) + (n * (eta * cosi - k.sqrt()))
 		}
 	}
 }
 
+/** A 4x4 matrix representing an affine transformation of points and vectors in homogeneous coordinates. */
+#[derive(Clone, Copy)]
+pub struct Matrix4 {
+	pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+	pub fn identity() -> Matrix4 {
+		Matrix4 {
+			m: [
+				[1.0, 0.0, 0.0, 0.0],
+				[0.0, 1.0, 0.0, 0.0],
+				[0.0, 0.0, 1.0, 0.0],
+				[0.0, 0.0, 0.0, 1.0],
+			],
+		}
+	}
+
+	pub fn translation(offset: Vector) -> Matrix4 {
+		let mut t = Matrix4::identity();
+		t.m[0][3] = offset.x;
+		t.m[1][3] = offset.y;
+		t.m[2][3] = offset.z;
+		t
+	}
+
+	pub fn scaling(factors: Vector) -> Matrix4 {
+		let mut t = Matrix4::identity();
+		t.m[0][0] = factors.x;
+		t.m[1][1] = factors.y;
+		t.m[2][2] = factors.z;
+		t
+	}
+
+	/** Rotation around the X axis (angle in radians) */
+	pub fn rotation_x(angle: f64) -> Matrix4 {
+		let (sin, cos) = angle.sin_cos();
+		let mut t = Matrix4::identity();
+		t.m[1][1] = cos;
+		t.m[1][2] = -sin;
+		t.m[2][1] = sin;
+		t.m[2][2] = cos;
+		t
+	}
+
+	/** Rotation around the Y axis (angle in radians) */
+	pub fn rotation_y(angle: f64) -> Matrix4 {
+		let (sin, cos) = angle.sin_cos();
+		let mut t = Matrix4::identity();
+		t.m[0][0] = cos;
+		t.m[0][2] = sin;
+		t.m[2][0] = -sin;
+		t.m[2][2] = cos;
+		t
+	}
+
+	/** Rotation around the Z axis (angle in radians) */
+	pub fn rotation_z(angle: f64) -> Matrix4 {
+		let (sin, cos) = angle.sin_cos();
+		let mut t = Matrix4::identity();
+		t.m[0][0] = cos;
+		t.m[0][1] = -sin;
+		t.m[1][0] = sin;
+		t.m[1][1] = cos;
+		t
+	}
+
+	pub fn transpose(&self) -> Matrix4 {
+		let mut t = Matrix4::identity();
+		for (i, row) in t.m.iter_mut().enumerate() {
+			for (j, value) in row.iter_mut().enumerate() {
+				*value = self.m[j][i];
+			}
+		}
+		t
+	}
+
+	/** The inverse of this matrix (Gauss-Jordan elimination), or None when the matrix is singular. */
+	pub fn inverse(&self) -> Option<Matrix4> {
+		let mut a = self.m;
+		let mut inv = Matrix4::identity().m;
+
+		for column in 0..4 {
+			// Select the row with the largest pivot for numerical stability
+			let pivot = (column..4).fold(column, |best, row| {
+				if a[row][column].abs() > a[best][column].abs() {
+					row
+				} else {
+					best
+				}
+			});
+			if a[pivot][column].abs() < 1e-12 {
+				return None;
+			}
+			a.swap(column, pivot);
+			inv.swap(column, pivot);
+
+			let factor = a[column][column];
+			for j in 0..4 {
+				a[column][j] /= factor;
+				inv[column][j] /= factor;
+			}
+
+			for row in 0..4 {
+				if row != column {
+					let factor = a[row][column];
+					for j in 0..4 {
+						a[row][j] -= factor * a[column][j];
+						inv[row][j] -= factor * inv[column][j];
+					}
+				}
+			}
+		}
+
+		Some(Matrix4 { m: inv })
+	}
+
+	/** Transform a point (applies translation) */
+	pub fn transform_point(&self, p: Vector) -> Vector {
+		let m = &self.m;
+		Vector {
+			x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+			y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+			z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+		}
+	}
+
+	/** Transform a direction vector (ignores translation) */
+	pub fn transform_vector(&self, v: Vector) -> Vector {
+		let m = &self.m;
+		Vector {
+			x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+			y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+			z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+		}
+	}
+}
+
+/** Matrix multiplication: (a * b) applies b first, then a */
+impl Mul<Matrix4> for Matrix4 {
+	type Output = Matrix4;
+
+	fn mul(self, rhs: Matrix4) -> Matrix4 {
+		let mut t = Matrix4 { m: [[0.0; 4]; 4] };
+		for i in 0..4 {
+			for j in 0..4 {
+				t.m[i][j] = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+			}
+		}
+		t
+	}
+}
+
+/** Fraction of light reflected (rather than refracted) when a ray in the incident direction hits a surface with the
+given normal, passing from a medium with index eta_incident into one with index eta_transmitted. Uses Schlick's
+approximation. */
+pub fn fresnel(incident: Vector, normal: Vector, eta_incident: f64, eta_transmitted: f64) -> f64 {
+	// Light passes between media of the same index without reflection (which Schlick's approximation does not give)
+	if eta_incident == eta_transmitted {
+		return 0.0;
+	}
+
+	let cos_incident = (incident.normalize() ^ normal.normalize()).clamp(-1.0, 1.0);
+
+	let sin_transmitted_squared =
+		(eta_incident / eta_transmitted).powi(2) * (1.0 - cos_incident * cos_incident);
+	if sin_transmitted_squared > 1.0 {
+		// Total internal reflection
+		return 1.0;
+	}
+
+	// Use the cosine of the larger of the two angles
+	let cos = if eta_incident > eta_transmitted {
+		(1.0 - sin_transmitted_squared).sqrt()
+	} else {
+		cos_incident.abs()
+	};
+
+	let r0 = ((eta_incident - eta_transmitted) / (eta_incident + eta_transmitted)).powi(2);
+	r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+}
+
 impl Add for Vector {
 	type Output = Vector;
 
@@ -109,6 +530,41 @@ impl Sub for Vector {
 	}
 }
 
+impl AddAssign for Vector {
+	fn add_assign(&mut self, other: Vector) {
+		*self = *self + other;
+	}
+}
+
+impl SubAssign for Vector {
+	fn sub_assign(&mut self, other: Vector) {
+		*self = *self - other;
+	}
+}
+
+impl From<[f64; 3]> for Vector {
+	fn from([x, y, z]: [f64; 3]) -> Vector {
+		Vector { x, y, z }
+	}
+}
+
+/** Parse a vector from three comma-separated numbers (e.g. '0.5,0.6,0.7') */
+impl FromStr for Vector {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Vector, String> {
+		let components = s
+			.split(',')
+			.map(|c| c.trim().parse::<f64>())
+			.collect::<Result<Vec<f64>, _>>()
+			.map_err(|e| format!("invalid vector '{}': {}", s, e))?;
+		match components[..] {
+			[x, y, z] => Ok(Vector { x, y, z }),
+			_ => Err(format!("invalid vector '{}': expected three components", s)),
+		}
+	}
+}
+
 /** Vector scalar multiplication */
 impl Mul<f64> for Vector {
 	type Output = Vector;
@@ -122,6 +578,37 @@ impl Mul<f64> for Vector {
 	}
 }
 
+impl MulAssign<f64> for Vector {
+	fn mul_assign(&mut self, scalar: f64) {
+		*self = *self * scalar;
+	}
+}
+
+/** Vector scalar division */
+impl Div<f64> for Vector {
+	type Output = Vector;
+
+	fn div(self, scalar: f64) -> Vector {
+		Vector {
+			x: self.x / scalar,
+			y: self.y / scalar,
+			z: self.z / scalar,
+		}
+	}
+}
+
+impl Neg for Vector {
+	type Output = Vector;
+
+	fn neg(self) -> Vector {
+		Vector {
+			x: -self.x,
+			y: -self.y,
+			z: -self.z,
+		}
+	}
+}
+
 /** Vector dot product */
 impl BitXor<Vector> for Vector {
 	type Output = f64;
@@ -130,3 +617,159 @@ impl BitXor<Vector> for Vector {
 		self.dot(&rhs)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cross_product_of_unit_vectors() {
+		let x = Vector::new(1.0, 0.0, 0.0);
+		let y = Vector::new(0.0, 1.0, 0.0);
+		let z = Vector::new(0.0, 0.0, 1.0);
+		assert_eq!(x.cross(&y), z);
+		assert_eq!(y.cross(&z), x);
+		assert_eq!(z.cross(&x), y);
+		assert_eq!(y.cross(&x), -z);
+	}
+
+	/** Whether the vectors are equal up to rounding errors */
+	fn close(a: Vector, b: Vector) -> bool {
+		a.distance(&b) < 1e-9
+	}
+
+	#[test]
+	fn total_internal_reflection_reflects() {
+		// Leaving glass for air at 60 degrees from the normal, beyond the critical angle of about 42 degrees
+		let angle = 60.0_f64.to_radians();
+		let direction = Vector::new(angle.sin(), angle.cos(), 0.0);
+		let normal = Vector::new(0.0, 1.0, 0.0);
+		let refracted = direction.refract(normal, 1.5, 1.0);
+		assert!(close(
+			refracted,
+			Vector::new(angle.sin(), -angle.cos(), 0.0)
+		));
+
+		// Below the critical angle the ray is refracted away from the normal, into the air
+		let angle = 20.0_f64.to_radians();
+		let direction = Vector::new(angle.sin(), angle.cos(), 0.0);
+		let refracted = direction.refract(normal, 1.5, 1.0);
+		assert!(refracted.y > 0.0 && refracted.x > direction.x);
+		assert!((refracted.norm() - 1.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn division_and_negation() {
+		let v = Vector::new(2.0, -4.0, 6.0);
+		assert_eq!(v / 2.0, Vector::new(1.0, -2.0, 3.0));
+		assert_eq!(v / -2.0, Vector::new(-1.0, 2.0, -3.0));
+		assert_eq!(-v, Vector::new(-2.0, 4.0, -6.0));
+		assert_eq!(-(-v), v);
+	}
+
+	#[test]
+	fn fresnel_reflectance_approaches_one_at_grazing_angles() {
+		let normal = Vector::new(0.0, 1.0, 0.0);
+		let incident = |degrees: f64| {
+			let angle = f64::to_radians(degrees);
+			Vector::new(angle.sin(), -angle.cos(), 0.0)
+		};
+
+		// Air into glass: 4% of the light is reflected at normal incidence
+		assert!((fresnel(incident(0.0), normal, 1.0, 1.5) - 0.04).abs() < 1e-9);
+		assert!(
+			fresnel(incident(60.0), normal, 1.0, 1.5) < fresnel(incident(80.0), normal, 1.0, 1.5)
+		);
+		assert!(fresnel(incident(89.9), normal, 1.0, 1.5) > 0.99);
+	}
+
+	#[test]
+	fn assign_operators_and_constructors() {
+		let mut v = Vector::new(1.0, 2.0, 3.0);
+		assert_eq!(
+			v,
+			Vector {
+				x: 1.0,
+				y: 2.0,
+				z: 3.0
+			}
+		);
+		assert_eq!(Vector::from([1.0, 2.0, 3.0]), v);
+
+		v += Vector::new(1.0, 1.0, 1.0);
+		assert_eq!(v, Vector::new(2.0, 3.0, 4.0));
+		v -= Vector::new(0.5, 1.0, 1.5);
+		assert_eq!(v, Vector::new(1.5, 2.0, 2.5));
+		v *= 2.0;
+		assert_eq!(v, Vector::new(3.0, 4.0, 5.0));
+	}
+
+	#[test]
+	fn fresnel_reflectance_vanishes_between_equal_media() {
+		let incident = Vector::new(0.8, -0.6, 0.0);
+		assert_eq!(fresnel(incident, Vector::new(0.0, 1.0, 0.0), 1.5, 1.5), 0.0);
+	}
+
+	#[test]
+	fn norms_and_distances() {
+		let a = Vector::new(1.0, 2.0, 2.0);
+		assert_eq!(a.norm_squared(), 9.0);
+		assert_eq!(a.norm(), 3.0);
+
+		let b = Vector::new(4.0, 6.0, 2.0);
+		assert_eq!(a.distance_squared(&b), 25.0);
+		assert_eq!(a.distance(&b), 5.0);
+		assert_eq!(b.distance(&a), 5.0);
+		assert_eq!(a.distance(&a), 0.0);
+	}
+
+	#[test]
+	fn lerp_and_clamp() {
+		let a = Vector::new(0.0, 2.0, -4.0);
+		let b = Vector::new(1.0, 4.0, 4.0);
+		assert_eq!(a.lerp(b, 0.0), a);
+		assert_eq!(a.lerp(b, 1.0), b);
+		assert_eq!(a.lerp(b, 0.25), Vector::new(0.25, 2.5, -2.0));
+
+		assert_eq!(
+			Vector::new(-0.5, 0.5, 1.5).clamp(0.0, 1.0),
+			Vector::new(0.0, 0.5, 1.0)
+		);
+	}
+
+	#[test]
+	fn normalize_degenerate_vectors() {
+		assert_eq!(Vector::default().normalize(), Vector::default());
+		assert!(Vector::default().try_normalize().is_none());
+		assert!(Vector::new(f64::NAN, 0.0, 0.0).try_normalize().is_none());
+
+		// The squared norm of these vectors underflows or overflows, but they still have a direction
+		let tiny = Vector::new(3e-200, 0.0, 4e-200).normalize();
+		assert!(tiny.distance(&Vector::new(0.6, 0.0, 0.8)) < 1e-12);
+		let subnormal = Vector::new(0.0, -5e-324, 0.0).normalize();
+		assert_eq!(subnormal, Vector::new(0.0, -1.0, 0.0));
+		let huge = Vector::new(3e200, 4e200, 0.0).normalize();
+		assert!(huge.distance(&Vector::new(0.6, 0.8, 0.0)) < 1e-12);
+	}
+
+	#[test]
+	fn component_wise_product() {
+		let a = Vector::new(1.0, 2.0, 3.0);
+		let b = Vector::new(4.0, 5.0, 6.0);
+		assert_eq!(a.mul_components(&b), Vector::new(4.0, 10.0, 18.0));
+		assert_eq!(b.mul_components(&a), a.mul_components(&b));
+	}
+
+	#[test]
+	fn spherical_coordinates_round_trip() {
+		for &theta in &[0.1, 0.5, 1.0, 1.5, 2.0, 3.0] {
+			for &phi in &[-3.0, -1.0, 0.0, 0.5, 2.0, 3.1] {
+				let direction = Vector::from_spherical(theta, phi);
+				assert!((direction.norm() - 1.0).abs() < 1e-12);
+				assert!((direction.z.acos() - theta).abs() < 1e-12);
+				assert!((direction.y.atan2(direction.x) - phi).abs() < 1e-12);
+			}
+		}
+		assert_eq!(Vector::from_spherical(0.0, 1.0), Vector::new(0.0, 0.0, 1.0));
+	}
+}
@@ -0,0 +1,379 @@
+use super::geometry::{Aabb, Ray, Vector};
+use super::scene::{Hit, Traceable};
+use std::sync::Arc;
+
+/** A uniform grid of cells over the box containing a set of objects, as a simpler alternative to a bounding volume
+hierarchy. Each cell lists the objects whose bounding box overlaps it; rays walk through the cells they pass in order,
+and stop at the first cell containing a hit. */
+pub struct Grid {
+	objects: Vec<Arc<dyn Traceable>>,
+
+	/** Box covered by the cells, or None when there are no objects of finite size */
+	bounds: Option<Aabb>,
+
+	/** Number of cells along each axis */
+	resolution: usize,
+	cell_size: Vector,
+
+	/** Indices of the objects overlapping each cell, with the X index varying fastest */
+	cells: Vec<Vec<usize>>,
+
+	/** Indices of objects of infinite size (such as planes), which are tested for every ray */
+	unbounded: Vec<usize>,
+}
+
+/** Largest number of cells along each axis; the cells of a grid take memory for the cube of the resolution */
+pub const MAX_RESOLUTION: usize = 128;
+
+/** Number of objects remembered as tested by a ray, so that objects overlapping several cells are tested only once */
+const MAILBOX_SIZE: usize = 16;
+
+fn components(v: Vector) -> [f64; 3] {
+	[v.x, v.y, v.z]
+}
+
+/** Indices of the objects recently tested against a ray. Each index can only be stored in one slot, so an index may be
+pushed out by another, after which its object is tested again (which gives the same result). */
+struct Mailbox([usize; MAILBOX_SIZE]);
+
+impl Mailbox {
+	fn new() -> Mailbox {
+		Mailbox([usize::MAX; MAILBOX_SIZE])
+	}
+
+	/** Remember the object as tested, returning whether it already was */
+	fn tested(&mut self, index: usize) -> bool {
+		let slot = &mut self.0[index % MAILBOX_SIZE];
+		let tested = *slot == index;
+		*slot = index;
+		tested
+	}
+}
+
+impl Grid {
+	/** Build a grid with the given number of cells along each axis, which must be between 1 and `MAX_RESOLUTION`. */
+	pub fn new(objects: Vec<Arc<dyn Traceable>>, resolution: usize) -> Result<Grid, String> {
+		if !(1..=MAX_RESOLUTION).contains(&resolution) {
+			return Err(format!(
+				"grid resolution {} is not between 1 and {}",
+				resolution, MAX_RESOLUTION
+			));
+		}
+
+		let aabbs: Vec<Aabb> = objects.iter().map(|o| o.aabb()).collect();
+		let unbounded = (0..objects.len())
+			.filter(|&i| !aabbs[i].is_finite())
+			.collect();
+
+		let bounds = aabbs
+			.iter()
+			.filter(|b| b.is_finite())
+			.fold(None, |bounds: Option<Aabb>, b| {
+				Some(bounds.map_or(*b, |bounds| bounds.union(b)))
+			})
+			.map(|b| {
+				// Pad the box, so that flat scenes still have cells of non-zero size
+				let extent = b.max - b.min;
+				let padding = 1e-6 * extent.x.max(extent.y).max(extent.z).max(1.0);
+				let padding = Vector::new(padding, padding, padding);
+				Aabb {
+					min: b.min - padding,
+					max: b.max + padding,
+				}
+			});
+
+		let cell_size = match &bounds {
+			Some(b) => (b.max - b.min) / resolution as f64,
+			None => Vector::default(),
+		};
+
+		let mut grid = Grid {
+			objects,
+			bounds,
+			resolution,
+			cell_size,
+			cells: vec![Vec::new(); resolution * resolution * resolution],
+			unbounded,
+		};
+
+		for (index, aabb) in aabbs.iter().enumerate() {
+			if !aabb.is_finite() {
+				continue;
+			}
+			let (min, max) = (grid.cell_of(aabb.min), grid.cell_of(aabb.max));
+			for z in min[2]..=max[2] {
+				for y in min[1]..=max[1] {
+					for x in min[0]..=max[0] {
+						let cell = grid.cell_index([x, y, z]);
+						grid.cells[cell].push(index);
+					}
+				}
+			}
+		}
+		Ok(grid)
+	}
+
+	/** Number of cells along each axis. */
+	pub fn resolution(&self) -> usize {
+		self.resolution
+	}
+
+	/** All objects contained in this grid. */
+	pub fn objects(&self) -> &[Arc<dyn Traceable>] {
+		&self.objects
+	}
+
+	/** Find the nearest hit of the ray with any of the visible objects, up to the maximum distance. */
+	pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Hit> {
+		self.intersect_filtered(ray, false, max_distance)
+	}
+
+	/** Find the nearest hit of a shadow ray with any of the objects that cast shadows, up to the maximum distance. */
+	pub fn intersect_shadow(&self, ray: &Ray, max_distance: f64) -> Option<Hit> {
+		self.intersect_filtered(ray, true, max_distance)
+	}
+
+	/** Coordinates of the cell containing the point, clamped to the grid */
+	fn cell_of(&self, point: Vector) -> [usize; 3] {
+		let min = components(self.bounds.as_ref().map_or(Vector::default(), |b| b.min));
+		let (point, size) = (components(point), components(self.cell_size));
+		let mut cell = [0; 3];
+		for axis in 0..3 {
+			let index = ((point[axis] - min[axis]) / size[axis]).floor();
+			cell[axis] = index.clamp(0.0, (self.resolution - 1) as f64) as usize;
+		}
+		cell
+	}
+
+	fn cell_index(&self, [x, y, z]: [usize; 3]) -> usize {
+		(z * self.resolution + y) * self.resolution + x
+	}
+
+	/** Test the ray against the objects, replacing the nearest hit when a nearer one is found */
+	fn test<I: Iterator<Item = usize>>(
+		&self,
+		ray: &Ray,
+		shadow: bool,
+		max_distance: f64,
+		indices: I,
+		mailbox: &mut Mailbox,
+		nearest: &mut Option<Hit>,
+	) {
+		for index in indices {
+			if mailbox.tested(index) {
+				continue;
+			}
+			let object = &self.objects[index];
+			let included = if shadow {
+				object.casts_shadows()
+			} else {
+				object.visible()
+			};
+			if !included {
+				continue;
+			}
+
+			if let Some(hit) = object.intersect(ray) {
+				if hit.distance <= max_distance
+					&& nearest.as_ref().is_none_or(|n| hit.distance < n.distance)
+				{
+					*nearest = Some(hit);
+				}
+			}
+		}
+	}
+
+	fn intersect_filtered(&self, ray: &Ray, shadow: bool, max_distance: f64) -> Option<Hit> {
+		let mut nearest: Option<Hit> = None;
+		let mut mailbox = Mailbox::new();
+		self.test(
+			ray,
+			shadow,
+			max_distance,
+			self.unbounded.iter().copied(),
+			&mut mailbox,
+			&mut nearest,
+		);
+
+		let bounds = match &self.bounds {
+			Some(bounds) => bounds,
+			None => return nearest,
+		};
+		let (entry, exit) = match bounds.intersect(ray) {
+			Some(distances) => distances,
+			None => return nearest,
+		};
+
+		// Walk through the cells along the ray (3D DDA), starting where it enters the grid
+		let entry = entry.max(0.0);
+		let mut cell = self.cell_of(ray.extend(entry));
+		let origin = components(ray.origin());
+		let direction = components(ray.direction());
+		let min = components(bounds.min);
+		let size = components(self.cell_size);
+
+		let mut step = [0i64; 3];
+		let mut next_crossing = [f64::INFINITY; 3];
+		let mut crossing_interval = [f64::INFINITY; 3];
+		for axis in 0..3 {
+			if direction[axis] > 0.0 {
+				step[axis] = 1;
+				let boundary = min[axis] + (cell[axis] + 1) as f64 * size[axis];
+				next_crossing[axis] = (boundary - origin[axis]) / direction[axis];
+				crossing_interval[axis] = size[axis] / direction[axis];
+			} else if direction[axis] < 0.0 {
+				step[axis] = -1;
+				let boundary = min[axis] + cell[axis] as f64 * size[axis];
+				next_crossing[axis] = (boundary - origin[axis]) / direction[axis];
+				crossing_interval[axis] = -size[axis] / direction[axis];
+			}
+		}
+
+		loop {
+			// A hit in this cell is only certainly the nearest when it lies before the ray leaves the cell, as
+			// objects may span several cells
+			let cell_exit = next_crossing[0].min(next_crossing[1]).min(next_crossing[2]);
+			let objects = &self.cells[self.cell_index(cell)];
+			self.test(
+				ray,
+				shadow,
+				max_distance,
+				objects.iter().copied(),
+				&mut mailbox,
+				&mut nearest,
+			);
+			if nearest.as_ref().is_some_and(|n| n.distance <= cell_exit)
+				|| cell_exit > exit
+				|| cell_exit > max_distance
+			{
+				return nearest;
+			}
+
+			let axis =
+				if next_crossing[0] <= next_crossing[1] && next_crossing[0] <= next_crossing[2] {
+					0
+				} else if next_crossing[1] <= next_crossing[2] {
+					1
+				} else {
+					2
+				};
+			let next = cell[axis] as i64 + step[axis];
+			if next < 0 || next >= self.resolution as i64 {
+				return nearest;
+			}
+			cell[axis] = next as usize;
+			next_crossing[axis] += crossing_interval[axis];
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::BoundingSphere;
+	use crate::primitives::{Plane, Sphere};
+	use crate::scene::tests::diffuse;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/** An object counting how often it is tested against a ray */
+	struct Counting {
+		object: Sphere,
+		tests: AtomicUsize,
+	}
+
+	impl Traceable for Counting {
+		fn intersect(&self, ray: &Ray) -> Option<Hit> {
+			self.tests.fetch_add(1, Ordering::Relaxed);
+			self.object.intersect(ray)
+		}
+
+		fn aabb(&self) -> Aabb {
+			self.object.aabb()
+		}
+
+		fn bounding_sphere(&self) -> BoundingSphere {
+			self.object.bounding_sphere()
+		}
+	}
+
+	fn sphere(center: Vector, radius: f64) -> Sphere {
+		Sphere {
+			center,
+			radius,
+			material: Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0))),
+		}
+	}
+
+	#[test]
+	fn nearest_hits_match_linear_scan() {
+		// Spheres of different sizes on a grid in the XY plane at z = -10, above a plane (which has an infinite box)
+		let mut objects: Vec<Arc<dyn Traceable>> = (0..500)
+			.map(|i| -> Arc<dyn Traceable> {
+				let center = Vector::new((i % 25) as f64, (i / 25) as f64, -10.0 - (i % 3) as f64);
+				Arc::new(sphere(center, 0.2 + 0.3 * (i % 4) as f64))
+			})
+			.collect();
+		objects.push(Arc::new(Plane {
+			point: Vector::new(0.0, -5.0, 0.0),
+			normal: Vector::new(0.0, 1.0, 0.0),
+			double_sided: false,
+			material: Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0))),
+		}));
+		let grid = Grid::new(objects.clone(), 16).unwrap();
+
+		let directions = [
+			Vector::new(0.0, 0.0, -1.0),
+			Vector::new(0.1, -1.0, -0.3).normalize(),
+			Vector::new(-0.4, 0.2, -1.0).normalize(),
+		];
+		for i in 0..40 {
+			// Offset from the sphere centers, so that no ray is (nearly) tangent to a sphere
+			let origin = Vector::new(i as f64 * 0.65 + 0.05, i as f64 * 0.5 + 0.05, 5.0);
+			for direction in directions {
+				let ray = Ray::new(origin, direction);
+				let expected = objects
+					.iter()
+					.filter_map(|o| o.intersect(&ray))
+					.map(|hit| hit.distance)
+					.min_by(f64::total_cmp);
+				let found = grid.intersect(&ray, f64::INFINITY).map(|hit| hit.distance);
+				assert_eq!(found, expected);
+			}
+		}
+	}
+
+	#[test]
+	fn resolution_is_limited() {
+		let objects =
+			|| -> Vec<Arc<dyn Traceable>> { vec![Arc::new(sphere(Vector::default(), 1.0))] };
+		assert!(Grid::new(objects(), 0).is_err());
+		assert!(Grid::new(objects(), MAX_RESOLUTION + 1).is_err());
+		assert!(Grid::new(objects(), usize::MAX).is_err());
+		assert_eq!(Grid::new(objects(), 8).unwrap().resolution(), 8);
+	}
+
+	#[test]
+	fn objects_in_several_cells_are_tested_once() {
+		let large = Arc::new(Counting {
+			object: sphere(Vector::default(), 10.0),
+			tests: AtomicUsize::new(0),
+		});
+		let objects: Vec<Arc<dyn Traceable>> = vec![
+			large.clone(),
+			Arc::new(sphere(Vector::new(15.0, -9.5, -9.5), 1.0)),
+		];
+		let grid = Grid::new(objects, 8).unwrap();
+
+		// Passes through the corners of the box around the large sphere, in cells it overlaps, without hitting it
+		let ray = Ray::new(Vector::new(-20.0, 9.5, 9.5), Vector::new(1.0, 0.0, 0.0));
+		let hit = grid.intersect(&ray, f64::INFINITY);
+		assert!(hit.is_none());
+		assert_eq!(large.tests.load(Ordering::Relaxed), 1);
+
+		// Passes the opposite corners to hit the small sphere
+		let ray = Ray::new(Vector::new(-20.0, -9.5, -9.5), Vector::new(1.0, 0.0, 0.0));
+		let hit = grid.intersect(&ray, f64::INFINITY).expect("ray should hit");
+		assert!((hit.distance - 34.0).abs() < 1e-9);
+		assert_eq!(large.tests.load(Ordering::Relaxed), 2);
+	}
+}
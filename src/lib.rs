@@ -0,0 +1,651 @@
+pub mod bvh;
+pub mod camera;
+pub mod description;
+pub mod geometry;
+pub mod grid;
+pub mod mesh;
+pub mod output;
+pub mod primitives;
+pub mod sampling;
+pub mod scene;
+pub mod tonemap;
+#[cfg(feature = "window")]
+pub mod window;
+
+use camera::Camera;
+use geometry::{Ray, Vector};
+use output::{pixel_index, RenderBuffer};
+use rayon::prelude::*;
+use sampling::SamplePattern;
+use scene::{Aov, DebugMode, Scene};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/** Width and height (in pixels) of the tiles the image is divided in for rendering */
+const TILE_SIZE: u32 = 32;
+
+/** Parameters controlling how an image is rendered. */
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+	/** Size of the full image in pixels */
+	pub width: u32,
+	pub height: u32,
+
+	/** Pixels to render, from (x0, y0) up to but not including (x1, y1) */
+	pub region: (u32, u32, u32, u32),
+
+	/** Number of samples per pixel along each axis */
+	pub samples: u32,
+
+	/** Placement of the samples within pixels (not used by adaptive sampling, which samples at random points) */
+	pub sample_pattern: SamplePattern,
+	pub max_depth: i32,
+
+	/** Seed for the random numbers used in sampling (see `sampling::seed_pixel`) */
+	pub seed: u64,
+
+	/** Visualize surface properties instead of rendering shaded colors */
+	pub debug: Option<DebugMode>,
+
+	/** Vary the number of samples per pixel with the noise in the pixel (instead of the fixed grid of samples) */
+	pub adaptive: Option<AdaptiveSampling>,
+
+	/** Report the number of tiles rendered so far on standard error */
+	pub show_progress: bool,
+}
+
+impl RenderSettings {
+	/** Settings for rendering the full image with one sample per pixel and the given maximum ray depth */
+	pub fn new(width: u32, height: u32, max_depth: i32) -> RenderSettings {
+		RenderSettings {
+			width,
+			height,
+			region: (0, 0, width, height),
+			samples: 1,
+			sample_pattern: SamplePattern::Grid,
+			max_depth,
+			seed: 0,
+			debug: None,
+			adaptive: None,
+			show_progress: false,
+		}
+	}
+}
+
+/** Parameters for adaptive sampling: pixels are sampled at random points until their color is estimated precisely. */
+#[derive(Clone, Copy)]
+pub struct AdaptiveSampling {
+	/** Number of samples cast for every pixel, and cast again each time more samples are needed */
+	pub min_samples: u32,
+
+	/** Maximum number of samples for a single pixel */
+	pub max_samples: u32,
+
+	/** Sampling stops when the variance of the estimated pixel color (summed over the channels) is below this */
+	pub variance: f64,
+}
+
+impl AdaptiveSampling {
+	/** Estimate pixel (x, y) from random rays through it, returning the color, coverage and number of samples */
+	fn sample<F: Fn(&Ray) -> (Vector, bool)>(
+		&self,
+		camera: &Camera,
+		(x, y): (u32, u32),
+		(width, height): (u32, u32),
+		trace: F,
+	) -> (Vector, f64, u32) {
+		let mut sum = Vector::default();
+		let mut sum_squares = Vector::default();
+		let mut hits = 0;
+		let mut count = 0;
+
+		while count < self.max_samples {
+			for _ in 0..self.min_samples.min(self.max_samples - count) {
+				let (color, hit) = trace(&camera.jittered_ray_for_pixel(x, y, width, height));
+				sum += color;
+				sum_squares += color.mul_components(&color);
+				hits += u32::from(hit);
+				count += 1;
+			}
+
+			// Variance of the mean of the samples, estimated from the variance among the samples
+			if count > 1 {
+				let n = f64::from(count);
+				let variance = sum_squares - sum.mul_components(&sum) / n;
+				if (variance.x + variance.y + variance.z) / (n * (n - 1.0)) <= self.variance {
+					break;
+				}
+			}
+		}
+		let n = f64::from(count);
+		(sum / n, f64::from(hits) / n, count)
+	}
+}
+
+/** Color seen along a camera ray, and whether it hit an object (rather than escaping to the background) */
+fn trace_camera_ray(scene: &Scene, ray: &Ray, settings: &RenderSettings) -> (Vector, bool) {
+	match settings.debug {
+		Some(mode) => {
+			let color = scene.debug_ray(ray, mode);
+			(color.unwrap_or_default(), color.is_some())
+		}
+		None => {
+			let components = scene.cast_ray_components(ray, settings.max_depth);
+			(scene.apply_fog(&components), components.is_hit())
+		}
+	}
+}
+
+/** Render pixel (x, y) of the image, returning its color, the fraction of its samples that hit an object (its coverage)
+and the number of samples taken */
+fn render_pixel(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	(x, y): (u32, u32),
+) -> (Vector, f64, u32) {
+	let (width, height) = (settings.width, settings.height);
+	let samples = settings.samples;
+	sampling::seed_pixel(
+		settings.seed,
+		u64::from(y) * u64::from(width) + u64::from(x),
+	);
+	let trace = |ray: &Ray| trace_camera_ray(scene, ray, settings);
+
+	let (color, coverage, sample_count) = match &settings.adaptive {
+		Some(adaptive) => adaptive.sample(camera, (x, y), (width, height), trace),
+		None => {
+			let mut color = Vector::default();
+			let mut hits = 0;
+			for ray in camera.rays_for_pixel(x, y, width, height, samples, settings.sample_pattern)
+			{
+				let (sample, hit) = trace(&ray);
+				color += sample;
+				hits += u32::from(hit);
+			}
+			let count = samples * samples;
+			(
+				color / f64::from(count),
+				f64::from(hits) / f64::from(count),
+				count,
+			)
+		}
+	};
+	if let (Some(statistics), None) = (&scene.statistics, settings.debug) {
+		statistics.count_pixel(sample_count);
+	}
+	(color, coverage, sample_count)
+}
+
+/** Top left corners of the tiles covering the region, row by row */
+fn tiles((x0, y0, x1, y1): (u32, u32, u32, u32)) -> Vec<(u32, u32)> {
+	(y0..y1)
+		.step_by(TILE_SIZE as usize)
+		.flat_map(|tile_y| {
+			(x0..x1)
+				.step_by(TILE_SIZE as usize)
+				.map(move |tile_x| (tile_x, tile_y))
+		})
+		.collect()
+}
+
+/** Render the scene as seen by the camera, returning linear colors row by row, starting at the top left. Pixels
+outside the region to render are black. */
+pub fn render(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Vec<Vector> {
+	render_cancellable(scene, camera, settings, &AtomicBool::new(false)).pixels
+}
+
+/** Render the scene like `render`, stopping early when the cancel flag is set (e.g. from another thread, which shares it
+through an `Arc`). Pixels that were not rendered before stopping are black, and have no samples in the buffer. */
+pub fn render_cancellable(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	cancel: &AtomicBool,
+) -> RenderBuffer {
+	let buffer = Mutex::new(RenderBuffer::new(settings.width, settings.height));
+	render_remaining(scene, camera, settings, &buffer, cancel, |_| {});
+	buffer.into_inner().unwrap()
+}
+
+/** Render the scene like `render_cancellable`, together with images of the given components of the colors (see `Aov`),
+which are returned in the same order. The components are rendered with the same samples as the image. Pixels are
+sampled on a grid, as the adaptive sampling and debug settings are ignored. Like `render_cancellable`, rendering stops
+when the cancel flag is set, leaving the remaining pixels black. */
+pub fn render_aovs(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	aovs: &[Aov],
+	cancel: &AtomicBool,
+) -> (RenderBuffer, Vec<Vec<Vector>>) {
+	let (width, height) = (settings.width, settings.height);
+	let (_, _, x1, y1) = settings.region;
+	let (samples, sample_pattern) = (settings.samples, settings.sample_pattern);
+
+	let rendered: Vec<Vec<_>> = tiles(settings.region)
+		.par_iter()
+		.map(|&(tile_x, tile_y)| {
+			let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+			for y in tile_y..(tile_y + TILE_SIZE).min(y1) {
+				if cancel.load(Ordering::Relaxed) {
+					break;
+				}
+				for x in tile_x..(tile_x + TILE_SIZE).min(x1) {
+					sampling::seed_pixel(
+						settings.seed,
+						u64::from(y) * u64::from(width) + u64::from(x),
+					);
+					let mut color = Vector::default();
+					let mut hits = 0;
+					let mut values = vec![Vector::default(); aovs.len()];
+					for ray in camera.rays_for_pixel(x, y, width, height, samples, sample_pattern) {
+						let components = scene.cast_ray_components(&ray, settings.max_depth);
+						color += scene.apply_fog(&components);
+						hits += u32::from(components.is_hit());
+						for (value, aov) in values.iter_mut().zip(aovs) {
+							*value += aov.value(&components);
+						}
+					}
+					if let Some(statistics) = &scene.statistics {
+						statistics.count_pixel(samples * samples);
+					}
+
+					let count = f64::from(samples * samples);
+					let values: Vec<Vector> =
+						values.into_iter().map(|value| value / count).collect();
+					let pixel = (
+						x,
+						y,
+						color / count,
+						f64::from(hits) / count,
+						samples * samples,
+					);
+					pixels.push((pixel, values));
+				}
+			}
+			pixels
+		})
+		.collect();
+
+	let mut buffer = RenderBuffer::new(width, height);
+	let mut images = vec![vec![Vector::default(); buffer.pixels.len()]; aovs.len()];
+	for (pixel, values) in rendered.into_iter().flatten() {
+		let index = pixel_index(width, pixel.0, pixel.1);
+		for (aov_image, value) in images.iter_mut().zip(values) {
+			aov_image[index] = value;
+		}
+		buffer.place(Some(pixel));
+	}
+	(buffer, images)
+}
+
+/** A backend rendering images of scenes, so that rendering may be done by other means than the CPU. */
+pub trait Renderer: Sync {
+	/** Render the scene as seen by the camera, returning linear colors row by row (as `render` does) */
+	fn render(&self, scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Vec<Vector>;
+
+	/** Render like `render_cancellable` if the backend can stop early, otherwise render the full image */
+	fn render_cancellable(
+		&self,
+		scene: &Scene,
+		camera: &Camera,
+		settings: &RenderSettings,
+		_cancel: &AtomicBool,
+	) -> Vec<Vector> {
+		self.render(scene, camera, settings)
+	}
+
+	/** Whether the backend renders parts of images (passes, tiles, rows and AOVs) with the functions of this crate */
+	fn renders_parts(&self) -> bool {
+		false
+	}
+}
+
+/** Renders on the CPU, dividing the image in tiles that are rendered in parallel on the current rayon thread pool. */
+#[derive(Clone, Copy, Default)]
+pub struct CpuRenderer;
+
+impl Renderer for CpuRenderer {
+	fn render(&self, scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Vec<Vector> {
+		render(scene, camera, settings)
+	}
+
+	fn render_cancellable(
+		&self,
+		scene: &Scene,
+		camera: &Camera,
+		settings: &RenderSettings,
+		cancel: &AtomicBool,
+	) -> Vec<Vector> {
+		render_cancellable(scene, camera, settings, cancel).pixels
+	}
+
+	fn renders_parts(&self) -> bool {
+		true
+	}
+}
+
+/** Render the tiles of the region that have not been rendered yet into the buffer (which must have the size of the
+image), e.g. to resume rendering from a checkpoint. Tiles in which every pixel has been sampled are skipped. After
+each tile is written to the buffer, the callback is called with the buffer (e.g. to save a checkpoint). Rendering stops
+when the cancel flag is set, after finishing the rows of pixels that are being rendered. */
+pub fn render_remaining<F: Fn(&RenderBuffer) + Sync>(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	buffer: &Mutex<RenderBuffer>,
+	cancel: &AtomicBool,
+	on_tile: F,
+) {
+	let RenderSettings {
+		width,
+		height,
+		region,
+		show_progress,
+		..
+	} = *settings;
+
+	// Divide the image into tiles, which are rendered in parallel
+	let (_, _, x1, y1) = region;
+	let tiles: Vec<(u32, u32)> = {
+		let buffer = buffer.lock().unwrap();
+		assert_eq!((buffer.width, buffer.height), (width, height));
+		tiles(region)
+			.into_iter()
+			.filter(|&(tile_x, tile_y)| {
+				(tile_y..(tile_y + TILE_SIZE).min(y1)).any(|y| {
+					(tile_x..(tile_x + TILE_SIZE).min(x1))
+						.any(|x| buffer.samples[pixel_index(width, x, y)] == 0)
+				})
+			})
+			.collect()
+	};
+	let tile_count = tiles.len();
+
+	// Number of tiles rendered so far, used to report progress
+	let tiles_done = AtomicUsize::new(0);
+
+	tiles.par_iter().for_each(|&(tile_x, tile_y)| {
+		// Tiles after cancelling are not started (nor counted as progress)
+		if cancel.load(Ordering::Relaxed) {
+			return;
+		}
+		let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+
+		// Render each pixel in this tile. When cancelled halfway, the rows rendered so far are still written to the
+		// buffer; the pixels in the other rows keep zero samples, so that the tile is rendered again when resuming.
+		for y in tile_y..(tile_y + TILE_SIZE).min(y1) {
+			if cancel.load(Ordering::Relaxed) {
+				break;
+			}
+			for x in tile_x..(tile_x + TILE_SIZE).min(x1) {
+				let (color, coverage, sample_count) = render_pixel(scene, camera, settings, (x, y));
+				pixels.push((x, y, color, coverage, sample_count));
+			}
+		}
+
+		// Pixels are placed by the coordinates stored with them, so the image does not depend on the order in which
+		// tiles are completed
+		{
+			let mut buffer = buffer.lock().unwrap();
+			buffer.place(pixels);
+			on_tile(&buffer);
+		}
+
+		// Report progress whenever another percent of the tiles has been completed
+		let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+		if show_progress && done * 100 / tile_count != (done - 1) * 100 / tile_count {
+			eprint!(
+				"\rRendered {}/{} tiles ({}%)",
+				done,
+				tile_count,
+				done * 100 / tile_count
+			);
+		}
+	});
+	if show_progress {
+		eprintln!();
+	}
+}
+
+/** Render the scene like `render`, but instead of returning the image, hand its rows (from the top) one by one to the
+writer function, e.g. to write them to a file while rendering. The image is rendered in bands of rows as high as a
+tile, which are written by another thread while the next band is rendered; as only a few bands are kept in memory,
+this can render images that would not fit in memory as a whole. When the cancel flag is set, the rows that are left
+are written without rendering them (black, like pixels outside the region). */
+pub fn render_rows<F: FnMut(&[Vector]) + Send>(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	cancel: &AtomicBool,
+	mut write_row: F,
+) {
+	let (width, height) = (settings.width, settings.height);
+	let (x0, y0, x1, y1) = settings.region;
+	let band_count = height.div_ceil(TILE_SIZE);
+
+	// The channel holds one band, so that rendering waits for the writer when it falls behind by more than that
+	let (sender, receiver) = mpsc::sync_channel::<Vec<Vec<Vector>>>(1);
+	thread::scope(|scope| {
+		scope.spawn(move || {
+			for band in receiver {
+				for row in band {
+					write_row(&row);
+				}
+			}
+		});
+
+		for (band_index, band_y) in (0..height).step_by(TILE_SIZE as usize).enumerate() {
+			let rows = band_y..(band_y + TILE_SIZE).min(height);
+			let mut band = vec![vec![Vector::default(); width as usize]; rows.len()];
+
+			// Columns of tiles of the band are rendered in parallel
+			let rows_in_region = rows.start.max(y0)..rows.end.min(y1);
+			let tile_xs: Vec<u32> = (x0..x1).step_by(TILE_SIZE as usize).collect();
+			let tiles: Vec<Vec<(u32, u32, Vector)>> = tile_xs
+				.par_iter()
+				.map(|&tile_x| {
+					let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+					for y in rows_in_region.clone() {
+						if cancel.load(Ordering::Relaxed) {
+							break;
+						}
+						for x in tile_x..(tile_x + TILE_SIZE).min(x1) {
+							let (color, _, _) = render_pixel(scene, camera, settings, (x, y));
+							pixels.push((x, y, color));
+						}
+					}
+					pixels
+				})
+				.collect();
+			for (x, y, color) in tiles.into_iter().flatten() {
+				band[(y - band_y) as usize][x as usize] = color;
+			}
+
+			if sender.send(band).is_err() {
+				// The writer stopped (it panicked, which is reported when the scope ends)
+				break;
+			}
+			if settings.show_progress {
+				eprint!("\rRendered {}/{} bands of rows", band_index + 1, band_count);
+			}
+		}
+		drop(sender);
+	});
+	if settings.show_progress {
+		eprintln!();
+	}
+}
+
+/** Take one more sample, at a random point, for every pixel in the region, and add it to the mean of the samples of the
+pixel in the buffer (which must have the size of the image). Repeating this gives increasingly converged images. Every
+pass (numbered from zero) takes different samples; the samples per pixel and adaptive sampling settings are ignored. */
+pub fn render_pass(
+	scene: &Scene,
+	camera: &Camera,
+	settings: &RenderSettings,
+	pass: u32,
+	buffer: &mut RenderBuffer,
+) {
+	let (width, height) = (settings.width, settings.height);
+	assert_eq!((buffer.width, buffer.height), (width, height));
+	let (_, _, x1, y1) = settings.region;
+	let pass_seed = settings.seed ^ u64::from(pass).wrapping_mul(0xD1B5_4A32_D192_ED03);
+
+	let rendered: Vec<Vec<_>> = tiles(settings.region)
+		.par_iter()
+		.map(|&(tile_x, tile_y)| {
+			let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE) as usize);
+			for y in tile_y..(tile_y + TILE_SIZE).min(y1) {
+				for x in tile_x..(tile_x + TILE_SIZE).min(x1) {
+					sampling::seed_pixel(pass_seed, u64::from(y) * u64::from(width) + u64::from(x));
+					let ray = camera.jittered_ray_for_pixel(x, y, width, height);
+					if let (Some(statistics), None) = (&scene.statistics, settings.debug) {
+						statistics.count_pixel(1);
+					}
+					let (color, hit) = trace_camera_ray(scene, &ray, settings);
+					pixels.push((x, y, color, hit));
+				}
+			}
+			pixels
+		})
+		.collect();
+
+	for (x, y, color, hit) in rendered.into_iter().flatten() {
+		let index = pixel_index(width, x, y);
+		let samples = f64::from(buffer.samples[index]);
+		buffer.pixels[index] = (buffer.pixels[index] * samples + color) / (samples + 1.0);
+		buffer.coverage[index] =
+			(buffer.coverage[index] * samples + f64::from(u8::from(hit))) / (samples + 1.0);
+		buffer.samples[index] += 1;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use camera::{FovAxis, Projection};
+	use primitives::Sphere;
+	use scene::tests::{diffuse, scene};
+	use scene::{Background, Light};
+	use std::sync::Arc;
+
+	/** A backend that only renders whole images, of a single color */
+	struct FlatRenderer;
+
+	impl Renderer for FlatRenderer {
+		fn render(&self, _: &Scene, _: &Camera, settings: &RenderSettings) -> Vec<Vector> {
+			vec![Vector::new(1.0, 0.0, 0.0); (settings.width * settings.height) as usize]
+		}
+	}
+
+	/** A camera at the origin looking along the negative Z axis */
+	fn camera() -> Camera {
+		Camera {
+			position: Vector::default(),
+			look_at: Vector::new(0.0, 0.0, -1.0),
+			up: Vector::new(0.0, 1.0, 0.0),
+			fov: 1.0,
+			fov_axis: FovAxis::Vertical,
+			aperture: 0.0,
+			focus_distance: 1.0,
+			shutter: 0.0,
+			projection: Projection::Perspective,
+		}
+	}
+
+	#[test]
+	fn renderers_render_whole_images() {
+		let sphere = Sphere {
+			center: Vector::new(0.0, 0.0, -5.0),
+			radius: 2.0,
+			material: Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0))),
+		};
+		let light = Light::Point {
+			position: Vector::new(5.0, 5.0, 0.0),
+			intensity: 1.0,
+			falloff: false,
+		};
+		let scene = scene(vec![Arc::new(sphere)], vec![light]);
+		let camera = camera();
+		let settings = RenderSettings::new(16, 8, 2);
+		let cancel = AtomicBool::new(false);
+
+		// The CPU renderer renders like the functions of the crate, and can render parts of images
+		let image = render(&scene, &camera, &settings);
+		assert!(image.iter().any(|color| color.x > 0.0));
+		assert_eq!(CpuRenderer.render(&scene, &camera, &settings), image);
+		assert_eq!(
+			CpuRenderer.render_cancellable(&scene, &camera, &settings, &cancel),
+			image
+		);
+		assert!(CpuRenderer.renders_parts());
+
+		// Other backends only need to render whole images
+		let flat = FlatRenderer.render(&scene, &camera, &settings);
+		assert_eq!(
+			FlatRenderer.render_cancellable(&scene, &camera, &settings, &cancel),
+			flat
+		);
+		assert!(!FlatRenderer.renders_parts());
+	}
+
+	#[test]
+	fn cancelled_render_renders_no_pixels() {
+		let mut scene = scene(vec![], vec![]);
+		scene.background = Background::Solid(Vector::new(1.0, 1.0, 1.0));
+		let settings = RenderSettings::new(16, 8, 2);
+
+		let buffer = render_cancellable(&scene, &camera(), &settings, &AtomicBool::new(false));
+		assert!(buffer.samples.iter().all(|&samples| samples > 0));
+		assert!(buffer.pixels.iter().all(|color| color.x > 0.0));
+
+		// Cancelled before starting, no pixel is rendered
+		let buffer = render_cancellable(&scene, &camera(), &settings, &AtomicBool::new(true));
+		assert_eq!(buffer.pixels.len(), 16 * 8);
+		assert!(buffer.samples.iter().all(|&samples| samples == 0));
+		assert!(buffer
+			.pixels
+			.iter()
+			.all(|&color| color == Vector::default()));
+	}
+
+	#[test]
+	fn background_pixels_have_no_coverage() {
+		let sphere = Sphere {
+			center: Vector::new(0.0, 0.0, -5.0),
+			radius: 1.0,
+			material: Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0))),
+		};
+		let mut scene = scene(vec![Arc::new(sphere)], vec![]);
+		scene.background = Background::Solid(Vector::new(1.0, 1.0, 1.0));
+		let settings = RenderSettings {
+			samples: 2,
+			..RenderSettings::new(16, 16, 2)
+		};
+		let buffer = render_cancellable(&scene, &camera(), &settings, &AtomicBool::new(false));
+
+		// The sphere covers the center of the image, but not the corners or the edge in between
+		assert_eq!(buffer.coverage[pixel_index(16, 8, 8)], 1.0);
+		assert_eq!(buffer.coverage[pixel_index(16, 0, 0)], 0.0);
+		assert_eq!(buffer.coverage[pixel_index(16, 15, 8)], 0.0);
+		assert!(buffer
+			.coverage
+			.iter()
+			.any(|&coverage| coverage > 0.0 && coverage < 1.0));
+
+		// Progressive passes estimate the same coverage
+		let mut passes = RenderBuffer::new(16, 16);
+		for pass in 0..4 {
+			render_pass(&scene, &camera(), &settings, pass, &mut passes);
+		}
+		assert_eq!(passes.coverage[pixel_index(16, 8, 8)], 1.0);
+		assert_eq!(passes.coverage[pixel_index(16, 0, 0)], 0.0);
+
+		// As do renders along with AOVs
+		let (rendered, _) = render_aovs(&scene, &camera(), &settings, &[], &AtomicBool::new(false));
+		assert_eq!(rendered.coverage, buffer.coverage);
+	}
+}
@@ -1,17 +1,328 @@
-mod geometry;
-mod primitives;
-mod scene;
-
-use clap::{App, Arg};
-use geometry::{Ray, Vector};
-use image::ImageBuffer;
-use primitives::{Plane, Sphere};
-use rayon::prelude::*;
-use scene::{Light, Material, Scene};
-use std::sync::Arc;
+use clap::{App, Arg, ArgMatches};
+use image::{DynamicImage, ImageBuffer};
+use log::{debug, info, warn, LevelFilter};
+use std::fmt::Debug;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tyray::camera::{Camera, Projection};
+use tyray::description::{
+	CameraDescription, EnvironmentMapDescription, ObjectDescription, ObjectEntry,
+	RenderDescription, SceneDescription,
+};
+use tyray::geometry::Vector;
+use tyray::output::{self, OutputFormat, RenderBuffer};
+use tyray::primitives::{Rectangle, Sphere};
+use tyray::scene::{
+	Aov, Background, DebugMode, EnvironmentMapping, Light, Material, MaterialLibrary, MipMap,
+	RadianceImage, RayStatistics, Texture, WrapMode,
+};
+use tyray::tonemap::ToneMapping;
+use tyray::{description, mesh, AdaptiveSampling, CpuRenderer, RenderSettings, Renderer};
 
-fn main() {
-	let app = App::new("tyray")
+/** Time between saving checkpoints of the image being rendered (see --checkpoint) */
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/** Factor by which the preview (see --preview) is smaller than the full image */
+const PREVIEW_SCALE: u32 = 8;
+
+/** Description of the built-in demo scene, used when no scene file is provided. The floor is optionally textured with an
+image and a normal map (which are not part of the description when it is written to a scene file). */
+fn demo_scene(
+	floor_texture: Option<RadianceImage>,
+	floor_normal_map: Option<DynamicImage>,
+	texture_wrap: WrapMode,
+	texture_scale: f64,
+) -> SceneDescription {
+	let ivory = Arc::new(Material {
+		albedo_diffuse: 0.6,
+		albedo_specular: 0.3,
+		albedo_reflect: 0.1,
+		diffuse_color: Texture::Solid(Vector::new(0.4, 0.4, 0.3)),
+		specular_exponent: 50.0,
+		..Default::default()
+	});
+
+	let red_rubber = Arc::new(Material {
+		albedo_diffuse: 0.9,
+		albedo_specular: 0.1,
+		diffuse_color: Texture::Solid(Vector::new(0.3, 0.1, 0.1)),
+		specular_exponent: 10.0,
+		..Default::default()
+	});
+
+	let mirror = Arc::new(Material {
+		albedo_diffuse: 0.0,
+		albedo_specular: 10.0,
+		albedo_reflect: 0.8,
+		diffuse_color: Texture::Solid(Vector::new(1.0, 1.0, 1.0)),
+		specular_exponent: 1425.0,
+		..Default::default()
+	});
+
+	let glass = Arc::new(Material {
+		albedo_diffuse: 0.0,
+		albedo_specular: 0.5,
+		albedo_reflect: 0.1,
+		albedo_refract: 0.8,
+		diffuse_color: Texture::Solid(Vector::new(0.6, 0.7, 0.8)),
+		specular_exponent: 125.0,
+		refractive_index: 1.3,
+		..Default::default()
+	});
+
+	let floor = Arc::new(Material {
+		albedo_diffuse: 0.3,
+		albedo_specular: 0.3,
+		albedo_reflect: 0.5,
+		diffuse_color: Texture::Solid(Vector::new(0.7, 0.7, 0.2)),
+		specular_exponent: 100.0,
+		diffuse_texture: floor_texture.map(|texture| Arc::new(MipMap::new(texture))),
+		normal_map: floor_normal_map.map(Arc::new),
+		texture_wrap,
+		texture_scale,
+		..Default::default()
+	});
+
+	let object = |object| ObjectEntry {
+		object,
+		visible: true,
+		casts_shadows: true,
+		velocity: None,
+		id: None,
+	};
+
+	let objects = vec![
+		object(ObjectDescription::Sphere(Sphere {
+			center: Vector::new(-3.0, 0.0, -16.0),
+			radius: 6.0,
+			material: ivory.clone(),
+		})),
+		object(ObjectDescription::Sphere(Sphere {
+			center: Vector::new(-1.0, -1.5, -8.0),
+			radius: 2.0,
+			material: glass.clone(),
+		})),
+		object(ObjectDescription::Sphere(Sphere {
+			center: Vector::new(5.0, -3.0, -8.0),
+			radius: 2.0,
+			material: glass.clone(),
+		})),
+		object(ObjectDescription::Sphere(Sphere {
+			center: Vector::new(1.5, -0.5, -18.0),
+			radius: 3.0,
+			material: red_rubber.clone(),
+		})),
+		object(ObjectDescription::Sphere(Sphere {
+			center: Vector::new(7.0, 5.0, -18.0),
+			radius: 4.0,
+			material: mirror.clone(),
+		})),
+		object(ObjectDescription::Rectangle(Rectangle {
+			x_min: -10.0,
+			x_max: 10.0,
+			z_min: -100.0,
+			z_max: -5.0,
+			y: -3.0,
+			double_sided: true,
+			material: floor.clone(),
+		})), /*Arc::new(Sphere {
+				 center: Vector { x: 0.0, y: 0.0, z: -16.0 }, radius: 12.0, material: mirror.clone()
+			 })*/
+	];
+
+	SceneDescription {
+		background: Background::Solid(Vector::new(0.2, 0.7, 0.8)),
+		environment_map: Some(EnvironmentMapDescription::Image("./envmap.jpg".to_string())),
+		environment_mapping: EnvironmentMapping::Planar,
+		input_degamma: true,
+		ambient: Vector::default(),
+		fog_color: Vector::default(),
+		fog_density: 0.0,
+		camera: CameraDescription::default(),
+		render: RenderDescription::default(),
+		materials: MaterialLibrary::default(),
+		objects,
+		lights: vec![
+			Light::Point {
+				position: Vector::new(-20.0, 20.0, 20.0),
+				intensity: 1.5,
+				falloff: false,
+			},
+			Light::Point {
+				position: Vector::new(30.0, 50.0, -25.0),
+				intensity: 1.8,
+				falloff: false,
+			},
+			Light::Point {
+				position: Vector::new(30.0, 20.0, 30.0),
+				intensity: 1.7,
+				falloff: false,
+			},
+		],
+	}
+}
+
+/** Convert a linear color to an 8-bit display color by applying tone mapping and gamma correction. */
+fn display_color(color: Vector, tone_mapping: ToneMapping, gamma: f64) -> image::Rgb<u8> {
+	let mut color = tone_mapping.apply(color);
+
+	// Gamma correction
+	color = Vector::new(
+		color.x.max(0.0).powf(1.0 / gamma),
+		color.y.max(0.0).powf(1.0 / gamma),
+		color.z.max(0.0).powf(1.0 / gamma),
+	);
+
+	let color = (color * 255.0).clamp(0.0, 255.0);
+	image::Rgb([color.x as u8, color.y as u8, color.z as u8])
+}
+
+/** Convert a linear color to an 8-bit display color like `display_color`, with the coverage of the pixel (the fraction
+covered by objects) as alpha, so that the background is transparent. */
+fn display_pixel(
+	color: Vector,
+	coverage: f64,
+	tone_mapping: ToneMapping,
+	gamma: f64,
+) -> image::Rgba<u8> {
+	let [r, g, b] = display_color(color, tone_mapping, gamma).data;
+	let a = (coverage * 255.0).round().clamp(0.0, 255.0) as u8;
+	image::Rgba([r, g, b, a])
+}
+
+/** Value of a command line option, or of the scene file when it specifies one and the option is not given explicitly.
+Options given on the command line take precedence over the scene file, which takes precedence over option defaults. */
+fn setting<T: FromStr>(matches: &ArgMatches, name: &str, file_value: Option<T>) -> T
+where
+	T::Err: Debug,
+{
+	match file_value {
+		Some(value) if matches.occurrences_of(name) == 0 => value,
+		_ => matches
+			.value_of(name)
+			.unwrap()
+			.parse()
+			.unwrap_or_else(|e| panic!("invalid {}: {:?}", name, e)),
+	}
+}
+
+/** Path for a frame of an animation: the frame number is appended to the file name (e.g. out_001.png) */
+fn frame_path(path: &str, frame: usize) -> String {
+	suffixed_path(path, &format!("{:03}", frame))
+}
+
+/** Path with the suffix appended to the file name, before the extension (e.g. out_preview.png) */
+fn suffixed_path(path: &str, suffix: &str) -> String {
+	let path = Path::new(path);
+	let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+	let file_name = match path.extension().and_then(|e| e.to_str()) {
+		Some(extension) => format!("{}_{}.{}", stem, suffix, extension),
+		None => format!("{}_{}", stem, suffix),
+	};
+	path.with_file_name(file_name)
+		.to_string_lossy()
+		.into_owned()
+}
+
+/** Path for an image of a component of the image (see --aov): the name of the component is inserted before the
+extension (e.g. out.diffuse.png) */
+fn aov_path(path: &str, aov: Aov) -> String {
+	let path = Path::new(path);
+	let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+	let file_name = match path.extension().and_then(|e| e.to_str()) {
+		Some(extension) => format!("{}.{}.{}", stem, aov.name(), extension),
+		None => format!("{}.{}", stem, aov.name()),
+	};
+	path.with_file_name(file_name)
+		.to_string_lossy()
+		.into_owned()
+}
+
+/** Write the image in the format. The alpha channel (the fraction of each pixel covered by objects) is only written to
+image formats that support it. */
+fn save_image(
+	path: &str,
+	format: OutputFormat,
+	(width, height): (u32, u32),
+	image: &[Vector],
+	alpha: Option<&[f64]>,
+	tone_mapping: ToneMapping,
+	gamma: f64,
+) {
+	match format {
+		OutputFormat::Hdr => {
+			// Linear colors are written as-is, without tone mapping or gamma correction
+			output::save_hdr(path, width, height, image).unwrap();
+		}
+		OutputFormat::Ppm => {
+			let pixels: Vec<_> = image
+				.iter()
+				.map(|&color| display_color(color, tone_mapping, gamma))
+				.collect();
+
+			output::save_ppm(path, width, height, &pixels).unwrap();
+		}
+		OutputFormat::Image => match alpha {
+			Some(alpha) => {
+				let img = ImageBuffer::from_fn(width, height, |x, y| {
+					let index = output::pixel_index(width, x, y);
+					display_pixel(image[index], alpha[index], tone_mapping, gamma)
+				});
+
+				img.save(path).unwrap();
+			}
+			None => {
+				let img = ImageBuffer::from_fn(width, height, |x, y| {
+					display_color(image[output::pixel_index(width, x, y)], tone_mapping, gamma)
+				});
+
+				img.save(path).unwrap();
+			}
+		},
+	}
+}
+
+/** Show the image in the buffer in a window while it is rendered (see --window), until the window is closed. This must
+be called by the main thread. */
+#[cfg(feature = "window")]
+fn preview_in_window(buffer: &Mutex<RenderBuffer>, tone_mapping: ToneMapping, gamma: f64) {
+	tyray::window::show("tyray", buffer, |color| {
+		display_color(color, tone_mapping, gamma).data
+	});
+}
+
+#[cfg(not(feature = "window"))]
+fn preview_in_window(_buffer: &Mutex<RenderBuffer>, _tone_mapping: ToneMapping, _gamma: f64) {
+	warn!("Built without the window feature, the image is only written to a file");
+}
+
+/** A flag that is set when Ctrl-C is pressed, so that rendering stops and the part rendered so far is written. Pressing
+Ctrl-C again exits immediately. */
+fn cancel_on_interrupt() -> Arc<AtomicBool> {
+	let cancel = Arc::new(AtomicBool::new(false));
+	let flag = cancel.clone();
+	let result = ctrlc::set_handler(move || {
+		if flag.swap(true, Ordering::Relaxed) {
+			std::process::exit(130);
+		}
+		eprintln!("\nStopping, press Ctrl-C again to exit without writing the image");
+	});
+	if let Err(e) = result {
+		warn!(
+			"Could not handle Ctrl-C, rendering cannot be stopped: {}",
+			e
+		);
+	}
+	cancel
+}
+
+/** The command line options */
+fn app() -> App<'static, 'static> {
+	App::new("tyray")
 		.version("1.0")
 		.author("Tommy van der Vorst <tommy@pixelspark.nl>")
 		.about("Ray tracer")
@@ -22,6 +333,13 @@ fn main() {
 				.required(true)
 				.index(1),
 		)
+		.arg(
+			Arg::with_name("format")
+				.long("format")
+				.help("Output format (by default, ppm for .ppm files, linear Radiance HDR for .hdr files, otherwise inferred by the image encoder)")
+				.possible_values(&["image", "ppm", "hdr"])
+				.takes_value(true),
+		)
 		.arg(
 			Arg::with_name("width")
 				.long("width")
@@ -39,276 +357,1092 @@ fn main() {
 		.arg(
 			Arg::with_name("fov")
 				.long("fov")
-				.help("Field of view angle")
+				.help("Field of view angle in degrees, along the axis given by --fov-axis")
 				.default_value("90")
 				.required(true),
 		)
+		.arg(
+			Arg::with_name("shutter")
+				.long("shutter")
+				.help("Fraction of the frame during which the shutter is open, blurring moving objects (0 disables motion blur)")
+				.default_value("0")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("fov-axis")
+				.long("fov-axis")
+				.help("Image axis along which --fov is measured (the other follows from the aspect ratio)")
+				.possible_values(&["vertical", "horizontal"])
+				.default_value("vertical")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("aperture")
+				.long("aperture")
+				.help("Diameter of the camera lens (0 for a pinhole camera without depth of field)")
+				.default_value("0")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("focus-distance")
+				.long("focus-distance")
+				.help("Distance from the camera at which objects are in focus")
+				.default_value("10")
+				.required(true),
+		)
 		.arg(
 			Arg::with_name("depth")
 				.long("depth")
 				.help("Ray tracing depth")
 				.default_value("6")
 				.required(true),
-		);
+		)
+		.arg(
+			Arg::with_name("samples")
+				.long("samples")
+				.help(
+					"Anti-aliasing: cast an NxN grid of rays per pixel (render time grows with N²)",
+				)
+				.default_value("1")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("sample-pattern")
+				.long("sample-pattern")
+				.help("Placement of the samples within each pixel: centers of a regular grid, random points in its cells, the Halton sequence or blue noise")
+				.possible_values(&["grid", "jitter", "halton", "blue-noise"])
+				.default_value("grid")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("progressive")
+				.long("progressive")
+				.help("Render this many passes of one random sample per pixel, writing the image after each pass (overrides samples)")
+				.takes_value(true)
+				.conflicts_with("samples-min"),
+		)
+		.arg(
+			Arg::with_name("samples-min")
+				.long("samples-min")
+				.help("Adaptive sampling: cast this many random rays per pixel, and more while the pixel is noisy (overrides samples)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("samples-max")
+				.long("samples-max")
+				.help("Adaptive sampling: maximum number of rays per pixel")
+				.default_value("64")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("variance")
+				.long("variance")
+				.help("Adaptive sampling: cast more rays while the variance of the estimated pixel color exceeds this")
+				.default_value("0.0001")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("tonemap")
+				.long("tonemap")
+				.help("Tone mapping operator applied to colors before gamma correction")
+				.possible_values(&["clamp", "reinhard", "aces"])
+				.default_value("clamp")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("gamma")
+				.long("gamma")
+				.help("Gamma applied to output colors (1.0 disables gamma correction)")
+				.default_value("2.2")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("ambient")
+				.long("ambient")
+				.help("Intensity of ambient light reaching all surfaces (overrides the scene)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("threads")
+				.long("threads")
+				.help("Number of render threads (default 0 uses all available cores)")
+				.default_value("0")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("scene")
+				.long("scene")
+				.help("JSON scene description file (renders the built-in demo scene when absent)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("dump-scene")
+				.long("dump-scene")
+				.help("Write the scene (the demo scene unless --scene is given) with the camera and render settings to a JSON scene file at this path, or print it when no path is given, instead of rendering (images used as textures are not included)")
+				.takes_value(true)
+				.min_values(0)
+				.max_values(1),
+		)
+		.arg(
+			Arg::with_name("obj")
+				.long("obj")
+				.help("Wavefront OBJ file with a mesh to add to the scene")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("materials")
+				.long("materials")
+				.help("JSON file with named materials, used by faces of the OBJ mesh that name them with usemtl")
+				.takes_value(true)
+				.requires("obj"),
+		)
+		.arg(
+			Arg::with_name("texture")
+				.long("texture")
+				.help("Image used to texture the floor of the demo scene")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("texture-wrap")
+				.long("texture-wrap")
+				.help("How the texture and normal map of the floor of the demo scene continue outside their edges")
+				.possible_values(&["clamp", "repeat", "mirror"])
+				.default_value("clamp")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("texture-scale")
+				.long("texture-scale")
+				.help("Number of times the texture and normal map fit along each side of the floor of the demo scene (see --texture-wrap)")
+				.default_value("1")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("no-input-degamma")
+				.long("no-input-degamma")
+				.help("Use colors of image textures and environment maps as linear colors, instead of converting them from sRGB"),
+		)
+		.arg(
+			Arg::with_name("normal-map")
+				.long("normal-map")
+				.help("Image with tangent space normals used to perturb the floor of the demo scene")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("window")
+				.long("window")
+				.help("Show the image in a window while it is rendered (requires building with the window feature)")
+				.conflicts_with_all(&["bench", "aov"]),
+		)
+		.arg(
+			Arg::with_name("stream")
+				.long("stream")
+				.help("Write rows of the image to the output file as soon as they are rendered instead of keeping the whole image in memory, for very large images (PNG and PPM output only)")
+				.conflicts_with_all(&[
+					"bench",
+					"aov",
+					"window",
+					"progressive",
+					"checkpoint",
+					"denoise",
+					"transparent-bg",
+				]),
+		)
+		.arg(
+			Arg::with_name("bench")
+				.long("bench")
+				.help("Render the specified number of times and report timings instead of writing the image")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("autoframe")
+				.long("autoframe")
+				.help("Move the camera back so that all objects (except unbounded ones such as planes) are in view"),
+		)
+		.arg(
+			Arg::with_name("grid-resolution")
+				.long("grid-resolution")
+				.help("Find objects hit by rays using a uniform grid of this many cells (at most 128) along each axis, instead of a bounding volume hierarchy")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("ortho")
+				.long("ortho")
+				.help("Use an orthographic projection showing the specified width of the scene (ignores fov and aperture)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("crop")
+				.long("crop")
+				.help("Only render pixels from (x0, y0) up to (x1, y1), leaving the rest of the image black")
+				.number_of_values(4)
+				.value_names(&["x0", "y0", "x1", "y1"]),
+		)
+		.arg(
+			Arg::with_name("frames")
+				.long("frames")
+				.help("Render an animation of this many frames, orbiting the camera around its focus point (writes numbered files)")
+				.default_value("1")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("preview")
+				.long("preview")
+				.help("First quickly render and write a low resolution preview (to a file with _preview appended to its name)"),
+		)
+		.arg(
+			Arg::with_name("debug")
+				.long("debug")
+				.help("Color pixels by a property of the first surface hit instead of shading them")
+				.possible_values(&["normals", "depth", "albedo", "object-id"])
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("aov")
+				.long("aov")
+				.help("Also write these components of the image to separate files, with the name of the component inserted before the extension (e.g. out.diffuse.png)")
+				.possible_values(&["diffuse", "specular", "reflection", "depth"])
+				.takes_value(true)
+				.multiple(true)
+				.require_delimiter(true)
+				.conflicts_with_all(&["debug", "progressive", "checkpoint"]),
+		)
+		.arg(
+			Arg::with_name("seed")
+				.long("seed")
+				.help("Seed for random sampling, for reproducible renders (random by default)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("env-light")
+				.long("env-light")
+				.help("Light diffuse surfaces by the environment, casting this many rays per hit")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("far")
+				.long("far")
+				.help("Maximum distance at which rays hit objects (further objects are not rendered)")
+				.default_value("inf")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("fog-density")
+				.long("fog-density")
+				.help("Fraction of light scattered by fog per unit of distance from the camera (0 disables fog)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("fog-color")
+				.long("fog-color")
+				.help("Color of the fog, as comma-separated red, green and blue components (e.g. 0.7,0.7,0.8)")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("gi")
+				.long("gi")
+				.help("Path tracing: light diffuse surfaces by light reflected off other surfaces (use many samples)"),
+		)
+		.arg(
+			Arg::with_name("denoise")
+				.long("denoise")
+				.help("Smooth out noise in the rendered image, preserving edges between surfaces")
+				.conflicts_with("debug"),
+		)
+		.arg(
+			Arg::with_name("transparent-bg")
+				.long("transparent-bg")
+				.help("Make pixels showing the background transparent (for image formats with an alpha channel, such as PNG)"),
+		)
+		.arg(
+			Arg::with_name("checkpoint")
+				.long("checkpoint")
+				.help("File to which the image is saved regularly while rendering, so that rendering can be resumed")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("resume")
+				.long("resume")
+				.help("Continue rendering from the checkpoint file (use the same settings as before)")
+				.requires("checkpoint"),
+		)
+		.arg(
+			Arg::with_name("stats")
+				.long("stats")
+				.help("Count the rays cast of each kind and print the totals after rendering"),
+		)
+		.arg(
+			Arg::with_name("roulette")
+				.long("roulette")
+				.help("Randomly terminate reflected and refracted rays with little contribution (faster, but noisy)"),
+		)
+		.arg(
+			Arg::with_name("quiet")
+				.long("quiet")
+				.short("q")
+				.help("Only log warnings and errors, and do not show progress")
+				.conflicts_with("verbose"),
+		)
+		.arg(
+			Arg::with_name("verbose")
+				.long("verbose")
+				.short("v")
+				.help("Log additional details about the scene and rendering"),
+		)
+}
 
-	let matches = app.get_matches();
+fn main() {
+	let matches = app().get_matches();
+	let quiet = matches.is_present("quiet");
+	let log_level = if quiet {
+		LevelFilter::Warn
+	} else if matches.is_present("verbose") {
+		LevelFilter::Debug
+	} else {
+		LevelFilter::Info
+	};
+	env_logger::Builder::new()
+		.filter_level(log_level)
+		.parse_default_env()
+		.init();
 	let output_path = matches.value_of("output").expect("no output path provided");
+	let output_format = match matches.value_of("format") {
+		Some(format) => format.parse().expect("invalid output format"),
+		None => OutputFormat::from_path(output_path),
+	};
+	assert!(
+		!matches.is_present("stream") || output::RowWriter::supports(output_path, output_format),
+		"only PNG and PPM images can be written row by row"
+	);
+
+	// A scene file may also specify camera and render settings
+	let input_degamma = !matches.is_present("no-input-degamma");
+	let mut scene_description = match matches.value_of("scene") {
+		Some(path) => description::load_scene_description(path).expect("could not load scene file"),
+		None => {
+			let floor_texture = matches.value_of("texture").map(|path| {
+				let texture = image::open(path).expect("could not load texture");
+				RadianceImage::from_image(&texture, input_degamma)
+			});
+			let floor_normal_map = matches
+				.value_of("normal-map")
+				.map(|path| image::open(path).expect("could not load normal map"));
+			let texture_wrap = matches
+				.value_of("texture-wrap")
+				.unwrap()
+				.parse()
+				.expect("invalid texture wrap mode");
+			let texture_scale: f64 = matches
+				.value_of("texture-scale")
+				.unwrap()
+				.parse()
+				.expect("invalid texture scale");
+			assert!(texture_scale > 0.0);
+			demo_scene(floor_texture, floor_normal_map, texture_wrap, texture_scale)
+		}
+	};
+	scene_description.input_degamma &= input_degamma;
+	let (file_camera, file_render) = (scene_description.camera, scene_description.render);
 
 	// Output image width and height
-	let width = matches
-		.value_of("width")
+	let width: u32 = setting(&matches, "width", file_render.width);
+	let height: u32 = setting(&matches, "height", file_render.height);
+	let fov_angle: f64 = setting(&matches, "fov", file_camera.fov);
+	let shutter: f64 = matches
+		.value_of("shutter")
 		.unwrap()
 		.parse()
-		.expect("invalid width");
-	let height = matches
-		.value_of("height")
+		.expect("invalid shutter");
+	assert!((0.0..=1.0).contains(&shutter));
+	let aperture: f64 = setting(&matches, "aperture", file_camera.aperture);
+	let focus_distance: f64 = setting(&matches, "focus-distance", file_camera.focus_distance);
+	let max_depth: i32 = setting(&matches, "depth", file_render.depth);
+	let samples: u32 = setting(&matches, "samples", file_render.samples);
+	let tone_mapping: ToneMapping = matches
+		.value_of("tonemap")
 		.unwrap()
 		.parse()
-		.expect("invalid width");
-	let fov_angle: f64 = matches
-		.value_of("fov")
+		.expect("invalid tone mapping operator");
+	let gamma: f64 = matches
+		.value_of("gamma")
 		.unwrap()
 		.parse()
-		.expect("invalid fov");
-	let max_depth: i32 = matches
-		.value_of("depth")
+		.expect("invalid gamma");
+	let debug: Option<DebugMode> = matches
+		.value_of("debug")
+		.map(|mode| mode.parse().expect("invalid debug mode"));
+
+	// Debug visualizations are not radiance, so they are written without gamma correction
+	let gamma = if debug.is_some() { 1.0 } else { gamma };
+	let threads: usize = matches
+		.value_of("threads")
 		.unwrap()
 		.parse()
-		.expect("invalid depth");
+		.expect("invalid number of threads");
 	assert!(width > 0);
 	assert!(max_depth > 0);
 	assert!(height > 0);
-	assert!(fov_angle > 0.0 && fov_angle <= 360.0);
+	assert!(samples > 0);
+	assert!(gamma > 0.0);
+	assert!(fov_angle > 0.0 && fov_angle < 180.0);
+	assert!(aperture >= 0.0);
+	assert!(focus_distance > 0.0);
+
+	let seed: u64 = match matches.value_of("seed") {
+		Some(seed) => seed.parse().expect("invalid seed"),
+		None => rand::random(),
+	};
+
+	let adaptive = match matches.value_of("samples-min") {
+		Some(min_samples) => {
+			let adaptive = AdaptiveSampling {
+				min_samples: min_samples
+					.parse()
+					.expect("invalid minimum number of samples"),
+				max_samples: matches
+					.value_of("samples-max")
+					.unwrap()
+					.parse()
+					.expect("invalid maximum number of samples"),
+				variance: matches
+					.value_of("variance")
+					.unwrap()
+					.parse()
+					.expect("invalid variance"),
+			};
+			assert!(adaptive.min_samples > 0);
+			assert!(adaptive.max_samples >= adaptive.min_samples);
+			assert!(adaptive.variance >= 0.0);
+			Some(adaptive)
+		}
+		None => None,
+	};
+
+	let region = match matches.values_of("crop") {
+		Some(values) => {
+			let bounds: Vec<u32> = values
+				.map(|v| v.parse().expect("invalid crop bounds"))
+				.collect();
+			assert!(bounds[0] < bounds[2] && bounds[2] <= width);
+			assert!(bounds[1] < bounds[3] && bounds[3] <= height);
+			(bounds[0], bounds[1], bounds[2], bounds[3])
+		}
+		None => (0, 0, width, height),
+	};
+
+	let projection = match matches.value_of("ortho") {
+		Some(view_width) => {
+			let view_width: f64 = view_width.parse().expect("invalid orthographic width");
+			assert!(view_width > 0.0);
+			Projection::Orthographic { width: view_width }
+		}
+		None => Projection::Perspective,
+	};
 
 	// Field of view
 	let fov: f64 = std::f64::consts::PI * 2.0 * fov_angle / 360.0;
 
-	println!("Configuring scene...");
+	info!("Configuring scene...");
 
-	let ivory = Arc::new(Material {
-		albedo_diffuse: 0.6,
-		albedo_specular: 0.3,
-		albedo_reflect: 0.1,
-		albedo_refract: 0.0,
-		diffuse_color: Vector {
-			x: 0.4,
-			y: 0.4,
-			z: 0.3,
-		},
-		specular_exponent: 50.0,
-		refractive_index: 1.0,
-	});
+	let camera_position = file_camera.position.unwrap_or(Vector::new(0.0, 0.0, 0.0));
+	let camera_look_at = file_camera.look_at.unwrap_or(Vector::new(0.0, 0.0, -1.0));
+	let camera_up = file_camera.up.unwrap_or(Vector::new(0.0, 1.0, 0.0));
 
-	let red_rubber = Arc::new(Material {
-		albedo_diffuse: 0.9,
-		albedo_specular: 0.1,
-		albedo_reflect: 0.0,
-		albedo_refract: 0.0,
-		diffuse_color: Vector {
-			x: 0.3,
-			y: 0.1,
-			z: 0.1,
-		},
-		specular_exponent: 10.0,
-		refractive_index: 1.0,
-	});
+	if matches.is_present("dump-scene") {
+		// The scene file includes the settings used, so that it renders the same without options
+		scene_description.camera = CameraDescription {
+			position: Some(camera_position),
+			look_at: Some(camera_look_at),
+			up: Some(camera_up),
+			fov: Some(fov_angle),
+			aperture: Some(aperture),
+			focus_distance: Some(focus_distance),
+		};
+		scene_description.render = RenderDescription {
+			width: Some(width),
+			height: Some(height),
+			samples: Some(samples),
+			depth: Some(max_depth),
+		};
+		let json =
+			serde_json::to_string_pretty(&scene_description).expect("could not serialize scene");
+		match matches.value_of("dump-scene") {
+			Some(path) => std::fs::write(path, json + "\n").expect("could not write scene file"),
+			None => println!("{}", json),
+		}
+		return;
+	}
 
-	let mirror = Arc::new(Material {
-		albedo_diffuse: 0.0,
-		albedo_specular: 10.0,
-		albedo_reflect: 0.8,
-		albedo_refract: 0.0,
-		diffuse_color: Vector {
-			x: 1.0,
-			y: 1.0,
-			z: 1.0,
-		},
-		specular_exponent: 1425.0,
-		refractive_index: 1.0,
-	});
+	let mut scene = scene_description
+		.into_scene()
+		.expect("could not load scene file");
 
-	let glass = Arc::new(Material {
-		albedo_diffuse: 0.0,
-		albedo_specular: 0.5,
-		albedo_reflect: 0.1,
-		albedo_refract: 0.8,
-		diffuse_color: Vector {
-			x: 0.6,
-			y: 0.7,
-			z: 0.8,
-		},
-		specular_exponent: 125.0,
-		refractive_index: 1.3,
-	});
+	if let Some(obj_path) = matches.value_of("obj") {
+		let material = Arc::new(Material {
+			albedo_diffuse: 0.6,
+			albedo_specular: 0.3,
+			albedo_reflect: 0.1,
+			diffuse_color: Texture::Solid(Vector::new(0.4, 0.4, 0.3)),
+			specular_exponent: 50.0,
+			..Default::default()
+		});
+		let library = match matches.value_of("materials") {
+			Some(path) => {
+				description::load_material_library(path).expect("could not load material library")
+			}
+			None => MaterialLibrary::default(),
+		};
+		let triangles =
+			mesh::load_obj(obj_path, material, &library).expect("could not load OBJ file");
+		info!("Loaded {} triangles from {}", triangles.len(), obj_path);
+		scene.add_objects(triangles);
+	}
 
-	let floor = Arc::new(Material {
-		albedo_diffuse: 0.3,
-		albedo_specular: 0.3,
-		albedo_reflect: 0.5,
-		albedo_refract: 0.0,
-		diffuse_color: Vector {
-			x: 0.7,
-			y: 0.7,
-			z: 0.2,
-		},
-		specular_exponent: 100.0,
-		refractive_index: 1.0,
-	});
+	// Non-finite or degenerate geometry would otherwise show up as NaN pixels or a hanging BVH build
+	scene
+		.validate()
+		.unwrap_or_else(|e| panic!("could not render scene: {}", e));
 
-	let scene = Arc::new(Scene {
-		environment_color: Vector {
-			x: 0.2,
-			y: 0.7,
-			z: 0.8,
-		},
-		environment_map: Some(image::open("./envmap.jpg").unwrap()),
-		objects: vec![
-			Arc::new(Sphere {
-				center: Vector {
-					x: -3.0,
-					y: 0.0,
-					z: -16.0,
-				},
-				radius: 6.0,
-				material: ivory.clone(),
-			}),
-			Arc::new(Sphere {
-				center: Vector {
-					x: -1.0,
-					y: -1.5,
-					z: -8.0,
-				},
-				radius: 2.0,
-				material: glass.clone(),
-			}),
-			Arc::new(Sphere {
-				center: Vector {
-					x: 5.0,
-					y: -3.0,
-					z: -8.0,
-				},
-				radius: 2.0,
-				material: glass.clone(),
-			}),
-			Arc::new(Sphere {
-				center: Vector {
-					x: 1.5,
-					y: -0.5,
-					z: -18.0,
-				},
-				radius: 3.0,
-				material: red_rubber.clone(),
-			}),
-			Arc::new(Sphere {
-				center: Vector {
-					x: 7.0,
-					y: 5.0,
-					z: -18.0,
-				},
-				radius: 4.0,
-				material: mirror.clone(),
-			}),
-			Arc::new(Plane {
-				x_min: -10.0,
-				x_max: 10.0,
-				z_min: -100.0,
-				z_max: -5.0,
-				y: -3.0,
-				material: floor.clone(),
-			}), /*Arc::new(Sphere {
-					center: Vector { x: 0.0, y: 0.0, z: -16.0 }, radius: 12.0, material: mirror.clone()
-				})*/
-		],
-		lights: vec![
-			Light {
-				position: Vector {
-					x: -20.0,
-					y: 20.0,
-					z: 20.0,
-				},
-				intensity: 1.5,
-			},
-			Light {
-				position: Vector {
-					x: 30.0,
-					y: 50.0,
-					z: -25.0,
-				},
-				intensity: 1.8,
-			},
-			Light {
-				position: Vector {
-					x: 30.0,
-					y: 20.0,
-					z: 30.0,
-				},
-				intensity: 1.7,
-			},
-		],
+	if let Some(resolution) = matches.value_of("grid-resolution") {
+		let resolution: usize = resolution.parse().expect("invalid grid resolution");
+		scene
+			.use_grid(resolution)
+			.unwrap_or_else(|e| panic!("could not use grid: {}", e));
+	}
+
+	if let Some(ambient) = matches.value_of("ambient") {
+		let ambient: f64 = ambient.parse().expect("invalid ambient intensity");
+		scene.ambient = Vector::new(ambient, ambient, ambient);
+	}
+	scene.russian_roulette = matches.is_present("roulette");
+	scene.global_illumination = matches.is_present("gi");
+	scene.max_distance = matches
+		.value_of("far")
+		.unwrap()
+		.parse()
+		.expect("invalid maximum distance");
+	assert!(scene.max_distance > 0.0);
+	if let Some(fog_density) = matches.value_of("fog-density") {
+		scene.fog_density = fog_density.parse().expect("invalid fog density");
+		assert!(scene.fog_density >= 0.0);
+	}
+	if let Some(fog_color) = matches.value_of("fog-color") {
+		scene.fog_color = fog_color.parse().expect("invalid fog color");
+	}
+	if matches.is_present("stats") {
+		scene.statistics = Some(RayStatistics::default());
+	}
+	if let Some(environment_samples) = matches.value_of("env-light") {
+		scene.environment_samples = environment_samples
+			.parse()
+			.expect("invalid number of environment samples");
+	}
+	let scene = Arc::new(scene);
+
+	let mut camera = Camera {
+		position: camera_position,
+		look_at: camera_look_at,
+		up: camera_up,
+		fov,
+		fov_axis: matches
+			.value_of("fov-axis")
+			.unwrap()
+			.parse()
+			.expect("invalid field of view axis"),
+		aperture,
+		focus_distance,
+		shutter,
+		projection,
+	};
+
+	if matches.is_present("autoframe") {
+		match scene.bounds() {
+			Some(bounds) => {
+				camera = camera.framing(&bounds, f64::from(width) / f64::from(height));
+				debug!(
+					"Framed scene, camera at ({:.2}, {:.2}, {:.2})",
+					camera.position.x, camera.position.y, camera.position.z
+				);
+			}
+			None => warn!("Scene has no objects of finite size to frame"),
+		}
+	}
+
+	debug!(
+		"Scene has {} objects and {} lights",
+		scene.objects.objects().len(),
+		scene.lights.len()
+	);
+	debug!(
+		"Rendering {}x{} pixels with {} samples per pixel, maximum depth {}",
+		width,
+		height,
+		samples * samples,
+		max_depth
+	);
+	debug!("Sampling with seed {}", seed);
+	info!("Start rendering...");
+
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.expect("could not create thread pool");
+
+	let renderer: Box<dyn Renderer> = Box::new(CpuRenderer);
+	let settings = RenderSettings {
+		width,
+		height,
+		region,
+		samples,
+		sample_pattern: matches
+			.value_of("sample-pattern")
+			.unwrap()
+			.parse()
+			.expect("invalid sample pattern"),
+		max_depth,
+		seed,
+		debug,
+		adaptive,
+		show_progress: false,
+	};
+
+	if let Some(runs) = matches.value_of("bench") {
+		let runs: usize = runs.parse().expect("invalid number of benchmark runs");
+		assert!(runs > 0);
+
+		// Only the rendering itself is timed, the result is discarded
+		let mut timings: Vec<f64> = (0..runs)
+			.map(|_| {
+				let start = Instant::now();
+				pool.install(|| renderer.render(&scene, &camera, &settings));
+				start.elapsed().as_secs_f64() * 1000.0
+			})
+			.collect();
+		timings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+		let mean = timings.iter().sum::<f64>() / runs as f64;
+		let median = if runs % 2 == 0 {
+			(timings[runs / 2 - 1] + timings[runs / 2]) / 2.0
+		} else {
+			timings[runs / 2]
+		};
+		println!(
+			"Rendered {} times: mean {:.1} ms, median {:.1} ms",
+			runs, mean, median
+		);
+		if let Some(statistics) = &scene.statistics {
+			println!(
+				"Cast {} in a scene of {} objects",
+				statistics,
+				scene.objects.objects().len()
+			);
+		}
+		return;
+	}
+
+	// Coverage is only written to formats that can store it in an alpha channel
+	let transparent_background = matches.is_present("transparent-bg");
+	if transparent_background && output_format != OutputFormat::Image {
+		warn!("The output format has no alpha channel, the background will not be transparent");
+	}
+	let transparent_background = transparent_background && output_format == OutputFormat::Image;
+
+	let frames: usize = matches
+		.value_of("frames")
+		.unwrap()
+		.parse()
+		.expect("invalid number of frames");
+	assert!(frames > 0);
+
+	let aovs: Vec<Aov> = matches.values_of("aov").map_or_else(Vec::new, |aovs| {
+		aovs.map(|aov| aov.parse().expect("invalid output variable"))
+			.collect()
 	});
+	if !aovs.is_empty() && adaptive.is_some() {
+		warn!("Adaptive sampling is not supported with --aov, pixels are sampled on a grid");
+	}
+
+	let passes: Option<u32> = matches
+		.value_of("progressive")
+		.map(|passes| passes.parse().expect("invalid number of passes"));
+	let checkpoint_path = matches.value_of("checkpoint");
+	assert!(
+		checkpoint_path.is_none() || frames == 1,
+		"checkpoints are not supported for animations"
+	);
+	let show_window = matches.is_present("window");
+	assert!(
+		!show_window || frames == 1,
+		"the window is not supported for animations"
+	);
+
+	// These modes render parts of the image at a time (or their coverage) using the functions of the crate, instead of
+	// the renderer
+	let renders_parts = passes.is_some()
+		|| checkpoint_path.is_some()
+		|| matches.is_present("stream")
+		|| !aovs.is_empty()
+		|| show_window
+		|| transparent_background;
+	assert!(
+		!renders_parts || renderer.renders_parts(),
+		"progressive, checkpointed, streamed, AOV, window and transparent renders are not supported by the renderer"
+	);
+
+	let cancel = cancel_on_interrupt();
+
+	// On some platforms (such as macOS) windows can only be shown by the main thread, so the image is then rendered by
+	// another thread, which hands the buffer it renders into to the window
+	let (window_sender, window_receiver) = mpsc::channel();
+	let render_frames = || {
+		let window_sender = window_sender;
 
-	println!("Start rendering...");
-
-	// Iterate over all horizontal lines in parallel and render each line
-	let image: Vec<Vec<_>> = (0..height)
-		.into_par_iter()
-		.map(move |y| {
-			// Render each pixel on this line
-			(0..width)
-				.map(|x| {
-					let w = f64::from(width);
-					let h = f64::from(height);
-					let fx = (2.0 * (f64::from(x) + 0.5) / w - 1.0) * ((fov / 2.0) * w / h).tan();
-					let fy = (2.0 * (f64::from(height - y) + 0.5) / h - 1.0) * (fov / 2.0).tan();
-					let dir = Vector {
-						x: fx,
-						y: fy,
-						z: -1.0,
+		for frame in 0..frames {
+			// Animations orbit the camera a full circle, ending just before the starting point
+			let (camera, path) = if frames > 1 {
+				info!("Frame {}/{}", frame + 1, frames);
+				let angle = 2.0 * std::f64::consts::PI * frame as f64 / frames as f64;
+				(camera.orbit(angle), frame_path(output_path, frame))
+			} else {
+				(camera, output_path.to_string())
+			};
+
+			if matches.is_present("preview") && frame == 0 {
+				// The preview shows the same part of the image, at a single sample per pixel
+				let (x0, y0, x1, y1) = region;
+				let preview_width = (width / PREVIEW_SCALE).max(1);
+				let preview_height = (height / PREVIEW_SCALE).max(1);
+				let preview_settings = RenderSettings {
+					width: preview_width,
+					height: preview_height,
+					region: (
+						(x0 / PREVIEW_SCALE).min(preview_width - 1),
+						(y0 / PREVIEW_SCALE).min(preview_height - 1),
+						x1.div_ceil(PREVIEW_SCALE).min(preview_width),
+						y1.div_ceil(PREVIEW_SCALE).min(preview_height),
+					),
+					samples: 1,
+					adaptive: None,
+					show_progress: false,
+					..settings
+				};
+				let preview = pool.install(|| renderer.render(&scene, &camera, &preview_settings));
+				let preview_path = suffixed_path(output_path, "preview");
+				info!("Rendered preview, writing to {}", preview_path);
+				save_image(
+					&preview_path,
+					output_format,
+					(preview_settings.width, preview_settings.height),
+					&preview,
+					None,
+					tone_mapping,
+					gamma,
+				);
+			}
+
+			let settings = RenderSettings {
+				show_progress: !quiet,
+				..settings
+			};
+			if matches.is_present("stream") {
+				let mut writer = output::RowWriter::create(&path, output_format, width, height)
+					.expect("could not create image file");
+				info!("Writing rows to {} while rendering", path);
+				pool.install(|| {
+					tyray::render_rows(&scene, &camera, &settings, &cancel, |row| {
+						let pixels: Vec<_> = row
+							.iter()
+							.map(|&color| display_color(color, tone_mapping, gamma))
+							.collect();
+						writer.write_row(&pixels).expect("could not write image");
+					})
+				});
+				writer.finish().expect("could not write image");
+				if cancel.load(Ordering::Relaxed) {
+					warn!("Rendering was stopped, the image is incomplete");
+					break;
+				}
+				continue;
+			}
+
+			let resume = |checkpoint_path: &str| {
+				let buffer =
+					output::load_buffer(checkpoint_path).expect("could not load checkpoint");
+				assert!(
+					buffer.width == width && buffer.height == height,
+					"checkpoint has a different image size"
+				);
+				info!("Resuming from {}", checkpoint_path);
+				buffer
+			};
+
+			// The buffer rendered into is shared with the window, if any
+			let buffer = match checkpoint_path {
+				Some(checkpoint_path) if matches.is_present("resume") => resume(checkpoint_path),
+				_ => RenderBuffer::new(width, height),
+			};
+			let buffer = Arc::new(Mutex::new(buffer));
+			if show_window {
+				window_sender
+					.send(buffer.clone())
+					.expect("could not show the buffer in the window");
+			}
+
+			let mut aov_images = vec![];
+			let mut image = match (passes, checkpoint_path) {
+				(Some(passes), _) => {
+					// Passes cover the whole region, so every pixel in it has had as many samples as there were passes
+					let (x0, y0, _, _) = region;
+					let first_pass =
+						buffer.lock().unwrap().samples[output::pixel_index(width, x0, y0)];
+					for pass in first_pass..passes {
+						// The image is written after every pass, so there is nothing left to write when stopping
+						if cancel.load(Ordering::Relaxed) {
+							break;
+						}
+						pool.install(|| {
+							let mut buffer = buffer.lock().unwrap();
+							tyray::render_pass(&scene, &camera, &settings, pass, &mut buffer)
+						});
+						info!("Rendered pass {}/{}, writing to {}", pass + 1, passes, path);
+						let buffer = buffer.lock().unwrap();
+						let alpha = if transparent_background {
+							Some(buffer.coverage.as_slice())
+						} else {
+							None
+						};
+						save_image(
+							&path,
+							output_format,
+							(width, height),
+							&buffer.pixels,
+							alpha,
+							tone_mapping,
+							gamma,
+						);
+						if let Some(checkpoint_path) = checkpoint_path {
+							output::save_buffer(checkpoint_path, &buffer)
+								.expect("could not save checkpoint");
+						}
 					}
-					.normalize();
-
-					let mut color = scene.cast_ray(
-						&Ray::new(
-							Vector {
-								x: 0.0,
-								y: 0.0,
-								z: 0.0,
+					buffer.lock().unwrap().pixels.clone()
+				}
+				(None, Some(checkpoint_path)) => {
+					let last_saved = Mutex::new(Instant::now());
+					pool.install(|| {
+						tyray::render_remaining(
+							&scene,
+							&camera,
+							&settings,
+							&buffer,
+							&cancel,
+							|buffer| {
+								let mut last_saved = last_saved.lock().unwrap();
+								if last_saved.elapsed() >= CHECKPOINT_INTERVAL {
+									output::save_buffer(checkpoint_path, buffer)
+										.expect("could not save checkpoint");
+									*last_saved = Instant::now();
+								}
 							},
-							dir,
-						),
-						max_depth,
-					);
-
-					// Scale color
-					let max = color.x.max(color.y.max(color.z));
-					if max > 1.0 {
-						color = color * (1.0 / max);
-					}
+						)
+					});
+					let buffer = buffer.lock().unwrap();
+					output::save_buffer(checkpoint_path, &buffer)
+						.expect("could not save checkpoint");
+					buffer.pixels.clone()
+				}
+				(None, None) if !aovs.is_empty() => {
+					// Components of the image are rendered along with it, as they are parts of the same samples
+					let (rendered, images) = pool
+						.install(|| tyray::render_aovs(&scene, &camera, &settings, &aovs, &cancel));
+					aov_images = images;
+					let mut buffer = buffer.lock().unwrap();
+					*buffer = rendered;
+					buffer.pixels.clone()
+				}
+				(None, None) if show_window || transparent_background => {
+					// Rendering into the shared buffer shows each tile in the window once it is done, and keeps the coverage
+					// of each pixel
+					pool.install(|| {
+						tyray::render_remaining(
+							&scene,
+							&camera,
+							&settings,
+							&buffer,
+							&cancel,
+							|_| {},
+						)
+					});
+					buffer.lock().unwrap().pixels.clone()
+				}
+				(None, None) => pool
+					.install(|| renderer.render_cancellable(&scene, &camera, &settings, &cancel)),
+			};
+
+			// A stopped render is written as far as it got (pixels that were not rendered are transparent), without denoising
+			let cancelled = cancel.load(Ordering::Relaxed);
+			if cancelled {
+				warn!("Rendering was stopped, the image is incomplete");
+			}
+			if matches.is_present("denoise") && !cancelled {
+				// Normals and albedo of the surfaces seen guide the filter, so that it does not blur across edges
+				info!("Denoising...");
+				let guides: Vec<Vec<Vector>> = [DebugMode::Normals, DebugMode::Albedo]
+					.iter()
+					.map(|&mode| {
+						let settings = RenderSettings {
+							samples: 1,
+							debug: Some(mode),
+							adaptive: None,
+							show_progress: false,
+							..settings
+						};
+						pool.install(|| renderer.render(&scene, &camera, &settings))
+					})
+					.collect();
+				let guides: Vec<&[Vector]> = guides.iter().map(|guide| guide.as_slice()).collect();
+				image = output::denoise(width, height, &image, &guides);
+			}
+
+			// Pixels are as opaque as the fraction of their samples that hit an object
+			let alpha = if transparent_background {
+				Some(buffer.lock().unwrap().coverage.clone())
+			} else {
+				None
+			};
+			info!("Rendered, writing to {}", path);
+			save_image(
+				&path,
+				output_format,
+				(width, height),
+				&image,
+				alpha.as_deref(),
+				tone_mapping,
+				gamma,
+			);
+
+			for (&aov, aov_image) in aovs.iter().zip(&aov_images) {
+				// Depth is not a color, so it is written like the depth debug visualization
+				let (tone_mapping, gamma) = match aov {
+					Aov::Depth => (ToneMapping::Clamp, 1.0),
+					_ => (tone_mapping, gamma),
+				};
+				save_image(
+					&aov_path(&path, aov),
+					output_format,
+					(width, height),
+					aov_image,
+					None,
+					tone_mapping,
+					gamma,
+				);
+			}
+
+			if cancelled {
+				break;
+			}
+		}
+
+		if let Some(statistics) = &scene.statistics {
+			println!(
+				"Cast {} in a scene of {} objects",
+				statistics,
+				scene.objects.objects().len()
+			);
+		}
 
-					(
-						x,
-						y,
-						image::Rgb([
-							(color.x * 255.0).min(255.0).max(0.0) as u8,
-							(color.y * 255.0).min(255.0).max(0.0) as u8,
-							(color.z * 255.0).min(255.0).max(0.0) as u8,
-						]),
-					)
-				})
-				.collect()
-		})
-		.collect();
-
-	println!("Rendered, writing to image...");
-
-	let mut img = ImageBuffer::new(width, height);
-
-	for row in image.iter() {
-		for pixel in row {
-			img.put_pixel(pixel.0, pixel.1, pixel.2)
+		if show_window && cfg!(feature = "window") {
+			info!("Close the window to exit");
 		}
+	};
+	if show_window {
+		thread::scope(|scope| {
+			scope.spawn(render_frames);
+			if let Ok(buffer) = window_receiver.recv() {
+				preview_in_window(&buffer, tone_mapping, gamma);
+			}
+		});
+	} else {
+		render_frames();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn gamma_brightens_mid_gray() {
+		let gray = Vector::new(0.5, 0.5, 0.5);
+		let [r, g, b] = display_color(gray, ToneMapping::Clamp, 2.2).data;
+		assert!((186..=188).contains(&r));
+		assert_eq!((r, g), (g, b));
+
+		// A gamma of 1.0 disables correction
+		let [r, _, _] = display_color(gray, ToneMapping::Clamp, 1.0).data;
+		assert_eq!(r, 127);
+	}
+
+	#[test]
+	fn coverage_is_written_as_alpha() {
+		let white = Vector::new(1.0, 1.0, 1.0);
+		assert_eq!(
+			display_pixel(white, 1.0, ToneMapping::Clamp, 1.0).data,
+			[255, 255, 255, 255]
+		);
+		assert_eq!(
+			display_pixel(white, 0.0, ToneMapping::Clamp, 1.0).data[3],
+			0
+		);
+		assert_eq!(
+			display_pixel(white, 0.5, ToneMapping::Clamp, 1.0).data[3],
+			128
+		);
+	}
+
+	#[test]
+	fn command_line_overrides_scene_file() {
+		let matches = |args: &[&str]| {
+			app().get_matches_from(
+				std::iter::once("tyray")
+					.chain(args.iter().copied())
+					.chain(std::iter::once("out.png")),
+			)
+		};
+		let given = matches(&["--width", "300"]);
+		assert_eq!(setting::<u32>(&given, "width", Some(640)), 300);
+		assert_eq!(setting::<u32>(&given, "width", None), 300);
+
+		// Without the option, the scene file value is used, and otherwise the option default
+		let defaults = matches(&[]);
+		assert_eq!(setting::<u32>(&defaults, "width", Some(640)), 640);
+		assert_eq!(setting::<u32>(&defaults, "width", None), 512);
 	}
 
-	println!("Written, writing to disk...");
-	img.save(output_path).unwrap();
+	#[test]
+	fn dumped_demo_scene_loads_as_the_same_scene() {
+		let demo = || demo_scene(None, None, WrapMode::Clamp, 1.0);
+		let json = serde_json::to_string_pretty(&demo()).expect("could not serialize scene");
+		let path = std::env::temp_dir().join(format!("tyray-dump-{}.json", std::process::id()));
+		let path = path.to_str().unwrap();
+		std::fs::write(path, &json).unwrap();
+		let loaded = description::load_scene_description(path);
+		std::fs::remove_file(path).unwrap();
+
+		// Written again, the loaded scene gives the same file
+		let loaded = loaded.expect("could not load dumped scene");
+		assert_eq!(serde_json::to_string_pretty(&loaded).unwrap(), json);
+
+		// Rays see the same colors in both scenes (without the environment map, which is slow to load)
+		let scene = |mut description: SceneDescription| {
+			description.environment_map = None;
+			description.into_scene().unwrap()
+		};
+		let (original, loaded) = (scene(demo()), scene(loaded));
+		for i in -5..=5 {
+			let direction = Vector::new(f64::from(i) * 0.15, -0.1, -1.0).normalize();
+			let ray = tyray::geometry::Ray::new(Vector::default(), direction);
+			assert_eq!(loaded.cast_ray(&ray, 4), original.cast_ray(&ray, 4));
+		}
+	}
 }
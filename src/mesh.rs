@@ -0,0 +1,205 @@
+use super::geometry::Vector;
+use super::primitives::Triangle;
+use super::scene::{Material, MaterialLibrary, Traceable};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum MeshError {
+	Io(std::io::Error),
+	Parse { line: usize, message: String },
+}
+
+impl fmt::Display for MeshError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MeshError::Io(e) => write!(f, "I/O error: {}", e),
+			MeshError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+		}
+	}
+}
+
+impl std::error::Error for MeshError {}
+
+impl From<std::io::Error> for MeshError {
+	fn from(e: std::io::Error) -> MeshError {
+		MeshError::Io(e)
+	}
+}
+
+/** Parse three floats following an element keyword (e.g. 'v 1.0 2.0 3.0'). */
+fn parse_vector<'a>(
+	mut parts: impl Iterator<Item = &'a str>,
+	line: usize,
+) -> Result<Vector, MeshError> {
+	let mut next = || -> Result<f64, MeshError> {
+		parts
+			.next()
+			.ok_or_else(|| MeshError::Parse {
+				line,
+				message: "expected three coordinates".to_string(),
+			})?
+			.parse()
+			.map_err(|e| MeshError::Parse {
+				line,
+				message: format!("invalid coordinate: {}", e),
+			})
+	};
+
+	Ok(Vector {
+		x: next()?,
+		y: next()?,
+		z: next()?,
+	})
+}
+
+/** Resolve a one-based (or negative, relative to the end) OBJ index into a zero-based index. */
+fn resolve_index(index: &str, count: usize, line: usize) -> Result<usize, MeshError> {
+	let parsed: i64 = index.parse().map_err(|e| MeshError::Parse {
+		line,
+		message: format!("invalid index: {}", e),
+	})?;
+
+	let resolved = if parsed < 0 {
+		count as i64 + parsed
+	} else {
+		parsed - 1
+	};
+
+	if resolved < 0 || resolved >= count as i64 {
+		return Err(MeshError::Parse {
+			line,
+			message: format!("index {} out of range", parsed),
+		});
+	}
+	Ok(resolved as usize)
+}
+
+/** Load the faces in a Wavefront OBJ file as triangles. Polygonal faces are triangulated as a fan around their
+first vertex. Vertex normals are kept when every vertex of a face specifies one. Faces following a `usemtl` statement
+get the material of that name from the library; faces before it, or after one naming a material that is not in the
+library, get the given default material. */
+pub fn load_obj(
+	path: &str,
+	material: Arc<Material>,
+	library: &MaterialLibrary,
+) -> Result<Vec<Arc<dyn Traceable>>, MeshError> {
+	let reader = BufReader::new(File::open(path)?);
+	let mut positions: Vec<Vector> = vec![];
+	let mut normals: Vec<Vector> = vec![];
+	let mut triangles: Vec<Arc<dyn Traceable>> = vec![];
+	let mut current_material = material.clone();
+
+	for (line_index, line) in reader.lines().enumerate() {
+		let line = line?;
+		let line_number = line_index + 1;
+		let mut parts = line.split_whitespace();
+
+		match parts.next() {
+			Some("v") => positions.push(parse_vector(parts, line_number)?),
+			Some("vn") => normals.push(parse_vector(parts, line_number)?.normalize()),
+			Some("usemtl") => {
+				current_material = parts
+					.next()
+					.and_then(|name| library.get(name))
+					.unwrap_or_else(|| material.clone());
+			}
+			Some("f") => {
+				// Each face vertex is of the form 'v', 'v/vt', 'v//vn' or 'v/vt/vn'
+				let mut vertices: Vec<(Vector, Option<Vector>)> = vec![];
+				for vertex in parts {
+					let mut indices = vertex.split('/');
+					let position = positions[resolve_index(
+						indices.next().unwrap_or(""),
+						positions.len(),
+						line_number,
+					)?];
+					let normal = match indices.nth(1) {
+						Some(n) if !n.is_empty() => {
+							Some(normals[resolve_index(n, normals.len(), line_number)?])
+						}
+						_ => None,
+					};
+					vertices.push((position, normal));
+				}
+
+				if vertices.len() < 3 {
+					return Err(MeshError::Parse {
+						line: line_number,
+						message: "face has fewer than three vertices".to_string(),
+					});
+				}
+
+				for i in 1..(vertices.len() - 1) {
+					let (a, na) = vertices[0];
+					let (b, nb) = vertices[i];
+					let (c, nc) = vertices[i + 1];
+					let normals = match (na, nb, nc) {
+						(Some(na), Some(nb), Some(nc)) => Some([na, nb, nc]),
+						_ => None,
+					};
+
+					triangles.push(Arc::new(Triangle {
+						a,
+						b,
+						c,
+						normals,
+						double_sided: true,
+						material: current_material.clone(),
+					}));
+				}
+			}
+			_ => {
+				// Ignore comments, texture coordinates, groups, material files and anything else
+			}
+		}
+	}
+
+	Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Ray;
+	use crate::scene::tests::diffuse;
+
+	#[test]
+	fn normals_are_interpolated_across_faces() {
+		// A square of two triangles, with vertex normals tilted outwards at the corners (as on a bulging surface)
+		let obj = "v -1 -1 -3\nv 1 -1 -3\nv 1 1 -3\nv -1 1 -3\n\
+			vn -0.5 -0.5 1\nvn 0.5 -0.5 1\nvn 0.5 0.5 1\nvn -0.5 0.5 1\n\
+			f 1//1 2//2 3//3\nf 1//1 3//3 4//4\n";
+		let path = std::env::temp_dir().join(format!("tyray-quad-{}.obj", std::process::id()));
+		std::fs::write(&path, obj).unwrap();
+		let material = Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0)));
+		let triangles = load_obj(
+			path.to_str().unwrap(),
+			material,
+			&MaterialLibrary::default(),
+		);
+		std::fs::remove_file(&path).unwrap();
+		let triangles = triangles.expect("could not load mesh");
+		assert_eq!(triangles.len(), 2);
+
+		let normal_at = |x: f64, y: f64| {
+			let ray = Ray::new(Vector::new(x, y, 0.0), Vector::new(0.0, 0.0, -1.0));
+			triangles
+				.iter()
+				.find_map(|triangle| triangle.intersect(&ray))
+				.expect("ray should hit the mesh")
+				.normal
+		};
+
+		// At the center (on the shared edge), the tilts of the opposite corners cancel out
+		assert!(normal_at(0.0, 0.0).distance(&Vector::new(0.0, 0.0, 1.0)) < 1e-9);
+
+		// Towards the corners, the normal tilts along with the vertex normals
+		let (lower_left, upper_right) = (normal_at(-0.9, -0.9), normal_at(0.9, 0.9));
+		assert!(lower_left.x < -0.1 && lower_left.y < -0.1);
+		assert!(upper_right.x > 0.1 && upper_right.y > 0.1);
+		assert!((upper_right.norm() - 1.0).abs() < 1e-9);
+	}
+}
@@ -0,0 +1,424 @@
+use super::geometry::Vector;
+use image::hdr::HDREncoder;
+use image::Rgb;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/** File format in which the rendered image is written. */
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+	/** Encoded by the image crate, which infers the format from the file extension */
+	Image,
+
+	/** Binary portable pixmap (P6), written directly */
+	Ppm,
+
+	/** Radiance HDR, containing the linear colors before tone mapping */
+	Hdr,
+}
+
+impl OutputFormat {
+	/** Determine the output format from the extension of the output path. */
+	pub fn from_path(path: &str) -> OutputFormat {
+		match Path::new(path).extension().and_then(|e| e.to_str()) {
+			Some(extension) if extension.eq_ignore_ascii_case("ppm") => OutputFormat::Ppm,
+			Some(extension) if extension.eq_ignore_ascii_case("hdr") => OutputFormat::Hdr,
+			_ => OutputFormat::Image,
+		}
+	}
+}
+
+impl FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<OutputFormat, String> {
+		match s {
+			"image" => Ok(OutputFormat::Image),
+			"ppm" => Ok(OutputFormat::Ppm),
+			"hdr" => Ok(OutputFormat::Hdr),
+			_ => Err(format!("unknown output format '{}'", s)),
+		}
+	}
+}
+
+/** Number of pixels in an image of the given size, or None when there are more than fit in memory. */
+pub fn pixel_count(width: u32, height: u32) -> Option<usize> {
+	usize::try_from(width)
+		.ok()?
+		.checked_mul(usize::try_from(height).ok()?)
+}
+
+/** Index of pixel (x, y) among the pixels of an image of the given width, stored row by row from the top left. */
+pub fn pixel_index(width: u32, x: u32, y: u32) -> usize {
+	y as usize * width as usize + x as usize
+}
+
+/** Write pixels (row by row, starting at the top left) as a binary PPM (P6) image. */
+pub fn write_ppm<W: Write>(
+	writer: &mut W,
+	width: u32,
+	height: u32,
+	pixels: &[Rgb<u8>],
+) -> std::io::Result<()> {
+	assert_eq!(Some(pixels.len()), pixel_count(width, height));
+	write!(writer, "P6\n{} {}\n255\n", width, height)?;
+	for pixel in pixels {
+		writer.write_all(&pixel.data)?;
+	}
+	writer.flush()
+}
+
+/** Write pixels as a binary PPM (P6) image file at the specified path. */
+pub fn save_ppm(path: &str, width: u32, height: u32, pixels: &[Rgb<u8>]) -> std::io::Result<()> {
+	let mut writer = BufWriter::new(File::create(path)?);
+	write_ppm(&mut writer, width, height, pixels)
+}
+
+/** Writes an image file row by row, starting at the top, so that the image does not need to be kept in memory as a
+whole. All rows of the image must be written before calling `finish`. */
+pub struct RowWriter {
+	encoder: RowEncoder,
+	width: u32,
+}
+
+enum RowEncoder {
+	Ppm(BufWriter<File>),
+	Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+}
+
+impl RowWriter {
+	/** Whether images at the path in the format can be written row by row: PPM files and (for the image format) PNG files. */
+	pub fn supports(path: &str, format: OutputFormat) -> bool {
+		let is_png = Path::new(path)
+			.extension()
+			.and_then(|e| e.to_str())
+			.is_some_and(|e| e.eq_ignore_ascii_case("png"));
+		format == OutputFormat::Ppm || (format == OutputFormat::Image && is_png)
+	}
+
+	/** Create an image file of the given size at the path, leaving files in formats that are not supported untouched */
+	pub fn create(
+		path: &str,
+		format: OutputFormat,
+		width: u32,
+		height: u32,
+	) -> std::io::Result<RowWriter> {
+		if !RowWriter::supports(path, format) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"only PNG and PPM images can be written row by row",
+			));
+		}
+
+		let mut writer = BufWriter::new(File::create(path)?);
+		let encoder = if format == OutputFormat::Ppm {
+			write!(writer, "P6\n{} {}\n255\n", width, height)?;
+			RowEncoder::Ppm(writer)
+		} else {
+			let mut encoder = png::Encoder::new(writer, width, height);
+			encoder.set_color(png::ColorType::Rgb);
+			encoder.set_depth(png::BitDepth::Eight);
+			let stream = encoder
+				.write_header()
+				.and_then(|writer| writer.into_stream_writer())
+				.map_err(std::io::Error::other)?;
+			RowEncoder::Png(Box::new(stream))
+		};
+		Ok(RowWriter { encoder, width })
+	}
+
+	/** Write the next row of pixels, from left to right */
+	pub fn write_row(&mut self, pixels: &[Rgb<u8>]) -> std::io::Result<()> {
+		assert_eq!(pixels.len(), self.width as usize);
+		let data: Vec<u8> = pixels.iter().flat_map(|pixel| pixel.data).collect();
+		match &mut self.encoder {
+			RowEncoder::Ppm(writer) => writer.write_all(&data),
+			RowEncoder::Png(stream) => stream.write_all(&data),
+		}
+	}
+
+	/** Finish writing the file, after all rows have been written */
+	pub fn finish(self) -> std::io::Result<()> {
+		match self.encoder {
+			RowEncoder::Ppm(mut writer) => writer.flush(),
+			RowEncoder::Png(stream) => stream.finish().map_err(std::io::Error::other),
+		}
+	}
+}
+
+/** Write linear colors (row by row, starting at the top left) as a Radiance HDR image file at the specified path. */
+pub fn save_hdr(path: &str, width: u32, height: u32, pixels: &[Vector]) -> std::io::Result<()> {
+	assert_eq!(Some(pixels.len()), pixel_count(width, height));
+	let data: Vec<Rgb<f32>> = pixels
+		.iter()
+		.map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]))
+		.collect();
+
+	HDREncoder::new(BufWriter::new(File::create(path)?)).encode(
+		&data,
+		width as usize,
+		height as usize,
+	)
+}
+
+/** Linear colors of an image being rendered (row by row, starting at the top left), with the number of samples taken
+for each pixel so far (zero for pixels that have not been rendered yet). */
+#[derive(Clone)]
+pub struct RenderBuffer {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<Vector>,
+
+	/** Fraction of the samples of each pixel that hit an object, rather than the background */
+	pub coverage: Vec<f64>,
+	pub samples: Vec<u32>,
+}
+
+impl RenderBuffer {
+	/** A buffer of the given size in which no pixels have been rendered */
+	pub fn new(width: u32, height: u32) -> RenderBuffer {
+		let size = pixel_count(width, height).expect("image is too large");
+		RenderBuffer {
+			width,
+			height,
+			pixels: vec![Vector::default(); size],
+			coverage: vec![0.0; size],
+			samples: vec![0; size],
+		}
+	}
+
+	/** Store rendered pixels, each given with its coordinates, coverage and number of samples, in any order */
+	pub fn place<I: IntoIterator<Item = (u32, u32, Vector, f64, u32)>>(&mut self, pixels: I) {
+		for (x, y, color, coverage, sample_count) in pixels {
+			let index = pixel_index(self.width, x, y);
+			self.pixels[index] = color;
+			self.coverage[index] = coverage;
+			self.samples[index] = sample_count;
+		}
+	}
+}
+
+/** Identifies files written by `save_buffer`, followed by the version of the format */
+const BUFFER_MAGIC: &[u8; 8] = b"TYRAYBUF";
+const BUFFER_VERSION: u32 = 1;
+
+/** Number of bytes in the header of a render buffer file, and of each pixel in it (see `save_buffer`) */
+const BUFFER_HEADER_BYTES: u64 = 20;
+const BUFFER_PIXEL_BYTES: u64 = 36;
+
+/** Write a render buffer to a file, so that rendering can be resumed later. The file starts with a header (the magic
+bytes, then the format version, width and height as little endian 32-bit integers), followed by each pixel as four
+little endian 64-bit floats (the color and coverage) and a 32-bit sample count. The file is replaced at once, so that an interrupted write does
+not destroy an earlier checkpoint. */
+pub fn save_buffer(path: &str, buffer: &RenderBuffer) -> std::io::Result<()> {
+	let temporary_path = format!("{}.tmp", path);
+	{
+		let mut writer = BufWriter::new(File::create(&temporary_path)?);
+		writer.write_all(BUFFER_MAGIC)?;
+		for value in &[BUFFER_VERSION, buffer.width, buffer.height] {
+			writer.write_all(&value.to_le_bytes())?;
+		}
+		let pixels = buffer.pixels.iter().zip(&buffer.coverage);
+		for ((color, coverage), samples) in pixels.zip(&buffer.samples) {
+			for component in &[color.x, color.y, color.z, *coverage] {
+				writer.write_all(&component.to_le_bytes())?;
+			}
+			writer.write_all(&samples.to_le_bytes())?;
+		}
+		writer.flush()?;
+	}
+	std::fs::rename(temporary_path, path)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+	let mut bytes = [0u8; 4];
+	reader.read_exact(&mut bytes)?;
+	Ok(u32::from_le_bytes(bytes))
+}
+
+/** Read a render buffer written by `save_buffer`. */
+pub fn load_buffer(path: &str) -> std::io::Result<RenderBuffer> {
+	let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+	let file = File::open(path)?;
+	let file_length = file.metadata()?.len();
+	let mut reader = BufReader::new(file);
+
+	let mut magic = [0u8; 8];
+	reader.read_exact(&mut magic)?;
+	if &magic != BUFFER_MAGIC {
+		return Err(invalid("not a render buffer file"));
+	}
+
+	if read_u32(&mut reader)? != BUFFER_VERSION {
+		return Err(invalid("unsupported render buffer version"));
+	}
+	let width = read_u32(&mut reader)?;
+	let height = read_u32(&mut reader)?;
+
+	// Check that the file holds as many pixels as the header says before allocating memory for them
+	let length = pixel_count(width, height)
+		.and_then(|count| u64::try_from(count).ok())
+		.and_then(|count| count.checked_mul(BUFFER_PIXEL_BYTES))
+		.and_then(|length| length.checked_add(BUFFER_HEADER_BYTES));
+	if length != Some(file_length) {
+		return Err(invalid(
+			"render buffer size does not match its width and height",
+		));
+	}
+
+	let mut buffer = RenderBuffer::new(width, height);
+	for index in 0..buffer.pixels.len() {
+		let mut values = [0.0; 4];
+		for value in values.iter_mut() {
+			let mut bytes = [0u8; 8];
+			reader.read_exact(&mut bytes)?;
+			*value = f64::from_le_bytes(bytes);
+		}
+		let [r, g, b, coverage] = values;
+		buffer.pixels[index] = Vector::new(r, g, b);
+		buffer.coverage[index] = coverage;
+		buffer.samples[index] = read_u32(&mut reader)?;
+	}
+	Ok(buffer)
+}
+
+/** Radius (in pixels) of the neighbourhood averaged by `denoise` */
+const DENOISE_RADIUS: i32 = 3;
+
+/** Standard deviation of the spatial (Gaussian) weights used by `denoise`, in pixels */
+const DENOISE_SIGMA_SPATIAL: f64 = 1.5;
+
+/** Standard deviation of the difference in guide values (normal, albedo) over which neighbours are averaged */
+const DENOISE_SIGMA_GUIDE: f64 = 0.1;
+
+/** Reduce noise in linear colors (row by row, starting at the top left) by averaging each pixel with its neighbours.
+The guide buffers hold per-pixel surface properties (such as normals and albedo); neighbours are weighed less as their
+guide values differ more, so that edges between surfaces stay sharp. */
+pub fn denoise(width: u32, height: u32, pixels: &[Vector], guides: &[&[Vector]]) -> Vec<Vector> {
+	assert_eq!(Some(pixels.len()), pixel_count(width, height));
+	let (width, height) = (width as i32, height as i32);
+	let index = |x: i32, y: i32| pixel_index(width as u32, x as u32, y as u32);
+
+	let mut denoised = Vec::with_capacity(pixels.len());
+	for y in 0..height {
+		for x in 0..width {
+			let center = index(x, y);
+			let mut sum = Vector::default();
+			let mut total_weight = 0.0;
+
+			for ny in (y - DENOISE_RADIUS).max(0)..=(y + DENOISE_RADIUS).min(height - 1) {
+				for nx in (x - DENOISE_RADIUS).max(0)..=(x + DENOISE_RADIUS).min(width - 1) {
+					let neighbour = index(nx, ny);
+					let distance_squared = f64::from((nx - x) * (nx - x) + (ny - y) * (ny - y));
+					let guide_difference: f64 = guides
+						.iter()
+						.map(|guide| guide[center].distance_squared(&guide[neighbour]))
+						.sum();
+					let weight = (-distance_squared
+						/ (2.0 * DENOISE_SIGMA_SPATIAL * DENOISE_SIGMA_SPATIAL)
+						- guide_difference / (2.0 * DENOISE_SIGMA_GUIDE * DENOISE_SIGMA_GUIDE))
+						.exp();
+					sum += pixels[neighbour] * weight;
+					total_weight += weight;
+				}
+			}
+
+			// The weight of the pixel itself is one, so the total is never zero
+			denoised.push(sum / total_weight);
+		}
+	}
+	denoised
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::rngs::SmallRng;
+	use rand::seq::SliceRandom;
+	use rand::SeedableRng;
+
+	#[test]
+	fn placed_pixels_do_not_depend_on_order() {
+		let (width, height) = (7, 5);
+		let mut pixels: Vec<(u32, u32, Vector, f64, u32)> = (0..height)
+			.flat_map(|y| (0..width).map(move |x| (x, y)))
+			.map(|(x, y)| {
+				(
+					x,
+					y,
+					Vector::new(f64::from(x), f64::from(y), 1.0),
+					f64::from(x) / 8.0,
+					x + y + 1,
+				)
+			})
+			.collect();
+
+		let mut in_order = RenderBuffer::new(width, height);
+		in_order.place(pixels.clone());
+		pixels.shuffle(&mut SmallRng::seed_from_u64(1));
+		let mut shuffled = RenderBuffer::new(width, height);
+		shuffled.place(pixels);
+
+		assert_eq!(shuffled.pixels, in_order.pixels);
+		assert_eq!(shuffled.coverage, in_order.coverage);
+		assert_eq!(shuffled.samples, in_order.samples);
+		assert_eq!(in_order.pixels[2 * 7 + 3], Vector::new(3.0, 2.0, 1.0));
+		assert_eq!(in_order.coverage[2 * 7 + 3], 0.375);
+		assert_eq!(in_order.samples[2 * 7 + 3], 6);
+	}
+
+	#[test]
+	fn buffer_dimensions_are_checked_against_file_size() {
+		let path = std::env::temp_dir().join(format!("tyray-buffer-{}.bin", std::process::id()));
+		let path = path.to_str().unwrap();
+		let mut buffer = RenderBuffer::new(3, 2);
+		buffer.place(vec![(2, 1, Vector::new(0.5, 1.0, 2.0), 0.75, 4)]);
+		save_buffer(path, &buffer).unwrap();
+		let loaded = load_buffer(path);
+
+		// A header claiming an enormous image must not make loading allocate memory for it
+		let mut data = std::fs::read(path).unwrap();
+		data[12..20].copy_from_slice(&[0xff; 8]);
+		std::fs::write(path, &data).unwrap();
+		let enormous = load_buffer(path);
+
+		// Nor may a header claiming more pixels than are in the file
+		data[12..20].copy_from_slice(&[3, 0, 0, 0, 3, 0, 0, 0]);
+		std::fs::write(path, &data).unwrap();
+		let truncated = load_buffer(path);
+		std::fs::remove_file(path).unwrap();
+
+		let loaded = loaded.expect("could not load buffer");
+		assert_eq!((loaded.width, loaded.height), (3, 2));
+		assert_eq!(loaded.pixels, buffer.pixels);
+		assert_eq!(loaded.coverage, buffer.coverage);
+		assert_eq!(loaded.samples, buffer.samples);
+		for result in [enormous, truncated] {
+			let error = result.err().expect("invalid buffer should not load");
+			assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+		}
+	}
+
+	#[test]
+	fn only_png_and_ppm_files_are_streamed() {
+		assert!(RowWriter::supports("out.png", OutputFormat::Image));
+		assert!(RowWriter::supports("out.PNG", OutputFormat::Image));
+		assert!(RowWriter::supports("out.png", OutputFormat::Ppm));
+		assert!(!RowWriter::supports("out.jpg", OutputFormat::Image));
+		assert!(!RowWriter::supports("out.png", OutputFormat::Hdr));
+
+		// An existing file that cannot be streamed is left as it is
+		let path = std::env::temp_dir().join(format!("tyray-stream-{}.hdr", std::process::id()));
+		let path = path.to_str().unwrap();
+		std::fs::write(path, b"existing").unwrap();
+		let result = RowWriter::create(path, OutputFormat::Hdr, 4, 4);
+		let contents = std::fs::read(path).unwrap();
+		std::fs::remove_file(path).unwrap();
+		let error = result.err().expect("HDR files should not be streamed");
+		assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+		assert_eq!(contents, b"existing");
+	}
+}
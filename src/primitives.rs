@@ -1,25 +1,190 @@
-use super::geometry::{Ray, Vector};
-use super::scene::{Material, Traceable};
+use super::geometry::{Aabb, BoundingSphere, Matrix4, Ray, Vector};
+use super::scene::{
+	check_direction, check_finite, check_positive, GeometryError, Hit, Material, Traceable,
+};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Sphere {
 	pub center: Vector,
 	pub radius: f64,
 	pub material: Arc<Material>,
 }
 
+/** An infinite plane through a point, facing the direction of its normal. */
+#[derive(Deserialize, Serialize)]
 pub struct Plane {
+	pub point: Vector,
+	pub normal: Vector,
+
+	/** Whether the normal faces rays hitting either side (otherwise it always faces the front) */
+	#[serde(default)]
+	pub double_sided: bool,
+	pub material: Arc<Material>,
+}
+
+/** A rectangle in the horizontal plane at height y, facing upwards. */
+#[derive(Deserialize, Serialize)]
+pub struct Rectangle {
 	pub y: f64,
 	pub x_min: f64,
 	pub x_max: f64,
 	pub z_min: f64,
 	pub z_max: f64,
+
+	/** Whether the normal faces rays hitting either side (otherwise it always faces the front) */
+	#[serde(default)]
+	pub double_sided: bool,
+	pub material: Arc<Material>,
+}
+
+/** A parallelogram spanned by the edges u and v from the origin corner, facing the direction of u x v. Texture
+coordinates run from (0, 0) at the origin to (1, 1) at the opposite corner. */
+#[derive(Deserialize, Serialize)]
+pub struct Quad {
+	pub origin: Vector,
+	pub u: Vector,
+	pub v: Vector,
+
+	/** Whether the normal faces rays hitting either side (otherwise it always faces the front) */
+	#[serde(default)]
+	pub double_sided: bool,
+	pub material: Arc<Material>,
+}
+
+/** A triangle defined by three vertices. The front face is the side from which the vertices appear in
+counter-clockwise order. Unless `double_sided` is set, rays hitting the back face do not intersect. */
+#[derive(Deserialize, Serialize)]
+pub struct Triangle {
+	pub a: Vector,
+	pub b: Vector,
+	pub c: Vector,
+
+	/** Vertex normals for a, b and c (when loaded from a mesh), interpolated across the triangle for smooth shading */
+	#[serde(default)]
+	pub normals: Option<[Vector; 3]>,
+
+	#[serde(default)]
+	pub double_sided: bool,
+	pub material: Arc<Material>,
+}
+
+/** A solid box with faces perpendicular to the axes, spanning from min to max. */
+#[derive(Deserialize, Serialize)]
+pub struct AxisAlignedBox {
+	pub min: Vector,
+	pub max: Vector,
+	pub material: Arc<Material>,
+}
+
+/** A cylinder of the given radius, extending from the center of its base along the axis for the given height. */
+#[derive(Deserialize, Serialize)]
+pub struct Cylinder {
+	pub base: Vector,
+	pub axis: Vector,
+	pub radius: f64,
+	pub height: f64,
+
+	/** Whether the cylinder is open on both ends (without caps) */
+	#[serde(default)]
+	pub open: bool,
+	pub material: Arc<Material>,
+}
+
+/** A cone with its tip at the apex, widening along the axis by the half angle (in radians) up to the given height. */
+#[derive(Deserialize, Serialize)]
+pub struct Cone {
+	pub apex: Vector,
+	pub axis: Vector,
+	pub half_angle: f64,
+	pub height: f64,
+
+	/** Whether the cone is open at the base (without cap) */
+	#[serde(default)]
+	pub open: bool,
+	pub material: Arc<Material>,
+}
+
+/** A torus (ring) around the axis through its center, with the given radius of the ring and of its tube. */
+#[derive(Deserialize, Serialize)]
+pub struct Torus {
+	pub center: Vector,
+	pub axis: Vector,
+	pub major_radius: f64,
+	pub minor_radius: f64,
+	pub material: Arc<Material>,
+}
+
+/** A flat disk of the given radius around a center point, perpendicular to its normal. */
+#[derive(Deserialize, Serialize)]
+pub struct Disk {
+	pub center: Vector,
+	pub normal: Vector,
+	pub radius: f64,
 	pub material: Arc<Material>,
 }
 
 impl Traceable for Plane {
-	fn intersect(&self, ray: &Ray) -> Option<f64> {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		let normal = self.normal.normalize();
+		let denominator = normal ^ ray.direction();
+
+		// Ray is parallel to the plane
+		if denominator.abs() < 1e-12 {
+			return None;
+		}
+
+		let d = ((self.point - ray.origin()) ^ normal) / denominator;
+		if d <= 0.0 {
+			return None;
+		}
+
+		let hit = Hit {
+			distance: d,
+			normal,
+			uv: (0.0, 0.0),
+			tangents: None,
+			material: self.material.clone(),
+			object_id: None,
+		};
+		Some(if self.double_sided {
+			hit.facing(ray.direction())
+		} else {
+			hit
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		Aabb {
+			min: Vector {
+				x: f64::NEG_INFINITY,
+				y: f64::NEG_INFINITY,
+				z: f64::NEG_INFINITY,
+			},
+			max: Vector {
+				x: f64::INFINITY,
+				y: f64::INFINITY,
+				z: f64::INFINITY,
+			},
+		}
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere {
+			center: self.point,
+			radius: f64::INFINITY,
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.point, self.normal], &[])?;
+		check_direction(self.normal)
+	}
+}
+
+impl Traceable for Rectangle {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
 		let d = -(ray.origin().y - self.y) / ray.direction().y;
 
 		if d <= 0.0 {
@@ -28,54 +193,1544 @@ impl Traceable for Plane {
 
 		let pt = ray.extend(d);
 		if pt.x >= self.x_min && pt.x <= self.x_max && pt.z >= self.z_min && pt.z <= self.z_max {
-			return Some(d);
+			let hit = Hit {
+				distance: d,
+				normal: Vector {
+					x: 0.0,
+					y: 1.0,
+					z: 0.0,
+				},
+				uv: (
+					(pt.x - self.x_min) / (self.x_max - self.x_min),
+					(pt.z - self.z_min) / (self.z_max - self.z_min),
+				),
+				tangents: Some((Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0))),
+				material: self.material.clone(),
+				object_id: None,
+			};
+			return Some(if self.double_sided {
+				hit.facing(ray.direction())
+			} else {
+				hit
+			});
 		}
 
 		None
 	}
 
-	fn material(&self) -> Arc<Material> {
-		self.material.clone()
+	fn aabb(&self) -> Aabb {
+		Aabb {
+			min: Vector {
+				x: self.x_min,
+				y: self.y,
+				z: self.z_min,
+			},
+			max: Vector {
+				x: self.x_max,
+				y: self.y,
+				z: self.z_max,
+			},
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(
+			&[],
+			&[self.y, self.x_min, self.x_max, self.z_min, self.z_max],
+		)?;
+		check_positive(self.x_max - self.x_min)?;
+		check_positive(self.z_max - self.z_min)
+	}
+}
+
+impl Traceable for Quad {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		let n = self.u.cross(&self.v);
+		let denominator = n ^ ray.direction();
+
+		// Ray is parallel to the quad
+		if denominator.abs() < 1e-12 {
+			return None;
+		}
+
+		let d = ((self.origin - ray.origin()) ^ n) / denominator;
+		if d <= 0.0 {
+			return None;
+		}
+
+		// Coordinates of the hit point along the edges
+		let p = ray.extend(d) - self.origin;
+		let w = n / n.norm_squared();
+		let alpha = w ^ p.cross(&self.v);
+		let beta = w ^ self.u.cross(&p);
+		if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+			return None;
+		}
+
+		let hit = Hit {
+			distance: d,
+			normal: n.normalize(),
+			uv: (alpha, beta),
+			tangents: Some((self.u.normalize(), self.v.normalize())),
+			material: self.material.clone(),
+			object_id: None,
+		};
+		Some(if self.double_sided {
+			hit.facing(ray.direction())
+		} else {
+			hit
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		let corners = [
+			self.origin + self.u,
+			self.origin + self.v,
+			self.origin + self.u + self.v,
+		];
+		corners.iter().fold(
+			Aabb {
+				min: self.origin,
+				max: self.origin,
+			},
+			|bounds, &corner| {
+				bounds.union(&Aabb {
+					min: corner,
+					max: corner,
+				})
+			},
+		)
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.origin, self.u, self.v], &[])?;
+		check_direction(self.u.cross(&self.v))
+	}
+}
+
+impl Sphere {
+	/** Hit at the specified distance along the ray, which should lie on the sphere */
+	fn hit_at(&self, ray: &Ray, distance: f64) -> Hit {
+		let normal = (ray.extend(distance) - self.center).normalize();
+
+		// Spherical coordinates of the normal: longitude around the Y axis and latitude from the top
+		let u = 0.5 + normal.z.atan2(normal.x) / (2.0 * std::f64::consts::PI);
+		let v = 0.5 - normal.y.clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+
+		// The tangent follows the longitude, the bitangent points downwards (towards increasing v)
+		let tangent = Vector::new(-normal.z, 0.0, normal.x);
+		let tangents = if tangent.norm_squared() > 1e-12 {
+			let tangent = tangent.normalize();
+			Some((tangent, normal.cross(&tangent)))
+		} else {
+			// At the poles the longitude is undefined
+			None
+		};
+
+		Hit {
+			distance,
+			normal,
+			uv: (u, v),
+			tangents,
+			material: self.material.clone(),
+			object_id: None,
+		}
 	}
 
-	fn normal_at(&self, _point: &Vector) -> Vector {
-		Vector {
-			x: 0.0,
-			y: 1.0,
-			z: 0.0,
+	/** Distances at which the (infinite) line along the ray enters and exits the sphere */
+	fn crossings(&self, ray: &Ray) -> Option<(f64, f64)> {
+		let l = self.center - ray.origin();
+		let tca = l ^ ray.direction();
+		let d2 = l.norm_squared() - tca * tca;
+		let r2 = self.radius * self.radius;
+		if d2 > r2 {
+			return None;
 		}
+		let thc = (r2 - d2).sqrt();
+		Some((tca - thc, tca + thc))
 	}
 }
 
 impl Traceable for Sphere {
-	fn intersect(&self, ray: &Ray) -> Option<f64> {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
 		let l = self.center - ray.origin();
 		let tca = l ^ ray.direction();
-		let d2 = l.dot(&l) - tca * tca;
+		let l2 = l.norm_squared();
+		let r2 = self.radius * self.radius;
+
+		// A sphere behind a ray starting outside of it cannot be hit
+		if tca < 0.0 && l2 > r2 {
+			return None;
+		}
 
-		if d2 > self.radius {
+		// Squared distance between the center and the ray; when larger than the radius squared, the ray passes by
+		let d2 = l2 - tca * tca;
+		if d2 > r2 {
+			return None;
+		}
+
+		let thc = (r2 - d2).sqrt();
+		let mut t0 = tca - thc;
+		let t1 = tca + thc;
+
+		if t0 < 0.0 {
+			t0 = t1
+		}
+		if t0 < 0.0 {
+			return None;
+		}
+
+		Some(self.hit_at(ray, t0))
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		Some(match self.crossings(ray) {
+			Some((t0, t1)) if t1 >= 0.0 => vec![(self.hit_at(ray, t0), self.hit_at(ray, t1))],
+			_ => vec![],
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		let r = Vector {
+			x: self.radius,
+			y: self.radius,
+			z: self.radius,
+		};
+		Aabb {
+			min: self.center - r,
+			max: self.center + r,
+		}
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere {
+			center: self.center,
+			radius: self.radius,
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.center], &[self.radius])?;
+		check_positive(self.radius)
+	}
+}
+
+impl Traceable for Triangle {
+	/** Möller–Trumbore ray-triangle intersection */
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		const EPSILON: f64 = 1e-9;
+		let e1 = self.b - self.a;
+		let e2 = self.c - self.a;
+		let p = ray.direction().cross(&e2);
+		let det = e1 ^ p;
+
+		// A negative determinant means the ray hits the back face, zero means it is parallel to the triangle
+		if (self.double_sided && det.abs() < EPSILON) || (!self.double_sided && det < EPSILON) {
+			return None;
+		}
+
+		let inv_det = 1.0 / det;
+		let s = ray.origin() - self.a;
+		let u = (s ^ p) * inv_det;
+		if !(0.0..=1.0).contains(&u) {
+			return None;
+		}
+
+		let q = s.cross(&e1);
+		let v = (ray.direction() ^ q) * inv_det;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
+
+		let t = (e2 ^ q) * inv_det;
+		if t > EPSILON {
+			// Barycentric interpolation of the vertex normals, otherwise the flat face normal
+			let normal = match &self.normals {
+				Some([na, nb, nc]) => (*na * (1.0 - u - v) + *nb * u + *nc * v).normalize(),
+				None => e1.cross(&e2).normalize(),
+			};
+
+			Some(Hit {
+				distance: t,
+				normal,
+				uv: (u, v),
+				tangents: None,
+				material: self.material.clone(),
+				object_id: None,
+			})
+		} else {
 			None
+		}
+	}
+
+	fn aabb(&self) -> Aabb {
+		Aabb {
+			min: Vector {
+				x: self.a.x.min(self.b.x.min(self.c.x)),
+				y: self.a.y.min(self.b.y.min(self.c.y)),
+				z: self.a.z.min(self.b.z.min(self.c.z)),
+			},
+			max: Vector {
+				x: self.a.x.max(self.b.x.max(self.c.x)),
+				y: self.a.y.max(self.b.y.max(self.c.y)),
+				z: self.a.z.max(self.b.z.max(self.c.z)),
+			},
+		}
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		let center = (self.a + self.b + self.c) / 3.0;
+		BoundingSphere {
+			center,
+			radius: self
+				.a
+				.distance(&center)
+				.max(self.b.distance(&center))
+				.max(self.c.distance(&center)),
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.a, self.b, self.c], &[])?;
+		if let Some(normals) = &self.normals {
+			check_finite(normals, &[])?;
+		}
+		// A triangle without area has no normal
+		check_direction((self.b - self.a).cross(&(self.c - self.a)))
+	}
+}
+
+impl AxisAlignedBox {
+	/** Hit at the specified distance along the ray, which should lie on a face of the box */
+	fn hit_at(&self, ray: &Ray, distance: f64) -> Hit {
+		Hit {
+			distance,
+			normal: self.normal_at(&ray.extend(distance)),
+			uv: (0.0, 0.0),
+			tangents: None,
+			material: self.material.clone(),
+			object_id: None,
+		}
+	}
+
+	/** Normal of the face on which the point lies */
+	fn normal_at(&self, point: &Vector) -> Vector {
+		// The point lies on the face it is closest to
+		let faces = [
+			(
+				(point.x - self.min.x).abs(),
+				Vector {
+					x: -1.0,
+					y: 0.0,
+					z: 0.0,
+				},
+			),
+			(
+				(point.x - self.max.x).abs(),
+				Vector {
+					x: 1.0,
+					y: 0.0,
+					z: 0.0,
+				},
+			),
+			(
+				(point.y - self.min.y).abs(),
+				Vector {
+					x: 0.0,
+					y: -1.0,
+					z: 0.0,
+				},
+			),
+			(
+				(point.y - self.max.y).abs(),
+				Vector {
+					x: 0.0,
+					y: 1.0,
+					z: 0.0,
+				},
+			),
+			(
+				(point.z - self.min.z).abs(),
+				Vector {
+					x: 0.0,
+					y: 0.0,
+					z: -1.0,
+				},
+			),
+			(
+				(point.z - self.max.z).abs(),
+				Vector {
+					x: 0.0,
+					y: 0.0,
+					z: 1.0,
+				},
+			),
+		];
+
+		faces
+			.iter()
+			.fold(
+				faces[0],
+				|closest, face| if face.0 < closest.0 { *face } else { closest },
+			)
+			.1
+	}
+}
+
+impl Traceable for AxisAlignedBox {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		let (t_near, t_far) = self.aabb().intersect(ray)?;
+
+		// When the ray starts inside the box, it hits the face through which it exits
+		let distance = if t_near > 0.0 {
+			t_near
+		} else if t_far > 0.0 {
+			t_far
 		} else {
-			let thc = ((self.radius * self.radius) - d2).sqrt();
-			let mut t0 = tca - thc;
-			let t1 = tca + thc;
+			return None;
+		};
+
+		Some(self.hit_at(ray, distance))
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		Some(match self.aabb().intersect(ray) {
+			Some((t_near, t_far)) => vec![(self.hit_at(ray, t_near), self.hit_at(ray, t_far))],
+			None => vec![],
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		Aabb {
+			min: self.min,
+			max: self.max,
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.min, self.max], &[])?;
+		check_positive(self.max.x - self.min.x)?;
+		check_positive(self.max.y - self.min.y)?;
+		check_positive(self.max.z - self.min.z)
+	}
+}
+
+impl Traceable for Cylinder {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		const EPSILON: f64 = 1e-9;
+		let axis = self.axis.normalize();
+		let origin = ray.origin() - self.base;
+		let direction = ray.direction();
 
-			if t0 < 0.0 {
-				t0 = t1
+		// Side: solve the quadratic for the infinite cylinder, using the components perpendicular to the axis
+		let d_perp = direction - axis * (direction ^ axis);
+		let o_perp = origin - axis * (origin ^ axis);
+		let a = d_perp.norm_squared();
+		let b = 2.0 * (d_perp ^ o_perp);
+		let c = o_perp.norm_squared() - self.radius * self.radius;
+		let discriminant = b * b - 4.0 * a * c;
+
+		let mut nearest: Option<(f64, Vector)> = None;
+		let mut consider = |t: f64, normal: Vector| {
+			if t > EPSILON && nearest.is_none_or(|(n, _)| t < n) {
+				nearest = Some((t, normal));
 			}
-			if t0 < 0.0 {
-				return None;
+		};
+
+		if a > EPSILON && discriminant >= 0.0 {
+			let root = discriminant.sqrt();
+			for t in &[(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)] {
+				let p = origin + direction * *t;
+				let h = p ^ axis;
+				if h >= 0.0 && h <= self.height {
+					consider(*t, (p - axis * h).normalize());
+				}
 			}
+		}
 
-			Some(t0)
+		// Caps
+		let denominator = direction ^ axis;
+		if !self.open && denominator.abs() > EPSILON {
+			for (cap_height, normal) in &[(0.0, -axis), (self.height, axis)] {
+				let t = (cap_height - (origin ^ axis)) / denominator;
+				let p = origin + direction * t - axis * *cap_height;
+				if p.norm_squared() <= self.radius * self.radius {
+					consider(t, *normal);
+				}
+			}
 		}
+
+		let (distance, normal) = nearest?;
+		Some(Hit {
+			distance,
+			normal,
+			uv: (0.0, 0.0),
+			tangents: None,
+			material: self.material.clone(),
+			object_id: None,
+		})
 	}
 
-	fn material(&self) -> Arc<Material> {
-		self.material.clone()
+	fn aabb(&self) -> Aabb {
+		let axis = self.axis.normalize();
+		let top = self.base + axis * self.height;
+
+		// Extent of the cap disks along each axis
+		let extent = Vector {
+			x: self.radius * (1.0 - axis.x * axis.x).max(0.0).sqrt(),
+			y: self.radius * (1.0 - axis.y * axis.y).max(0.0).sqrt(),
+			z: self.radius * (1.0 - axis.z * axis.z).max(0.0).sqrt(),
+		};
+
+		Aabb {
+			min: self.base - extent,
+			max: self.base + extent,
+		}
+		.union(&Aabb {
+			min: top - extent,
+			max: top + extent,
+		})
 	}
 
-	fn normal_at(&self, point: &Vector) -> Vector {
-		(*point - self.center).normalize()
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere {
+			center: self.base + self.axis.normalize() * (self.height / 2.0),
+			radius: (self.radius * self.radius + self.height * self.height / 4.0).sqrt(),
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.base, self.axis], &[self.radius, self.height])?;
+		check_direction(self.axis)?;
+		check_positive(self.radius)?;
+		check_positive(self.height)
+	}
+}
+
+impl Traceable for Cone {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		const EPSILON: f64 = 1e-9;
+		let axis = self.axis.normalize();
+		let origin = ray.origin() - self.apex;
+		let direction = ray.direction();
+		let cos2 = self.half_angle.cos().powi(2);
+
+		// Side: solve the quadratic for the infinite double cone (p . axis)^2 = cos^2 |p|^2
+		let da = direction ^ axis;
+		let oa = origin ^ axis;
+		let a = da * da - cos2;
+		let b = 2.0 * (da * oa - cos2 * (direction ^ origin));
+		let c = oa * oa - cos2 * origin.norm_squared();
+
+		let mut nearest: Option<(f64, Vector)> = None;
+		let mut consider = |t: f64, normal: Vector| {
+			if t > EPSILON && nearest.is_none_or(|(n, _)| t < n) {
+				nearest = Some((t, normal));
+			}
+		};
+
+		let discriminant = b * b - 4.0 * a * c;
+		if a.abs() > EPSILON && discriminant >= 0.0 {
+			let root = discriminant.sqrt();
+			for t in &[(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)] {
+				let p = origin + direction * *t;
+				let h = p ^ axis;
+
+				// Only the half of the double cone along the axis, up to the height
+				if h > EPSILON && h <= self.height {
+					consider(*t, (p * cos2 - axis * h).normalize());
+				}
+			}
+		}
+
+		// Rays through the tip itself touch the side only at a single point, which rounding errors easily miss
+		let t_apex = -(origin ^ direction);
+		if (origin + direction * t_apex).norm_squared() < EPSILON * EPSILON {
+			consider(t_apex, -axis);
+		}
+
+		// Base cap
+		let radius = self.height * self.half_angle.tan();
+		if !self.open && da.abs() > EPSILON {
+			let t = (self.height - oa) / da;
+			let p = origin + direction * t - axis * self.height;
+			if p.norm_squared() <= radius * radius {
+				consider(t, axis);
+			}
+		}
+
+		let (distance, normal) = nearest?;
+		Some(Hit {
+			distance,
+			normal,
+			uv: (0.0, 0.0),
+			tangents: None,
+			material: self.material.clone(),
+			object_id: None,
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		let axis = self.axis.normalize();
+		let base = self.apex + axis * self.height;
+		let radius = self.height * self.half_angle.tan();
+
+		// Extent of the base disk along each axis
+		let extent = Vector::new(
+			radius * (1.0 - axis.x * axis.x).max(0.0).sqrt(),
+			radius * (1.0 - axis.y * axis.y).max(0.0).sqrt(),
+			radius * (1.0 - axis.z * axis.z).max(0.0).sqrt(),
+		);
+
+		Aabb {
+			min: base - extent,
+			max: base + extent,
+		}
+		.union(&Aabb {
+			min: self.apex,
+			max: self.apex,
+		})
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.apex, self.axis], &[self.half_angle, self.height])?;
+		check_direction(self.axis)?;
+		check_positive(self.half_angle)?;
+		check_positive(self.height)
+	}
+}
+
+impl Torus {
+	/** Analytic normal at a point on the surface: away from the nearest point on the circle through the tube */
+	fn normal_at(&self, point: Vector) -> Vector {
+		let axis = self.axis.normalize();
+		let p = point - self.center;
+		let radial = p - axis * (p ^ axis);
+		(p - radial.normalize() * self.major_radius).normalize()
+	}
+
+	/** Implicit function of the torus for a point relative to its center: negative inside, positive outside */
+	fn implicit(&self, p: Vector, axis: Vector) -> f64 {
+		let r2 = self.major_radius * self.major_radius;
+		let h = p ^ axis;
+		let k = p.norm_squared() + r2 - self.minor_radius * self.minor_radius;
+		k * k - 4.0 * r2 * (p.norm_squared() - h * h)
+	}
+}
+
+impl Traceable for Torus {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		const EPSILON: f64 = 1e-9;
+		let axis = self.axis.normalize();
+		let origin = ray.origin() - self.center;
+		let direction = ray.direction();
+
+		// Only search the part of the ray inside the bounding sphere
+		let bound = self.major_radius + self.minor_radius;
+		let tca = -(origin ^ direction);
+		let d2 = origin.norm_squared() - tca * tca;
+		if d2 > bound * bound {
+			return None;
+		}
+		let thc = (bound * bound - d2).sqrt();
+		let (t_start, t_end) = ((tca - thc).max(EPSILON), tca + thc);
+		if t_end <= t_start {
+			return None;
+		}
+
+		// Step along the ray in increments small enough not to skip over the tube, until the sign changes. The step
+		// must not grow with the size of the ring, or thin rings are missed.
+		let step = self.minor_radius / 4.0;
+		let f = |t: f64| self.implicit(origin + direction * t, axis);
+		let mut t0 = t_start;
+		let mut f0 = f(t0);
+		while t0 < t_end {
+			let t1 = (t0 + step).min(t_end);
+			let f1 = f(t1);
+			if f0.signum() != f1.signum() {
+				// Refine the root by bisection
+				let (mut lo, mut hi, f_lo) = (t0, t1, f0);
+				for _ in 0..64 {
+					let mid = 0.5 * (lo + hi);
+					if f(mid).signum() == f_lo.signum() {
+						lo = mid;
+					} else {
+						hi = mid;
+					}
+				}
+				let distance = 0.5 * (lo + hi);
+				return Some(Hit {
+					distance,
+					normal: self.normal_at(ray.extend(distance)),
+					uv: (0.0, 0.0),
+					tangents: None,
+					material: self.material.clone(),
+					object_id: None,
+				});
+			}
+			t0 = t1;
+			f0 = f1;
+		}
+		None
+	}
+
+	fn aabb(&self) -> Aabb {
+		let axis = self.axis.normalize();
+		let extent = Vector::new(
+			self.major_radius * (1.0 - axis.x * axis.x).max(0.0).sqrt() + self.minor_radius,
+			self.major_radius * (1.0 - axis.y * axis.y).max(0.0).sqrt() + self.minor_radius,
+			self.major_radius * (1.0 - axis.z * axis.z).max(0.0).sqrt() + self.minor_radius,
+		);
+		Aabb {
+			min: self.center - extent,
+			max: self.center + extent,
+		}
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere {
+			center: self.center,
+			radius: self.major_radius + self.minor_radius,
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(
+			&[self.center, self.axis],
+			&[self.major_radius, self.minor_radius],
+		)?;
+		check_direction(self.axis)?;
+		check_positive(self.major_radius)?;
+		check_positive(self.minor_radius)
+	}
+}
+
+impl Traceable for Disk {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		let normal = self.normal.normalize();
+		let denominator = normal ^ ray.direction();
+
+		// Ray is parallel to the disk
+		if denominator.abs() < 1e-12 {
+			return None;
+		}
+
+		let d = ((self.center - ray.origin()) ^ normal) / denominator;
+		if d <= 0.0 {
+			return None;
+		}
+
+		let offset = ray.extend(d) - self.center;
+		if offset.norm_squared() > self.radius * self.radius {
+			return None;
+		}
+
+		// The disk has no thickness, so it always faces the ray
+		Some(Hit {
+			distance: d,
+			normal: if denominator > 0.0 { -normal } else { normal },
+			uv: (0.0, 0.0),
+			tangents: None,
+			material: self.material.clone(),
+			object_id: None,
+		})
+	}
+
+	fn aabb(&self) -> Aabb {
+		let normal = self.normal.normalize();
+		let extent = Vector::new(
+			self.radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+			self.radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+			self.radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+		);
+
+		Aabb {
+			min: self.center - extent,
+			max: self.center + extent,
+		}
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere {
+			center: self.center,
+			radius: self.radius,
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.center, self.normal], &[self.radius])?;
+		check_direction(self.normal)?;
+		check_positive(self.radius)
+	}
+}
+
+/** An object placed in the scene using a transformation from its own (local) space to world space. */
+pub struct Transformed<T: Traceable + ?Sized> {
+	object: Arc<T>,
+	transform: Matrix4,
+	inverse: Matrix4,
+}
+
+impl<T: Traceable + ?Sized> Transformed<T> {
+	/** Wrap the object, transforming it with the specified matrix. Returns None when the matrix is not invertible. */
+	pub fn new(object: Arc<T>, transform: Matrix4) -> Option<Transformed<T>> {
+		Some(Transformed {
+			object,
+			transform,
+			inverse: transform.inverse()?,
+		})
+	}
+
+	/** The ray in the coordinates of the object */
+	fn local_ray(&self, ray: &Ray) -> Ray {
+		Ray::new(
+			self.inverse.transform_point(ray.origin()),
+			self.inverse.transform_vector(ray.direction()),
+		)
+		.at_time(ray.time())
+	}
+
+	/** A hit on the object along the local ray, transformed back to world space */
+	fn world_hit(&self, ray: &Ray, local_ray: &Ray, local_hit: Hit) -> Hit {
+		// Distances are not preserved under scaling, so measure the distance to the hit point in world space
+		let point = self
+			.transform
+			.transform_point(local_ray.extend(local_hit.distance));
+		let distance = point.distance(&ray.origin());
+
+		Hit {
+			distance: if local_hit.distance < 0.0 {
+				-distance
+			} else {
+				distance
+			},
+			// Normals transform with the inverse transpose of the transformation
+			normal: self
+				.inverse
+				.transpose()
+				.transform_vector(local_hit.normal)
+				.normalize(),
+			tangents: local_hit.tangents.map(|(tangent, bitangent)| {
+				(
+					self.transform.transform_vector(tangent).normalize(),
+					self.transform.transform_vector(bitangent).normalize(),
+				)
+			}),
+			..local_hit
+		}
+	}
+}
+
+impl<T: Traceable + ?Sized> Traceable for Transformed<T> {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		let local_ray = self.local_ray(ray);
+		let local_hit = self.object.intersect(&local_ray)?;
+		Some(self.world_hit(ray, &local_ray, local_hit))
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		let local_ray = self.local_ray(ray);
+		let intervals = self.object.intersect_intervals(&local_ray)?;
+		Some(
+			intervals
+				.into_iter()
+				.map(|(entry, exit)| {
+					(
+						self.world_hit(ray, &local_ray, entry),
+						self.world_hit(ray, &local_ray, exit),
+					)
+				})
+				.collect(),
+		)
+	}
+
+	fn visible(&self) -> bool {
+		self.object.visible()
+	}
+
+	fn casts_shadows(&self) -> bool {
+		self.object.casts_shadows()
+	}
+
+	fn aabb(&self) -> Aabb {
+		let local = self.object.aabb();
+		let corners: Vec<Vector> = (0..8)
+			.map(|i| {
+				self.transform.transform_point(Vector {
+					x: if i & 1 == 0 { local.min.x } else { local.max.x },
+					y: if i & 2 == 0 { local.min.y } else { local.max.y },
+					z: if i & 4 == 0 { local.min.z } else { local.max.z },
+				})
+			})
+			.collect();
+
+		corners.iter().fold(
+			Aabb {
+				min: corners[0],
+				max: corners[0],
+			},
+			|bounds, &corner| {
+				bounds.union(&Aabb {
+					min: corner,
+					max: corner,
+				})
+			},
+		)
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		self.object.validate()
+	}
+}
+
+/** Boolean operation combining the volumes of two solid objects. */
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsgOperation {
+	/** Inside either object */
+	Union,
+
+	/** Inside both objects */
+	Intersection,
+
+	/** Inside the first object, but not inside the second */
+	Difference,
+}
+
+impl CsgOperation {
+	fn contains(self, in_a: bool, in_b: bool) -> bool {
+		match self {
+			CsgOperation::Union => in_a || in_b,
+			CsgOperation::Intersection => in_a && in_b,
+			CsgOperation::Difference => in_a && !in_b,
+		}
+	}
+}
+
+/** Constructive solid geometry: a solid made by combining two solid objects (objects that report the intervals along a
+ray that lie inside them, such as spheres, boxes and other CSG objects). An object that is not solid counts as empty.
+Surfaces cut out of the first object by the second object of a difference get the material of the second object. */
+pub struct Csg {
+	pub a: Arc<dyn Traceable>,
+	pub b: Arc<dyn Traceable>,
+	pub operation: CsgOperation,
+}
+
+impl Traceable for Csg {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		// Nearest boundary in front of the ray; the exit of the interval when the ray starts inside the solid
+		const EPSILON: f64 = 1e-9;
+		self.intersect_intervals(ray)?
+			.into_iter()
+			.flat_map(|(entry, exit)| [entry, exit])
+			.find(|hit| hit.distance > EPSILON)
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		let a = self.a.intersect_intervals(ray).unwrap_or_default();
+		let b = self.b.intersect_intervals(ray).unwrap_or_default();
+
+		// Walk through the boundaries of both objects along the ray, keeping track of which objects the line is inside
+		let mut boundaries: Vec<(Hit, bool, bool)> = vec![];
+		for (from_a, intervals) in [(true, a), (false, b)] {
+			for (entry, exit) in intervals {
+				boundaries.push((entry, from_a, true));
+				boundaries.push((exit, from_a, false));
+			}
+		}
+		boundaries.sort_by(|x, y| x.0.distance.total_cmp(&y.0.distance));
+
+		let (mut in_a, mut in_b) = (false, false);
+		let mut entry: Option<Hit> = None;
+		let mut intervals = vec![];
+		for (mut hit, from_a, entering) in boundaries {
+			if from_a {
+				in_a = entering;
+			} else {
+				in_b = entering;
+				if let CsgOperation::Difference = self.operation {
+					// The inside of the second object is the outside of the result
+					hit.normal = -hit.normal;
+					hit.tangents = hit
+						.tangents
+						.map(|(tangent, bitangent)| (tangent, -bitangent));
+				}
+			}
+
+			let inside = self.operation.contains(in_a, in_b);
+			if inside && entry.is_none() {
+				entry = Some(hit);
+			} else if !inside {
+				if let Some(entry) = entry.take() {
+					intervals.push((entry, hit));
+				}
+			}
+		}
+		Some(intervals)
+	}
+
+	fn aabb(&self) -> Aabb {
+		match self.operation {
+			CsgOperation::Union => self.a.aabb().union(&self.b.aabb()),
+			CsgOperation::Intersection | CsgOperation::Difference => self.a.aabb(),
+		}
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		self.a.validate()?;
+		self.b.validate()
+	}
+}
+
+/** An object moving in a straight line at constant speed: at time t during the frame (see `Ray::time`) it is moved by
+velocity * t from its position. */
+pub struct Moving {
+	pub object: Arc<dyn Traceable>,
+	pub velocity: Vector,
+}
+
+impl Moving {
+	/** The ray relative to the object: moving the ray back is the same as moving the object forward */
+	fn relative_ray(&self, ray: &Ray) -> Ray {
+		Ray::new(ray.origin() - self.velocity * ray.time(), ray.direction()).at_time(ray.time())
+	}
+}
+
+impl Traceable for Moving {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		self.object.intersect(&self.relative_ray(ray))
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		self.object.intersect_intervals(&self.relative_ray(ray))
+	}
+
+	/** The box swept by the object during the frame */
+	fn aabb(&self) -> Aabb {
+		let start = self.object.aabb();
+		let end = Aabb {
+			min: start.min + self.velocity,
+			max: start.max + self.velocity,
+		};
+		start.union(&end)
+	}
+
+	fn visible(&self) -> bool {
+		self.object.visible()
+	}
+
+	fn casts_shadows(&self) -> bool {
+		self.object.casts_shadows()
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		check_finite(&[self.velocity], &[])?;
+		self.object.validate()
+	}
+}
+
+/** An object with an identifier, which is reported with its hits (e.g. to see which object is hit where). */
+pub struct Identified {
+	pub object: Arc<dyn Traceable>,
+	pub id: u32,
+}
+
+impl Traceable for Identified {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		self.object.intersect(ray).map(|hit| Hit {
+			object_id: Some(self.id),
+			..hit
+		})
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		self.object.intersect_intervals(ray)
+	}
+
+	fn aabb(&self) -> Aabb {
+		self.object.aabb()
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		self.object.bounding_sphere()
+	}
+
+	fn visible(&self) -> bool {
+		self.object.visible()
+	}
+
+	fn casts_shadows(&self) -> bool {
+		self.object.casts_shadows()
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		self.object.validate()
+	}
+}
+
+/** An object that is hidden from camera rays or from shadow rays, e.g. for compositing. */
+pub struct Visibility {
+	pub object: Arc<dyn Traceable>,
+	pub visible: bool,
+	pub casts_shadows: bool,
+}
+
+impl Traceable for Visibility {
+	fn intersect(&self, ray: &Ray) -> Option<Hit> {
+		self.object.intersect(ray)
+	}
+
+	fn intersect_intervals(&self, ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		self.object.intersect_intervals(ray)
+	}
+
+	fn aabb(&self) -> Aabb {
+		self.object.aabb()
+	}
+
+	fn bounding_sphere(&self) -> BoundingSphere {
+		self.object.bounding_sphere()
+	}
+
+	fn visible(&self) -> bool {
+		self.visible
+	}
+
+	fn casts_shadows(&self) -> bool {
+		self.casts_shadows
+	}
+
+	fn validate(&self) -> Result<(), GeometryError> {
+		self.object.validate()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::scene::tests::diffuse;
+
+	fn material() -> Arc<Material> {
+		Arc::new(diffuse(Vector::new(1.0, 1.0, 1.0)))
+	}
+
+	fn sphere(center: Vector, radius: f64) -> Sphere {
+		Sphere {
+			center,
+			radius,
+			material: material(),
+		}
+	}
+
+	#[test]
+	fn sphere_tangent_ray_misses() {
+		// Passes just outside the sphere, at a squared distance between the radius squared and the radius
+		let sphere = sphere(Vector::new(0.0, 0.0, -5.0), 0.5);
+		let ray = Ray::new(
+			Vector::new(0.5 + 1e-9, 0.0, 0.0),
+			Vector::new(0.0, 0.0, -1.0),
+		);
+		assert!(sphere.intersect(&ray).is_none());
+	}
+
+	#[test]
+	fn sphere_grazing_ray_hits() {
+		let sphere = sphere(Vector::new(0.0, 0.0, -10.0), 2.0);
+		let ray = Ray::new(Vector::new(1.9, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		let hit = sphere.intersect(&ray).expect("grazing ray should hit");
+		assert!((hit.distance - (10.0 - 0.39_f64.sqrt())).abs() < 1e-9);
+	}
+
+	#[test]
+	fn triangle_hit_distance() {
+		let triangle = Triangle {
+			a: Vector::new(-1.0, -1.0, -3.0),
+			b: Vector::new(1.0, -1.0, -3.0),
+			c: Vector::new(0.0, 1.0, -3.0),
+			normals: None,
+			double_sided: false,
+			material: material(),
+		};
+		let ray = Ray::new(Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		let hit = triangle
+			.intersect(&ray)
+			.expect("ray should hit the triangle");
+		assert!((hit.distance - 3.0).abs() < 1e-9);
+		assert!((hit.normal.z - 1.0).abs() < 1e-9);
+
+		// The back face is only hit when the triangle is double sided
+		let back = Ray::new(Vector::new(0.0, 0.0, -6.0), Vector::new(0.0, 0.0, 1.0));
+		assert!(triangle.intersect(&back).is_none());
+		let triangle = Triangle {
+			double_sided: true,
+			..triangle
+		};
+		let hit = triangle
+			.intersect(&back)
+			.expect("ray should hit the back face");
+		assert!((hit.distance - 3.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn box_faces_have_outward_normals() {
+		let cube = AxisAlignedBox {
+			min: Vector::new(-1.0, -1.0, -1.0),
+			max: Vector::new(1.0, 1.0, 1.0),
+			material: material(),
+		};
+		let axes = [
+			Vector::new(1.0, 0.0, 0.0),
+			Vector::new(0.0, 1.0, 0.0),
+			Vector::new(0.0, 0.0, 1.0),
+		];
+		for outward in axes.iter().flat_map(|&axis| [axis, -axis]) {
+			// Aim slightly off the center of the face, towards the box
+			let offset = Vector::new(0.1, 0.2, 0.3);
+			let origin = outward * 5.0 + offset - outward * (outward ^ offset);
+			let hit = cube
+				.intersect(&Ray::new(origin, -outward))
+				.expect("ray should hit the face");
+			assert!((hit.distance - 4.0).abs() < 1e-9);
+			assert_eq!(hit.normal, outward);
+		}
+	}
+
+	fn assert_hit(object: &dyn Traceable, ray: &Ray, distance: f64, normal: Vector) {
+		let hit = object.intersect(ray).expect("ray should hit");
+		assert!((hit.distance - distance).abs() < 1e-9);
+		assert!(hit.normal.distance(&normal) < 1e-9);
+	}
+
+	#[test]
+	fn cylinder_side_and_cap_hits() {
+		let cylinder = Cylinder {
+			base: Vector::new(0.0, 0.0, -5.0),
+			axis: Vector::new(0.0, 1.0, 0.0),
+			radius: 1.0,
+			height: 2.0,
+			open: false,
+			material: material(),
+		};
+
+		let side = Ray::new(Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		assert_hit(&cylinder, &side, 4.0, Vector::new(0.0, 0.0, 1.0));
+
+		let top = Ray::new(Vector::new(0.3, 5.0, -5.0), Vector::new(0.0, -1.0, 0.0));
+		assert_hit(&cylinder, &top, 3.0, Vector::new(0.0, 1.0, 0.0));
+		let bottom = Ray::new(Vector::new(0.3, -5.0, -5.0), Vector::new(0.0, 1.0, 0.0));
+		assert_hit(&cylinder, &bottom, 5.0, Vector::new(0.0, -1.0, 0.0));
+
+		// Without caps, the ray passes through along the axis
+		let open = Cylinder {
+			open: true,
+			..cylinder
+		};
+		assert!(open.intersect(&top).is_none());
+	}
+
+	#[test]
+	fn disk_hits_inside_radius_only() {
+		let disk = Disk {
+			center: Vector::new(0.0, 0.0, -4.0),
+			normal: Vector::new(0.0, 0.0, 1.0),
+			radius: 1.0,
+			material: material(),
+		};
+		let through_center = Ray::new(Vector::default(), Vector::new(0.0, 0.0, -1.0));
+		assert_hit(&disk, &through_center, 4.0, Vector::new(0.0, 0.0, 1.0));
+		let outside = Ray::new(Vector::new(1.1, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		assert!(disk.intersect(&outside).is_none());
+
+		// Seen from behind, the normal faces the ray
+		let behind = Ray::new(Vector::new(0.5, 0.0, -8.0), Vector::new(0.0, 0.0, 1.0));
+		assert_hit(&disk, &behind, 4.0, Vector::new(0.0, 0.0, -1.0));
+	}
+
+	#[test]
+	fn cone_is_hit_near_the_apex() {
+		let half_angle = 30.0_f64.to_radians();
+		let cone = Cone {
+			apex: Vector::new(0.0, 2.0, -5.0),
+			axis: Vector::new(0.0, -1.0, 0.0),
+			half_angle,
+			height: 2.0,
+			open: false,
+			material: material(),
+		};
+
+		// Straight down the axis, the ray hits the tip; slightly beside it, the side just below the tip
+		let down = Vector::new(0.0, -1.0, 0.0);
+		let through_apex = Ray::new(Vector::new(0.0, 5.0, -5.0), down);
+		assert_hit(&cone, &through_apex, 3.0, Vector::new(0.0, 1.0, 0.0));
+
+		let beside = Ray::new(Vector::new(0.01, 5.0, -5.0), down);
+		let sloped = Vector::new(half_angle.cos(), half_angle.sin(), 0.0);
+		assert_hit(&cone, &beside, 3.0 + 0.01 / half_angle.tan(), sloped);
+	}
+
+	#[test]
+	fn quad_center_has_uv_of_one_half() {
+		// A tilted quad with edges of different lengths, that are not perpendicular
+		let quad = Quad {
+			origin: Vector::new(-1.0, -1.0, -4.0),
+			u: Vector::new(3.0, 0.0, -1.0),
+			v: Vector::new(0.5, 2.0, 0.5),
+			double_sided: false,
+			material: material(),
+		};
+		let center = quad.origin + quad.u * 0.5 + quad.v * 0.5;
+		let ray = Ray::new(Vector::default(), center.normalize());
+		let hit = quad.intersect(&ray).expect("ray should hit the quad");
+		assert!((hit.distance - center.norm()).abs() < 1e-9);
+		assert!((hit.uv.0 - 0.5).abs() < 1e-9 && (hit.uv.1 - 0.5).abs() < 1e-9);
+
+		// Near a corner, the coordinates are those of the corner
+		let corner = quad.origin + quad.u * 0.99 + quad.v * 0.01;
+		let hit = quad
+			.intersect(&Ray::new(Vector::default(), corner.normalize()))
+			.expect("ray should hit the quad");
+		assert!((hit.uv.0 - 0.99).abs() < 1e-9 && (hit.uv.1 - 0.01).abs() < 1e-9);
+	}
+
+	fn torus(major_radius: f64, minor_radius: f64) -> Torus {
+		Torus {
+			center: Vector::new(0.0, 0.0, -5.0),
+			axis: Vector::new(0.0, 0.0, 1.0),
+			major_radius,
+			minor_radius,
+			material: material(),
+		}
+	}
+
+	#[test]
+	fn torus_hole_misses_and_ring_hits() {
+		let torus = torus(1.0, 0.25);
+		let down = Vector::new(0.0, 0.0, -1.0);
+		assert!(torus
+			.intersect(&Ray::new(Vector::default(), down))
+			.is_none());
+
+		// Through the middle of the tube, and inside its outer edge
+		let ring = Ray::new(Vector::new(1.0, 0.0, 0.0), down);
+		assert_hit(&torus, &ring, 4.75, Vector::new(0.0, 0.0, 1.0));
+		let edge = Ray::new(Vector::new(0.0, 1.2, 0.0), down);
+		let hit = torus.intersect(&edge).expect("ray should hit the ring");
+		assert!((hit.distance - (5.0 - (0.25_f64.powi(2) - 0.2_f64.powi(2)).sqrt())).abs() < 1e-9);
+
+		// Just outside the ring, and just inside the hole
+		assert!(torus
+			.intersect(&Ray::new(Vector::new(1.26, 0.0, 0.0), down))
+			.is_none());
+		assert!(torus
+			.intersect(&Ray::new(Vector::new(0.0, -0.74, 0.0), down))
+			.is_none());
+	}
+
+	#[test]
+	fn thin_torus_is_hit() {
+		// The tube is much thinner than the ring is large, so that the search must take many small steps
+		let torus = torus(10.0, 0.01);
+		let ray = Ray::new(Vector::new(10.0, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+		assert_hit(&torus, &ray, 4.99, Vector::new(0.0, 0.0, 1.0));
+
+		// Along the plane of the ring, the ray hits the outside of the tube on the near side
+		let along = Ray::new(Vector::new(-20.0, 0.0, -5.0), Vector::new(1.0, 0.0, 0.0));
+		assert_hit(&torus, &along, 9.99, Vector::new(-1.0, 0.0, 0.0));
+	}
+
+	/** Distance to a sphere as computed before spheres behind the ray were rejected early */
+	fn reference_sphere_distance(sphere: &Sphere, ray: &Ray) -> Option<f64> {
+		let l = sphere.center - ray.origin();
+		let tca = l ^ ray.direction();
+		let d2 = l.norm_squared() - tca * tca;
+		if d2 > sphere.radius * sphere.radius {
+			return None;
+		}
+		let thc = (sphere.radius * sphere.radius - d2).sqrt();
+		let (t0, t1) = (tca - thc, tca + thc);
+		let t = if t0 < 0.0 { t1 } else { t0 };
+		(t >= 0.0).then_some(t)
+	}
+
+	#[test]
+	fn sphere_distances_match_reference() {
+		use rand::rngs::SmallRng;
+		use rand::{RngExt, SeedableRng};
+
+		let mut rng = SmallRng::seed_from_u64(77);
+		let mut coordinate = || rng.random::<f64>() * 8.0 - 4.0;
+		let sphere = sphere(Vector::new(0.5, -0.25, 0.0), 1.5);
+		let (mut hits, mut inside) = (0, 0);
+		for _ in 0..10_000 {
+			// Origins inside, in front of and behind the sphere, in random directions
+			let origin = Vector::new(coordinate(), coordinate(), coordinate());
+			let direction = Vector::new(coordinate(), coordinate(), coordinate()).normalize();
+			let ray = Ray::new(origin, direction);
+			let expected = reference_sphere_distance(&sphere, &ray);
+			let found = sphere.intersect(&ray).map(|hit| hit.distance);
+			match (expected, found) {
+				(Some(expected), Some(found)) => assert!((expected - found).abs() < 1e-12),
+				(None, None) => {}
+				_ => panic!("hit differs for {:?} in direction {:?}", origin, direction),
+			}
+			hits += usize::from(expected.is_some());
+			inside += usize::from(origin.distance(&sphere.center) < sphere.radius);
+		}
+		assert!(hits > 500 && inside > 100);
+	}
+
+	#[test]
+	fn csg_difference_cuts_a_hole() {
+		let drill = Arc::new(diffuse(Vector::new(1.0, 0.0, 0.0)));
+		let block = AxisAlignedBox {
+			min: Vector::new(-1.0, -1.0, -6.0),
+			max: Vector::new(1.0, 1.0, -4.0),
+			material: material(),
+		};
+		let hole = AxisAlignedBox {
+			min: Vector::new(-0.3, -0.3, -10.0),
+			max: Vector::new(0.3, 0.3, 0.0),
+			material: drill.clone(),
+		};
+		let csg = Csg {
+			a: Arc::new(block),
+			b: Arc::new(hole),
+			operation: CsgOperation::Difference,
+		};
+
+		// Straight through the hole, and along its length from inside it
+		let forward = Vector::new(0.0, 0.0, -1.0);
+		assert!(csg
+			.intersect(&Ray::new(Vector::default(), forward))
+			.is_none());
+		assert!(csg
+			.intersect(&Ray::new(Vector::new(0.2, -0.2, -5.0), forward))
+			.is_none());
+
+		// Beside the hole, the front of the block is hit
+		let beside = Ray::new(Vector::new(0.5, 0.0, 0.0), forward);
+		assert_hit(&csg, &beside, 4.0, Vector::new(0.0, 0.0, 1.0));
+
+		// Into the hole at an angle, the ray hits its wall, which has the material of the object cut out
+		let angled = Ray::new(Vector::default(), Vector::new(0.06, 0.0, -1.0));
+		let hit = csg
+			.intersect(&angled)
+			.expect("ray should hit the wall of the hole");
+		assert!((angled.extend(hit.distance).x - 0.3).abs() < 1e-9);
+		assert!(hit.normal.distance(&Vector::new(-1.0, 0.0, 0.0)) < 1e-9);
+		assert!(Arc::ptr_eq(&hit.material, &drill));
+
+		// Operands with an identifier are still solid
+		let identified = Csg {
+			a: Arc::new(Identified {
+				object: csg.a.clone(),
+				id: 1,
+			}),
+			b: Arc::new(Identified {
+				object: csg.b.clone(),
+				id: 2,
+			}),
+			..csg
+		};
+		assert!(identified
+			.intersect(&Ray::new(Vector::default(), forward))
+			.is_none());
+		assert_hit(&identified, &beside, 4.0, Vector::new(0.0, 0.0, 1.0));
+
+		// As are operands that do not cast shadows
+		let shadowless = Csg {
+			b: Arc::new(Visibility {
+				object: csg.b.clone(),
+				visible: true,
+				casts_shadows: false,
+			}),
+			..csg
+		};
+		assert!(shadowless
+			.intersect(&Ray::new(Vector::default(), forward))
+			.is_none());
+		assert_hit(&shadowless, &beside, 4.0, Vector::new(0.0, 0.0, 1.0));
+	}
+
+	#[test]
+	fn moving_sphere_is_smeared_along_its_path() {
+		// Moves from x = 0 to x = 2 during the frame
+		let moving = Moving {
+			object: Arc::new(sphere(Vector::new(0.0, 0.0, -5.0), 0.5)),
+			velocity: Vector::new(2.0, 0.0, 0.0),
+		};
+
+		// Fraction of the frame during which the point at x is covered, from rays at evenly spread times
+		let coverage = |x: f64| {
+			let hits = (0..1000)
+				.filter(|&i| {
+					let time = (f64::from(i) + 0.5) / 1000.0;
+					let ray = Ray::new(Vector::new(x, 0.0, 0.0), Vector::new(0.0, 0.0, -1.0));
+					moving.intersect(&ray.at_time(time)).is_some()
+				})
+				.count();
+			hits as f64 / 1000.0
+		};
+
+		// Each point on the path is covered for the time the sphere takes to move its diameter
+		assert!((coverage(1.0) - 0.5).abs() < 0.01);
+		assert!((coverage(0.5) - 0.5).abs() < 0.01);
+		assert!((coverage(-0.4) - 0.05).abs() < 0.01);
+		assert_eq!(coverage(2.6), 0.0);
+		assert_eq!(coverage(-0.6), 0.0);
+
+		// The box contains the whole path
+		let aabb = moving.aabb();
+		assert!((aabb.min.x + 0.5).abs() < 1e-9 && (aabb.max.x - 2.5).abs() < 1e-9);
+	}
+
+	#[test]
+	fn double_sided_floor_seen_from_below_faces_down() {
+		let plane = |double_sided| Plane {
+			point: Vector::default(),
+			normal: Vector::new(0.0, 1.0, 0.0),
+			double_sided,
+			material: material(),
+		};
+		let up = Ray::new(Vector::new(0.0, -2.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+		let down = Ray::new(Vector::new(0.0, 2.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+		assert_hit(&plane(true), &up, 2.0, Vector::new(0.0, -1.0, 0.0));
+		assert_hit(&plane(true), &down, 2.0, Vector::new(0.0, 1.0, 0.0));
+		assert_hit(&plane(false), &up, 2.0, Vector::new(0.0, 1.0, 0.0));
+
+		let rectangle = Rectangle {
+			x_min: -1.0,
+			x_max: 1.0,
+			z_min: -1.0,
+			z_max: 1.0,
+			y: 0.0,
+			double_sided: true,
+			material: material(),
+		};
+		assert_hit(&rectangle, &up, 2.0, Vector::new(0.0, -1.0, 0.0));
+		assert_hit(&rectangle, &down, 2.0, Vector::new(0.0, 1.0, 0.0));
+
+		let quad = Quad {
+			origin: Vector::new(-1.0, 0.0, 1.0),
+			u: Vector::new(2.0, 0.0, 0.0),
+			v: Vector::new(0.0, 0.0, -2.0),
+			double_sided: true,
+			material: material(),
+		};
+		assert_hit(&quad, &up, 2.0, Vector::new(0.0, -1.0, 0.0));
+
+		// Lit from below, the double sided floor is shaded with the downward normal, while the single sided one stays dark
+		let brightness = |double_sided| {
+			let light = crate::scene::Light::Point {
+				position: Vector::new(0.0, -5.0, 0.0),
+				intensity: 1.0,
+				falloff: false,
+			};
+			let scene =
+				crate::scene::tests::scene(vec![Arc::new(plane(double_sided))], vec![light]);
+			scene.cast_ray(&up, 1).x
+		};
+		assert!((brightness(true) - 1.0).abs() < 1e-9);
+		assert_eq!(brightness(false), 0.0);
 	}
 }
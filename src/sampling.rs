@@ -0,0 +1,282 @@
+use super::geometry::Vector;
+use rand::rngs::SmallRng;
+use rand::{RngExt, SeedableRng};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+thread_local! {
+	/** Random number generator used for sampling on the current thread */
+	static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::seed_from_u64(0));
+}
+
+/** Reseed the random number generator of the current thread for rendering the pixel with the given index (y * width +
+x). The generator is seeded with the render seed XOR the pixel index multiplied by 2^64 divided by the golden ratio,
+which spreads consecutive pixels over the seed space. As every pixel starts from its own seed, the samples do not
+depend on the order in which pixels are rendered or on the thread rendering them. */
+pub fn seed_pixel(seed: u64, pixel_index: u64) {
+	let pixel_seed = seed ^ pixel_index.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+	RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(pixel_seed));
+}
+
+/** A uniformly distributed random number in [0, 1) */
+pub fn random() -> f64 {
+	RNG.with(|rng| rng.borrow_mut().random::<f64>())
+}
+
+/** A uniformly distributed random point on the unit disk, as (x, y) */
+pub fn in_unit_disk() -> (f64, f64) {
+	let r = random().sqrt();
+	let theta = 2.0 * std::f64::consts::PI * random();
+	(r * theta.cos(), r * theta.sin())
+}
+
+/** A uniformly distributed random point inside the unit sphere */
+pub fn in_unit_sphere() -> Vector {
+	loop {
+		let point = Vector::new(
+			2.0 * random() - 1.0,
+			2.0 * random() - 1.0,
+			2.0 * random() - 1.0,
+		);
+		if point.norm_squared() < 1.0 {
+			return point;
+		}
+	}
+}
+
+/** A random direction on the hemisphere around the (unit) normal, distributed proportionally to the cosine of its angle
+with the normal */
+pub fn cosine_hemisphere(normal: Vector) -> Vector {
+	// Orthonormal basis around the normal
+	let helper = if normal.x.abs() > 0.9 {
+		Vector::new(0.0, 1.0, 0.0)
+	} else {
+		Vector::new(1.0, 0.0, 0.0)
+	};
+	let u = normal.cross(&helper).normalize();
+	let v = normal.cross(&u);
+
+	// Uniform point on the unit disk, projected up onto the hemisphere
+	let (x, y) = in_unit_disk();
+	u * x + v * y + normal * (1.0 - x * x - y * y).max(0.0).sqrt()
+}
+
+/** Placement of the samples within a pixel when supersampling with a fixed number of samples. */
+#[derive(Clone, Copy, Default)]
+pub enum SamplePattern {
+	/** A regular grid, with samples at the centers of its cells */
+	#[default]
+	Grid,
+
+	/** A random point in each cell of a regular grid (stratified sampling) */
+	Jitter,
+
+	/** Points of the Halton sequence in bases 2 and 3, which cover the pixel evenly for any number of samples */
+	Halton,
+
+	/** Precomputed points that are spread evenly without regular structure (see `blue_noise`) */
+	BlueNoise,
+}
+
+impl SamplePattern {
+	/** Positions in [0, 1) x [0, 1) within a pixel of samples x samples samples */
+	pub fn offsets(self, samples: u32) -> Vec<(f64, f64)> {
+		// Patterns that are the same in every pixel are shifted by a random amount (wrapping around) in each pixel, so
+		// that neighbouring pixels do not show the same aliasing
+		let (shift_x, shift_y) = match self {
+			SamplePattern::Halton | SamplePattern::BlueNoise => (random(), random()),
+			SamplePattern::Grid | SamplePattern::Jitter => (0.0, 0.0),
+		};
+		(0..(samples * samples))
+			.map(|index| {
+				let (x, y) = self.offset(index, samples);
+				((x + shift_x).fract(), (y + shift_y).fract())
+			})
+			.collect()
+	}
+
+	/** Position of sample `index` out of samples x samples, before shifting */
+	fn offset(self, index: u32, samples: u32) -> (f64, f64) {
+		let n = f64::from(samples);
+		match self {
+			SamplePattern::Grid => (
+				(f64::from(index % samples) + 0.5) / n,
+				(f64::from(index / samples) + 0.5) / n,
+			),
+			SamplePattern::Jitter => (
+				(f64::from(index % samples) + random()) / n,
+				(f64::from(index / samples) + random()) / n,
+			),
+			SamplePattern::Halton => {
+				// The first point (0, 0) is skipped
+				(halton(index + 1, 2), halton(index + 1, 3))
+			}
+			SamplePattern::BlueNoise => {
+				let points = blue_noise();
+				points[index as usize % points.len()]
+			}
+		}
+	}
+}
+
+impl FromStr for SamplePattern {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<SamplePattern, String> {
+		match s {
+			"grid" => Ok(SamplePattern::Grid),
+			"jitter" => Ok(SamplePattern::Jitter),
+			"halton" => Ok(SamplePattern::Halton),
+			"blue-noise" => Ok(SamplePattern::BlueNoise),
+			_ => Err(format!("unknown sample pattern '{}'", s)),
+		}
+	}
+}
+
+/** Element `index` of the Halton sequence in the given base (the radical inverse of the index): its digits mirrored
+around the decimal point, e.g. 0.5, 0.25, 0.75, 0.125 for indices 1 to 4 in base 2. */
+pub fn halton(mut index: u32, base: u32) -> f64 {
+	let mut result = 0.0;
+	let mut fraction = 1.0 / f64::from(base);
+	while index > 0 {
+		result += f64::from(index % base) * fraction;
+		index /= base;
+		fraction /= f64::from(base);
+	}
+	result
+}
+
+/** Number of points in the blue noise pattern; pixels with more samples reuse points */
+const BLUE_NOISE_POINTS: usize = 256;
+
+/** Points in [0, 1) x [0, 1) of which any first part is spread evenly, generated once using Mitchell's best candidate
+algorithm: each point is the one furthest from the points before it out of a number of random candidates. Distances wrap
+around the edges, so that the pattern tiles. */
+pub fn blue_noise() -> &'static [(f64, f64)] {
+	static POINTS: OnceLock<Vec<(f64, f64)>> = OnceLock::new();
+	POINTS.get_or_init(|| {
+		// A fixed seed makes the pattern the same in every run
+		let mut rng = SmallRng::seed_from_u64(0x5EED);
+		let wrapped = |a: f64, b: f64| {
+			let d = (a - b).abs();
+			d.min(1.0 - d)
+		};
+
+		let mut points: Vec<(f64, f64)> = Vec::with_capacity(BLUE_NOISE_POINTS);
+		while points.len() < BLUE_NOISE_POINTS {
+			let candidates = points.len() + 1;
+			let best = (0..candidates)
+				.map(|_| (rng.random::<f64>(), rng.random::<f64>()))
+				.map(|(x, y)| {
+					let distance = points
+						.iter()
+						.map(|&(px, py)| wrapped(x, px).powi(2) + wrapped(y, py).powi(2))
+						.fold(f64::INFINITY, f64::min);
+					((x, y), distance)
+				})
+				.fold(((0.0, 0.0), -1.0), |best, candidate| {
+					if candidate.1 > best.1 {
+						candidate
+					} else {
+						best
+					}
+				});
+			points.push(best.0);
+		}
+		points
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cosine_hemisphere_statistics() {
+		seed_pixel(1, 0);
+		let count = 100_000;
+		for normal in [
+			Vector::new(1.0, 2.0, -2.0) / 3.0,
+			Vector::new(-1.0, 0.0, 0.0),
+		] {
+			let mut sum_cosine = 0.0;
+			let mut sum_squared_cosine = 0.0;
+			let mut sum = Vector::default();
+			for _ in 0..count {
+				let direction = cosine_hemisphere(normal);
+				assert!((direction.norm() - 1.0).abs() < 1e-9);
+				let cosine = direction ^ normal;
+				assert!(cosine >= 0.0);
+				sum_cosine += cosine;
+				sum_squared_cosine += cosine * cosine;
+				sum += direction;
+			}
+
+			// For a density proportional to the cosine, the mean cosine is 2/3 and the mean squared cosine is 1/2
+			let count = f64::from(count);
+			assert!((sum_cosine / count - 2.0 / 3.0).abs() < 0.005);
+			assert!((sum_squared_cosine / count - 0.5).abs() < 0.005);
+
+			// Directions are spread evenly around the normal, so they average out to a multiple of it
+			let mean = sum / count;
+			assert!(mean.distance(&(normal * (2.0 / 3.0))) < 0.01);
+		}
+	}
+
+	#[test]
+	fn halton_sequence_values() {
+		let base2: Vec<f64> = (1..=4).map(|i| halton(i, 2)).collect();
+		assert_eq!(base2, [0.5, 0.25, 0.75, 0.125]);
+		let base3: Vec<f64> = (1..=5).map(|i| halton(i, 3)).collect();
+		let expected = [1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0, 7.0 / 9.0];
+		for (value, expected) in base3.iter().zip(&expected) {
+			assert!((value - expected).abs() < 1e-12);
+		}
+		assert_eq!(halton(0, 2), 0.0);
+
+		// The Halton pattern places samples inside the pixel, each at a different point
+		let offsets = SamplePattern::Halton.offsets(3);
+		assert_eq!(offsets.len(), 9);
+		assert!(offsets
+			.iter()
+			.all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+		for (i, a) in offsets.iter().enumerate() {
+			assert!(offsets[i + 1..].iter().all(|b| a != b));
+		}
+	}
+
+	#[test]
+	fn unit_disk_and_sphere_statistics() {
+		seed_pixel(2, 0);
+		let count = 100_000;
+		let (mut sum_x, mut sum_y, mut sum_squared_radius) = (0.0, 0.0, 0.0);
+		for _ in 0..count {
+			let (x, y) = in_unit_disk();
+			let squared_radius = x * x + y * y;
+			assert!(squared_radius < 1.0);
+			sum_x += x;
+			sum_y += y;
+			sum_squared_radius += squared_radius;
+		}
+
+		// Uniform over the area, the mean squared radius of the disk is 1/2
+		let count = f64::from(count);
+		assert!((sum_x / count).abs() < 0.01);
+		assert!((sum_y / count).abs() < 0.01);
+		assert!((sum_squared_radius / count - 0.5).abs() < 0.005);
+
+		let mut sum = Vector::default();
+		let mut sum_squared_norm = 0.0;
+		for _ in 0..count as u32 {
+			let point = in_unit_sphere();
+			assert!(point.norm() < 1.0);
+			sum += point;
+			sum_squared_norm += point.norm_squared();
+		}
+
+		// Uniform over the volume, the mean squared distance from the centre of the sphere is 3/5
+		assert!((sum / count).norm() < 0.01);
+		assert!((sum_squared_norm / count - 0.6).abs() < 0.005);
+	}
+}
@@ -1,152 +1,2268 @@
-use super::geometry::{Ray, Vector};
-use image::{DynamicImage, GenericImageView};
+use super::bvh::Bvh;
+use super::geometry::{fresnel, Aabb, BoundingSphere, Ray, Vector};
+use super::grid::Grid;
+use super::sampling;
+use image::hdr::HDRDecoder;
+use image::{DynamicImage, GenericImageView, ImageResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 pub struct Scene {
-	pub objects: Vec<Arc<dyn Traceable>>,
+	pub objects: Bvh,
+
+	/** Uniform grid over the objects, used instead of the bounding volume hierarchy when present */
+	pub grid: Option<Grid>,
 	pub lights: Vec<Light>,
-	pub environment_color: Vector,
-	pub environment_map: Option<DynamicImage>,
+	pub background: Background,
+	pub environment_map: Option<EnvironmentMap>,
+	pub environment_mapping: EnvironmentMapping,
+
+	/** Light reaching every surface regardless of occlusion, approximating indirect lighting */
+	pub ambient: Vector,
+
+	/** Randomly terminate secondary rays that contribute little to the result (makes renders non-deterministic) */
+	pub russian_roulette: bool,
+
+	/** Number of rays cast from diffuse surfaces to gather light from the environment (0 disables) */
+	pub environment_samples: u32,
+
+	/** Path tracing: diffuse surfaces are also lit by light reflected off other surfaces (diffuse interreflection) */
+	pub global_illumination: bool,
+
+	/** Objects further than this from the origin of a ray are ignored, as if the ray escaped to the background */
+	pub max_distance: f64,
+
+	/** Color that camera rays fade to with distance, as light is scattered by fog */
+	pub fog_color: Vector,
+
+	/** Fraction of light scattered by fog per unit of distance (0 disables fog) */
+	pub fog_density: f64,
+
+	/** Counts of the rays cast, when enabled (counting slightly slows down rendering) */
+	pub statistics: Option<RayStatistics>,
+}
+
+/** Purpose for which a ray is cast. */
+#[derive(Clone, Copy)]
+pub enum RayKind {
+	/** Cast from the camera */
+	Primary,
+
+	/** Cast towards a light or the environment to determine whether it is occluded */
+	Shadow,
+	Reflection,
+	Refraction,
+
+	/** Cast in a random direction from a diffuse surface to gather indirect light (path tracing) */
+	Indirect,
+}
+
+/** Number of rays cast of each kind. Counters are shared between render threads. */
+#[derive(Default)]
+pub struct RayStatistics {
+	pub primary: AtomicU64,
+	pub shadow: AtomicU64,
+	pub reflection: AtomicU64,
+	pub refraction: AtomicU64,
+	pub indirect: AtomicU64,
+
+	/** Number of shaded pixels and the total number of samples taken for them */
+	pub pixels: AtomicU64,
+	pub samples: AtomicU64,
+
+	/** Number of nodes of the bounding volume hierarchy visited while finding the objects hit by rays */
+	pub nodes: AtomicU64,
+}
+
+impl RayStatistics {
+	pub fn count(&self, kind: RayKind) {
+		let counter = match kind {
+			RayKind::Primary => &self.primary,
+			RayKind::Shadow => &self.shadow,
+			RayKind::Reflection => &self.reflection,
+			RayKind::Refraction => &self.refraction,
+			RayKind::Indirect => &self.indirect,
+		};
+		counter.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/** Count a shaded pixel, for which the specified number of samples was taken */
+	pub fn count_pixel(&self, samples: u32) {
+		self.pixels.fetch_add(1, Ordering::Relaxed);
+		self.samples
+			.fetch_add(u64::from(samples), Ordering::Relaxed);
+	}
+}
+
+impl fmt::Display for RayStatistics {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} primary, {} shadow, {} reflection, {} refraction, {} indirect rays",
+			self.primary.load(Ordering::Relaxed),
+			self.shadow.load(Ordering::Relaxed),
+			self.reflection.load(Ordering::Relaxed),
+			self.refraction.load(Ordering::Relaxed),
+			self.indirect.load(Ordering::Relaxed)
+		)?;
+
+		let rays = [
+			&self.primary,
+			&self.shadow,
+			&self.reflection,
+			&self.refraction,
+			&self.indirect,
+		]
+		.iter()
+		.map(|counter| counter.load(Ordering::Relaxed))
+		.sum::<u64>();
+		let nodes = self.nodes.load(Ordering::Relaxed);
+		if rays > 0 && nodes > 0 {
+			write!(
+				f,
+				", visiting {:.1} hierarchy nodes per ray",
+				nodes as f64 / rays as f64
+			)?;
+		}
+
+		let pixels = self.pixels.load(Ordering::Relaxed);
+		if pixels > 0 {
+			let samples = self.samples.load(Ordering::Relaxed);
+			write!(
+				f,
+				" ({:.2} samples per pixel)",
+				samples as f64 / pixels as f64
+			)?;
+		}
+		Ok(())
+	}
+}
+
+/** Surface property visualized instead of the shaded color, for debugging. */
+#[derive(Clone, Copy)]
+pub enum DebugMode {
+	/** World-space normal, mapped from [-1, 1] to [0, 1] per channel */
+	Normals,
+
+	/** Distance to the camera as grayscale, from white (near) to black (far) */
+	Depth,
+
+	/** Diffuse color of the surface, without lighting */
+	Albedo,
+
+	/** A color derived from the identifier of the object hit (white for objects without one) */
+	ObjectId,
+}
+
+impl FromStr for DebugMode {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<DebugMode, String> {
+		match s {
+			"normals" => Ok(DebugMode::Normals),
+			"depth" => Ok(DebugMode::Depth),
+			"albedo" => Ok(DebugMode::Albedo),
+			"object-id" => Ok(DebugMode::ObjectId),
+			_ => Err(format!("unknown debug mode '{}'", s)),
+		}
+	}
+}
+
+/** A component of the shaded colors that can be rendered as an image of its own (an arbitrary output variable), e.g. for
+compositing. */
+#[derive(Clone, Copy)]
+pub enum Aov {
+	/** Light reflected diffusely by the first surface hit, including ambient and indirect light */
+	Diffuse,
+
+	/** Specular highlights of the lights on the first surface hit */
+	Specular,
+
+	/** Light reflected and refracted by the first surface hit */
+	Reflection,
+
+	/** Distance to the first surface hit as grayscale, as in the depth debug mode */
+	Depth,
+}
+
+impl Aov {
+	/** Name of the component, as accepted by `from_str` */
+	pub fn name(self) -> &'static str {
+		match self {
+			Aov::Diffuse => "diffuse",
+			Aov::Specular => "specular",
+			Aov::Reflection => "reflection",
+			Aov::Depth => "depth",
+		}
+	}
+
+	/** Value of this component in the components of the color seen along a ray */
+	pub fn value(self, components: &Components) -> Vector {
+		match self {
+			Aov::Diffuse => components.diffuse,
+			Aov::Specular => components.specular,
+			Aov::Reflection => components.reflection,
+			Aov::Depth => {
+				let brightness = 1.0 / (1.0 + components.distance / DEBUG_DEPTH_SCALE);
+				Vector::new(brightness, brightness, brightness)
+			}
+		}
+	}
+}
+
+impl FromStr for Aov {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Aov, String> {
+		match s {
+			"diffuse" => Ok(Aov::Diffuse),
+			"specular" => Ok(Aov::Specular),
+			"reflection" => Ok(Aov::Reflection),
+			"depth" => Ok(Aov::Depth),
+			_ => Err(format!("unknown output variable '{}'", s)),
+		}
+	}
+}
+
+/** The color seen along a ray, split by how the light reached the ray from the first surface hit. */
+#[derive(Clone, Copy, Default)]
+pub struct Components {
+	/** Light reflected diffusely, including ambient and indirect light */
+	pub diffuse: Vector,
+
+	/** Specular highlights of the lights */
+	pub specular: Vector,
+
+	/** Light reflected and refracted by the surface (along a single direction each) */
+	pub reflection: Vector,
+
+	/** Light emitted by the surface, or coming from the environment when no surface is hit */
+	pub emission: Vector,
+
+	/** Distance to the surface hit (infinite when no surface is hit) */
+	pub distance: f64,
+}
+
+impl Components {
+	/** The color seen along the ray */
+	pub fn total(&self) -> Vector {
+		self.diffuse + self.specular + self.reflection + self.emission
+	}
+
+	/** Whether the ray hit a surface, rather than escaping to the environment */
+	pub fn is_hit(&self) -> bool {
+		self.distance.is_finite()
+	}
+
+	/** The components with each color multiplied component-wise by the factor */
+	fn attenuated(self, factor: Vector) -> Components {
+		Components {
+			diffuse: self.diffuse.mul_components(&factor),
+			specular: self.specular.mul_components(&factor),
+			reflection: self.reflection.mul_components(&factor),
+			emission: self.emission.mul_components(&factor),
+			..self
+		}
+	}
+}
+
+/** A false color for an object identifier. Consecutive identifiers get very different colors, which never come close to
+black (the background) or white (objects without identifier). */
+fn id_color(id: u32) -> Vector {
+	let hash = id.wrapping_add(1).wrapping_mul(0x9E37_79B9);
+	let channel = |shift: u32| 0.15 + 0.7 * f64::from((hash >> shift) & 0xFF) / 255.0;
+	Vector::new(channel(24), channel(16), channel(8))
+}
+
+/** Distance at which the depth visualization reaches half brightness */
+const DEBUG_DEPTH_SCALE: f64 = 10.0;
+
+/** Distance by which secondary rays are moved off a surface, relative to the largest coordinate of the hit point */
+const OFFSET_EPSILON: f64 = 1e-4;
+
+/** Throughput below which secondary rays become subject to Russian roulette */
+const ROULETTE_THRESHOLD: f64 = 0.1;
+
+/** Color of the environment in directions where it is not defined by an environment map. */
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Background {
+	/** The same color in all directions */
+	Solid(Vector),
+
+	/** A vertical gradient, from the bottom color straight down to the top color straight up */
+	Gradient { bottom: Vector, top: Vector },
+}
+
+impl Background {
+	pub fn color_in(&self, direction: Vector) -> Vector {
+		match self {
+			Background::Solid(color) => *color,
+			Background::Gradient { bottom, top } => {
+				let t = 0.5 * (direction.normalize().y + 1.0);
+				bottom.lerp(*top, t)
+			}
+		}
+	}
+}
+
+/** Projection used to look up the environment map color in a certain direction. */
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvironmentMapping {
+	/** Projection of the direction onto the YZ plane */
+	#[default]
+	Planar,
+
+	/** Mirror ball (light probe) image */
+	Spherical,
+
+	/** Latitude-longitude panorama, as commonly used for HDRIs */
+	Equirectangular,
+}
+
+impl EnvironmentMapping {
+	/** Texture coordinates (in [0, 1], from the top left of the image) for the specified direction */
+	pub fn uv(self, direction: Vector) -> (f64, f64) {
+		match self {
+			EnvironmentMapping::Planar => {
+				// https://stackoverflow.com/questions/39283698/direction-to-environment-map-uv-coordinates
+				let m = direction.norm() * 2.0;
+				(-direction.z / m + 0.5, -direction.y / m + 0.5)
+			}
+			EnvironmentMapping::Spherical => {
+				let d = direction.normalize();
+				let m = 2.0 * (d.x * d.x + d.y * d.y + (d.z + 1.0) * (d.z + 1.0)).sqrt();
+				(d.x / m + 0.5, -d.y / m + 0.5)
+			}
+			EnvironmentMapping::Equirectangular => {
+				let d = direction.normalize();
+				let u = 0.5 + d.x.atan2(-d.z) / (2.0 * std::f64::consts::PI);
+				let v = 0.5 - d.y.clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+				(u, v)
+			}
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Light {
+	/** A light emitting in all directions from a single point */
+	Point {
+		position: Vector,
+		intensity: f64,
+
+		/** Whether the light falls off with the square of the distance (otherwise it reaches all distances undimmed) */
+		#[serde(default)]
+		falloff: bool,
+	},
+
+	/** A light infinitely far away, whose rays all travel in the same direction (e.g. the sun) */
+	Directional { direction: Vector, intensity: f64 },
+
+	/** A point light emitting in a cone, falling off between the inner and outer angle (in radians) */
+	Spot {
+		position: Vector,
+		direction: Vector,
+		inner_angle: f64,
+		outer_angle: f64,
+		intensity: f64,
+
+		/** Whether the light falls off with the square of the distance, as for point lights */
+		#[serde(default)]
+		falloff: bool,
+	},
+
+	/** A spherical light of the given radius, sampled using multiple shadow rays to produce soft shadows */
+	Area {
+		position: Vector,
+		radius: f64,
+		intensity: f64,
+		samples: u32,
+	},
+}
+
+impl Light {
+	/** Points on the light as seen from the point, as (normalized) directions towards them and their squared distances. */
+	pub fn samples_from(&self, point: Vector) -> Vec<(Vector, f64)> {
+		match self {
+			Light::Point { position, .. } | Light::Spot { position, .. } => {
+				vec![(
+					(*position - point).normalize(),
+					position.distance_squared(&point),
+				)]
+			}
+			Light::Directional { direction, .. } => {
+				vec![((-*direction).normalize(), f64::INFINITY)]
+			}
+			Light::Area {
+				position,
+				radius,
+				samples,
+				..
+			} => {
+				// Spread the samples over the disk facing the point, using a golden angle spiral
+				let w = (*position - point).normalize();
+				let helper = if w.x.abs() > 0.9 {
+					Vector {
+						x: 0.0,
+						y: 1.0,
+						z: 0.0,
+					}
+				} else {
+					Vector {
+						x: 1.0,
+						y: 0.0,
+						z: 0.0,
+					}
+				};
+				let u = w.cross(&helper).normalize();
+				let v = w.cross(&u);
+				let golden_angle = std::f64::consts::PI * (3.0 - 5.0_f64.sqrt());
+				let n = f64::from((*samples).max(1));
+
+				(0..(*samples).max(1))
+					.map(|i| {
+						let r = radius * ((f64::from(i) + 0.5) / n).sqrt();
+						let theta = f64::from(i) * golden_angle;
+						let sample = *position + (u * (r * theta.cos())) + (v * (r * theta.sin()));
+						(
+							(sample - point).normalize(),
+							sample.distance_squared(&point),
+						)
+					})
+					.collect()
+			}
+		}
+	}
+
+	/** Fraction of the intensity of the light that reaches a point in the given direction and at the squared distance. */
+	pub fn attenuation(&self, light_direction: Vector, distance_squared: f64) -> f64 {
+		let falloff = match self {
+			Light::Point { falloff: true, .. } | Light::Spot { falloff: true, .. } => {
+				1.0 / distance_squared
+			}
+			_ => 1.0,
+		};
+
+		let cone = match self {
+			Light::Spot {
+				direction,
+				inner_angle,
+				outer_angle,
+				..
+			} => {
+				let cos_angle = -light_direction ^ direction.normalize();
+				let cos_inner = inner_angle.cos();
+				let cos_outer = outer_angle.cos();
+				if cos_angle >= cos_inner {
+					1.0
+				} else if cos_angle <= cos_outer {
+					0.0
+				} else {
+					// Smoothstep between the outer and inner cone
+					let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+					t * t * (3.0 - 2.0 * t)
+				}
+			}
+			_ => 1.0,
+		};
+		falloff * cone
+	}
+
+	/** Check that the position, direction and size of the light are valid */
+	pub fn validate(&self) -> Result<(), GeometryError> {
+		match self {
+			Light::Point {
+				position,
+				intensity,
+				..
+			} => check_finite(&[*position], &[*intensity]),
+			Light::Directional {
+				direction,
+				intensity,
+			} => {
+				check_finite(&[*direction], &[*intensity])?;
+				check_direction(*direction)
+			}
+			Light::Spot {
+				position,
+				direction,
+				inner_angle,
+				outer_angle,
+				intensity,
+				..
+			} => {
+				check_finite(
+					&[*position, *direction],
+					&[*inner_angle, *outer_angle, *intensity],
+				)?;
+				check_direction(*direction)?;
+				if inner_angle > outer_angle {
+					return Err(GeometryError::InvalidConeAngles {
+						inner: *inner_angle,
+						outer: *outer_angle,
+					});
+				}
+				Ok(())
+			}
+			Light::Area {
+				position,
+				radius,
+				intensity,
+				..
+			} => {
+				check_finite(&[*position], &[*radius, *intensity])?;
+				check_positive(*radius)
+			}
+		}
+	}
+
+	pub fn intensity(&self) -> f64 {
+		match self {
+			Light::Point { intensity, .. }
+			| Light::Directional { intensity, .. }
+			| Light::Spot { intensity, .. }
+			| Light::Area { intensity, .. } => *intensity,
+		}
+	}
+}
+
+/** The (diffuse) color of a surface, possibly varying across the surface. */
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Texture {
+	/** A single color */
+	Solid(Vector),
+
+	/** A 3D checkerboard pattern alternating between colors a and b, with cubes of size `scale` */
+	Checker { a: Vector, b: Vector, scale: f64 },
 }
 
-pub struct Light {
-	pub position: Vector,
-	pub intensity: f64,
+impl Texture {
+	pub fn color_at(&self, point: &Vector) -> Vector {
+		match self {
+			Texture::Solid(color) => *color,
+			Texture::Checker { a, b, scale } => {
+				let cell = (point.x / scale).floor()
+					+ (point.y / scale).floor()
+					+ (point.z / scale).floor();
+				if (cell as i64).rem_euclid(2) == 0 {
+					*a
+				} else {
+					*b
+				}
+			}
+		}
+	}
+}
+
+/** Model for the shape of specular highlights. Blinn-Phong is cheaper to compute and gives elongated highlights at grazing
+angles; its exponent needs to be about four times as high as with Phong for highlights of similar size. */
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecularModel {
+	/** Angle between the view direction and the light direction reflected about the normal */
+	#[default]
+	Phong,
+
+	/** Angle between the normal and the half vector between the view and light directions */
+	BlinnPhong,
+}
+
+impl SpecularModel {
+	/** Cosine of the angle that determines the strength of the highlight (before raising it to the exponent) */
+	pub fn cosine(self, light_direction: Vector, ray_direction: Vector, normal: Vector) -> f64 {
+		match self {
+			SpecularModel::Phong => (-(-light_direction).reflect(normal)) ^ ray_direction,
+			SpecularModel::BlinnPhong => (light_direction - ray_direction).normalize() ^ normal,
+		}
+	}
+}
+
+/** How texture coordinates outside [0, 1] map onto a texture. */
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WrapMode {
+	/** Use the color at the nearest edge of the texture */
+	#[default]
+	Clamp,
+
+	/** Tile the texture */
+	Repeat,
+
+	/** Tile the texture, mirroring every other tile so that the tiles join seamlessly */
+	Mirror,
 }
 
-#[derive(Clone)]
+impl WrapMode {
+	/** Index of the texel used for (integer) texel coordinate `index` in a texture row or column of `size` texels */
+	fn texel(self, index: f64, size: u32) -> u32 {
+		let (index, size) = (index as i64, i64::from(size));
+		let texel = match self {
+			WrapMode::Clamp => index.clamp(0, size - 1),
+			WrapMode::Repeat => index.rem_euclid(size),
+			WrapMode::Mirror => {
+				let index = index.rem_euclid(2 * size);
+				if index < size {
+					index
+				} else {
+					2 * size - 1 - index
+				}
+			}
+		};
+		texel as u32
+	}
+}
+
+impl FromStr for WrapMode {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<WrapMode, String> {
+		match s {
+			"clamp" => Ok(WrapMode::Clamp),
+			"repeat" => Ok(WrapMode::Repeat),
+			"mirror" => Ok(WrapMode::Mirror),
+			_ => Err(format!("unknown wrap mode '{}'", s)),
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Material {
-	pub diffuse_color: Vector,
+	pub diffuse_color: Texture,
+
+	/** Color of specular highlights (white unless specified) */
+	#[serde(default = "white")]
+	pub specular_color: Vector,
 	pub specular_exponent: f64,
+
+	#[serde(default)]
+	pub specular_model: SpecularModel,
 	pub albedo_diffuse: f64,
 	pub albedo_reflect: f64,
 	pub albedo_specular: f64,
 	pub albedo_refract: f64,
 	pub refractive_index: f64,
+
+	/** Amount by which reflections are blurred, from 0 (a perfect mirror) to about 1 (taking many samples) */
+	#[serde(default)]
+	pub roughness: f64,
+
+	/** Light emitted by the surface itself, regardless of any lighting */
+	#[serde(default)]
+	pub emission: Vector,
+
+	/** Light absorbed per unit of distance inside the object: over a distance d, exp(-absorption * d) passes (Beer's law) */
+	#[serde(default)]
+	pub absorption: Vector,
+
+	/** Image sampled at the texture coordinates of the surface, replacing the diffuse color when present */
+	#[serde(skip)]
+	pub diffuse_texture: Option<Arc<MipMap>>,
+
+	/** Image holding tangent space normals, which perturb the surface normal used for shading when present */
+	#[serde(skip)]
+	pub normal_map: Option<Arc<DynamicImage>>,
+
+	/** How the diffuse texture and normal map are continued outside texture coordinates [0, 1] */
+	#[serde(default)]
+	pub texture_wrap: WrapMode,
+
+	/** Factor applied to the texture coordinates before sampling the textures, e.g. 4 to tile a texture 4 x 4 times */
+	#[serde(default = "unit_scale")]
+	pub texture_scale: f64,
 }
 
-pub trait Traceable: Send + Sync {
-	fn intersect(&self, ray: &Ray) -> Option<f64>;
-	fn material(&self) -> Arc<Material>;
-	fn normal_at(&self, point: &Vector) -> Vector;
+/** A white, purely diffuse material: without highlights, reflection, refraction, emission or textures */
+impl Default for Material {
+	fn default() -> Material {
+		Material {
+			diffuse_color: Texture::Solid(white()),
+			specular_color: white(),
+			specular_exponent: 1.0,
+			specular_model: SpecularModel::default(),
+			albedo_diffuse: 1.0,
+			albedo_reflect: 0.0,
+			albedo_specular: 0.0,
+			albedo_refract: 0.0,
+			refractive_index: 1.0,
+			roughness: 0.0,
+			emission: Vector::default(),
+			absorption: Vector::default(),
+			diffuse_texture: None,
+			normal_map: None,
+			texture_wrap: WrapMode::default(),
+			texture_scale: unit_scale(),
+		}
+	}
 }
 
-impl Scene {
-	fn intersect(self: &Scene, ray: &Ray) -> (f64, Option<Arc<dyn Traceable>>) {
-		let mut min_dist: f64 = std::f64::MAX;
-		let mut hit_object: Option<Arc<dyn Traceable>> = None;
-
-		// Find the first object hit by this ray
-		for object in &self.objects {
-			if let Some(distance) = object.intersect(ray) {
-				if distance < min_dist {
-					min_dist = distance;
-					hit_object = Some(object.clone());
+fn white() -> Vector {
+	Vector::new(1.0, 1.0, 1.0)
+}
+
+fn unit_scale() -> f64 {
+	1.0
+}
+
+/** Reason why the shape of an object or a light cannot be rendered. */
+#[derive(Debug)]
+pub enum GeometryError {
+	/** A coordinate or size is infinite or not a number */
+	NonFinite,
+
+	/** A size that must be positive (such as a radius) is zero or negative */
+	NonPositiveSize(f64),
+
+	/** The shape is flat or has no direction where it should not, e.g. a triangle with its vertices on a line */
+	Degenerate,
+
+	/** The inner angle of a spot light cone is larger than the outer angle, at which the light has faded out */
+	InvalidConeAngles { inner: f64, outer: f64 },
+}
+
+impl fmt::Display for GeometryError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			GeometryError::NonFinite => write!(f, "coordinates or sizes are not finite"),
+			GeometryError::NonPositiveSize(size) => write!(f, "size {} is not positive", size),
+			GeometryError::Degenerate => write!(f, "shape is degenerate"),
+			GeometryError::InvalidConeAngles { inner, outer } => write!(
+				f,
+				"inner cone angle {} is larger than outer cone angle {}",
+				inner, outer
+			),
+		}
+	}
+}
+
+impl std::error::Error for GeometryError {}
+
+/** Check that the points or directions and sizes are all finite */
+pub fn check_finite(vectors: &[Vector], sizes: &[f64]) -> Result<(), GeometryError> {
+	if vectors.iter().all(Vector::is_finite) && sizes.iter().all(|size| size.is_finite()) {
+		Ok(())
+	} else {
+		Err(GeometryError::NonFinite)
+	}
+}
+
+/** Check that the size is positive */
+pub fn check_positive(size: f64) -> Result<(), GeometryError> {
+	if size > 0.0 {
+		Ok(())
+	} else {
+		Err(GeometryError::NonPositiveSize(size))
+	}
+}
+
+/** Check that the direction is not zero, so that it can be normalized */
+pub fn check_direction(direction: Vector) -> Result<(), GeometryError> {
+	if direction.norm_squared() > 0.0 {
+		Ok(())
+	} else {
+		Err(GeometryError::Degenerate)
+	}
+}
+
+/** Objects and lights of a scene that cannot be rendered, each as a description (e.g. "object 3") with the reason. */
+#[derive(Debug)]
+pub struct InvalidScene {
+	pub problems: Vec<(String, GeometryError)>,
+}
+
+impl fmt::Display for InvalidScene {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid scene")?;
+		for (index, (item, error)) in self.problems.iter().enumerate() {
+			let separator = if index == 0 { ": " } else { "; " };
+			write!(f, "{}{}: {}", separator, item, error)?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for InvalidScene {}
+
+/** Reason why a material is not physically plausible. */
+#[derive(Debug)]
+pub enum MaterialError {
+	/** One of the albedos is negative */
+	NegativeAlbedo,
+
+	/** One of the components of the absorption is negative, so light would be amplified inside the object */
+	NegativeAbsorption,
+
+	/** The diffuse, reflected and refracted albedos sum to more than 1.0, so the surface returns more light than it receives */
+	EnergyGain(f64),
+}
+
+impl fmt::Display for MaterialError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MaterialError::NegativeAlbedo => write!(f, "albedos must not be negative"),
+			MaterialError::NegativeAbsorption => write!(f, "absorption must not be negative"),
+			MaterialError::EnergyGain(total) => write!(
+				f,
+				"diffuse, reflect and refract albedos sum to {} (more than 1.0)",
+				total
+			),
+		}
+	}
+}
+
+impl std::error::Error for MaterialError {}
+
+impl Material {
+	/** Check that the material does not add energy to the scene. The specular albedo only scales highlights and is not included. */
+	pub fn validate(&self) -> Result<(), MaterialError> {
+		let albedos = [
+			self.albedo_diffuse,
+			self.albedo_specular,
+			self.albedo_reflect,
+			self.albedo_refract,
+		];
+		if albedos.iter().any(|&albedo| albedo < 0.0) {
+			return Err(MaterialError::NegativeAlbedo);
+		}
+		let absorption = self.absorption;
+		if absorption.x < 0.0 || absorption.y < 0.0 || absorption.z < 0.0 {
+			return Err(MaterialError::NegativeAbsorption);
+		}
+
+		let total = self.albedo_diffuse + self.albedo_reflect + self.albedo_refract;
+		if total > 1.0 + 1e-9 {
+			return Err(MaterialError::EnergyGain(total));
+		}
+		Ok(())
+	}
+
+	/** The diffuse color at the point and texture coordinates, averaging the texture over the footprint if it is known */
+	pub fn diffuse_at(
+		&self,
+		point: &Vector,
+		uv: (f64, f64),
+		footprint: Option<(f64, f64)>,
+	) -> Vector {
+		let uv = self.texture_uv(uv);
+		let wrap = self.texture_wrap;
+		match (&self.diffuse_texture, footprint) {
+			(Some(image), Some((du, dv))) => {
+				let scale = self.texture_scale.abs();
+				image.sample_area(uv, (du * scale, dv * scale), wrap)
+			}
+			(Some(image), None) => image.sample(uv, wrap),
+			(None, _) => self.diffuse_color.color_at(point),
+		}
+	}
+
+	/** Coordinates at which the textures are sampled for the texture coordinates of a surface */
+	fn texture_uv(&self, (u, v): (f64, f64)) -> (f64, f64) {
+		(u * self.texture_scale, v * self.texture_scale)
+	}
+}
+
+/** A set of named materials, which objects in scene files and faces in OBJ files (through `usemtl`) can refer to by name
+instead of defining a material of their own. In a file, a library is a JSON object mapping names to materials. */
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct MaterialLibrary {
+	materials: HashMap<String, Arc<Material>>,
+}
+
+impl MaterialLibrary {
+	/** The material with the given name, if there is one */
+	pub fn get(&self, name: &str) -> Option<Arc<Material>> {
+		self.materials.get(name).cloned()
+	}
+
+	/** Add a material under the given name, replacing any material previously added under that name */
+	pub fn insert(&mut self, name: String, material: Arc<Material>) {
+		self.materials.insert(name, material);
+	}
+
+	/** Names and materials in this library, in no particular order */
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<Material>)> {
+		self.materials
+			.iter()
+			.map(|(name, material)| (name.as_str(), material))
+	}
+}
+
+/** Image surrounding the scene, seen in directions in which rays do not hit any object. */
+pub enum EnvironmentMap {
+	/** A single image, projected onto the directions according to the environment mapping */
+	Image(RadianceImage),
+
+	/** Images of the six faces of a cube around the scene, facing the positive and negative X, Y and Z axes */
+	CubeMap {
+		px: RadianceImage,
+		nx: RadianceImage,
+		py: RadianceImage,
+		ny: RadianceImage,
+		pz: RadianceImage,
+		nz: RadianceImage,
+	},
+}
+
+impl EnvironmentMap {
+	/** Radiance of the environment in the direction, projected onto a single image by the mapping */
+	pub fn sample(&self, direction: Vector, mapping: EnvironmentMapping) -> Vector {
+		match self {
+			EnvironmentMap::Image(image) => image.sample(mapping.uv(direction)),
+			EnvironmentMap::CubeMap {
+				px,
+				nx,
+				py,
+				ny,
+				pz,
+				nz,
+			} => {
+				// The face is determined by the dominant axis of the direction. Face coordinates follow the OpenGL
+				// cube map convention, as seen from inside the cube
+				let (ax, ay, az) = (direction.x.abs(), direction.y.abs(), direction.z.abs());
+				let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+					if direction.x > 0.0 {
+						(px, -direction.z, -direction.y, ax)
+					} else {
+						(nx, direction.z, -direction.y, ax)
+					}
+				} else if ay >= az {
+					if direction.y > 0.0 {
+						(py, direction.x, direction.z, ay)
+					} else {
+						(ny, direction.x, -direction.z, ay)
+					}
+				} else if direction.z > 0.0 {
+					(pz, direction.x, -direction.y, az)
+				} else {
+					(nz, -direction.x, -direction.y, az)
+				};
+				face.sample((0.5 * (sc / ma + 1.0), 0.5 * (tc / ma + 1.0)))
+			}
+		}
+	}
+}
+
+/** An image storing radiance as floating point colors, so that high dynamic range images keep their range. Like all
+colors used in rendering, the colors are linear: they are proportional to the amount of light. */
+pub struct RadianceImage {
+	width: u32,
+	height: u32,
+
+	/** Colors row by row, starting at the top left */
+	pixels: Vec<Vector>,
+}
+
+impl RadianceImage {
+	/** Load an image: Radiance HDR (.hdr) files as linear radiance, other images mapped to [0, 1] (see `from_image`) */
+	pub fn open<P: AsRef<Path>>(path: P, srgb: bool) -> ImageResult<RadianceImage> {
+		let path = path.as_ref();
+		let is_hdr = path
+			.extension()
+			.and_then(|e| e.to_str())
+			.is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+		if !is_hdr {
+			return Ok(RadianceImage::from_image(&image::open(path)?, srgb));
+		}
+
+		let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+		let metadata = decoder.metadata();
+		let pixels = decoder
+			.read_image_hdr()?
+			.into_iter()
+			.map(|color| Vector {
+				x: f64::from(color[0]),
+				y: f64::from(color[1]),
+				z: f64::from(color[2]),
+			})
+			.collect();
+		Ok(RadianceImage {
+			width: metadata.width,
+			height: metadata.height,
+			pixels,
+		})
+	}
+
+	/** Radiance image from a low dynamic range image, with colors mapped to [0, 1], converted to linear if they are sRGB */
+	pub fn from_image(image: &DynamicImage, srgb: bool) -> RadianceImage {
+		let pixels = image
+			.pixels()
+			.map(|(_, _, color)| {
+				let color = ldr_color(color);
+				if srgb {
+					Vector::new(
+						srgb_to_linear(color.x),
+						srgb_to_linear(color.y),
+						srgb_to_linear(color.z),
+					)
+				} else {
+					color
 				}
+			})
+			.collect();
+		RadianceImage {
+			width: image.width(),
+			height: image.height(),
+			pixels,
+		}
+	}
+
+	/** Bilinearly filtered radiance at the texture coordinates (in [0, 1], from the top left) */
+	pub fn sample(&self, uv: (f64, f64)) -> Vector {
+		self.sample_wrapped(uv, WrapMode::Clamp)
+	}
+
+	/** Bilinearly filtered radiance at the texture coordinates, which are wrapped when outside [0, 1] */
+	pub fn sample_wrapped(&self, uv: (f64, f64), wrap: WrapMode) -> Vector {
+		sample_bilinear(self.width, self.height, uv, wrap, |x, y| {
+			self.pixels[(y * self.width + x) as usize]
+		})
+	}
+
+	/** This image halved in size (rounding up), with each pixel the average of the (up to four) pixels it covers */
+	fn halved(&self) -> RadianceImage {
+		let width = self.width.div_ceil(2);
+		let height = self.height.div_ceil(2);
+		let texel = |x: u32, y: u32| {
+			self.pixels[(y.min(self.height - 1) * self.width + x.min(self.width - 1)) as usize]
+		};
+		let pixels = (0..height)
+			.flat_map(|y| (0..width).map(move |x| (x, y)))
+			.map(|(x, y)| {
+				(texel(2 * x, 2 * y)
+					+ texel(2 * x + 1, 2 * y)
+					+ texel(2 * x, 2 * y + 1)
+					+ texel(2 * x + 1, 2 * y + 1))
+					* 0.25
+			})
+			.collect();
+		RadianceImage {
+			width,
+			height,
+			pixels,
+		}
+	}
+}
+
+/** An image with versions of it halved in size until a single pixel remains (mipmaps), so that it can be averaged over
+areas of any size quickly, e.g. to prevent aliasing of textures seen from far away. */
+pub struct MipMap {
+	/** The image itself, followed by the mipmaps from large to small */
+	levels: Vec<RadianceImage>,
+}
+
+impl MipMap {
+	pub fn new(image: RadianceImage) -> MipMap {
+		let mut levels = vec![image];
+		loop {
+			let image = levels.last().unwrap();
+			if image.width == 1 && image.height == 1 {
+				return MipMap { levels };
 			}
+			let halved = image.halved();
+			levels.push(halved);
 		}
+	}
 
-		(min_dist, hit_object)
+	/** Bilinearly filtered radiance of the full size image at the texture coordinates (see `RadianceImage::sample`) */
+	pub fn sample(&self, uv: (f64, f64), wrap: WrapMode) -> Vector {
+		self.levels[0].sample_wrapped(uv, wrap)
 	}
 
+	/** Radiance averaged over an area of the given size around the texture coordinates, using trilinear filtering */
+	pub fn sample_area(&self, uv: (f64, f64), (du, dv): (f64, f64), wrap: WrapMode) -> Vector {
+		let image = &self.levels[0];
+		let texels = (du * f64::from(image.width)).max(dv * f64::from(image.height));
+		if texels.is_nan() || texels <= 1.0 {
+			return self.sample(uv, wrap);
+		}
+
+		// Blend bilinearly filtered samples from the two levels with pixels closest in size to the area
+		let top = self.levels.len() - 1;
+		let level = texels.log2().min(top as f64);
+		let lower = level.floor() as usize;
+		let color = self.levels[lower].sample_wrapped(uv, wrap);
+		if lower == top {
+			return color;
+		}
+		color.lerp(
+			self.levels[lower + 1].sample_wrapped(uv, wrap),
+			level.fract(),
+		)
+	}
+}
+
+/** Color of an 8-bit pixel, mapped to [0, 1] */
+fn ldr_color(color: image::Rgba<u8>) -> Vector {
+	Vector {
+		x: f64::from(color[0]) / 255.0,
+		y: f64::from(color[1]) / 255.0,
+		z: f64::from(color[2]) / 255.0,
+	}
+}
+
+/** Linear value of an sRGB encoded color component in [0, 1] */
+fn srgb_to_linear(value: f64) -> f64 {
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+/** Bilinearly filtered color at the texture coordinates (in [0, 1], from the top left, and wrapped outside it) of an
+image of the specified size, of which the texel function returns the color at integer coordinates. */
+fn sample_bilinear<F: Fn(u32, u32) -> Vector>(
+	width: u32,
+	height: u32,
+	(u, v): (f64, f64),
+	wrap: WrapMode,
+	texel: F,
+) -> Vector {
+	// Texel centers are at half-integer coordinates
+	let x = u * f64::from(width) - 0.5;
+	let y = v * f64::from(height) - 0.5;
+	let (x, y) = match wrap {
+		WrapMode::Clamp => (
+			x.clamp(0.0, f64::from(width - 1)),
+			y.clamp(0.0, f64::from(height - 1)),
+		),
+		WrapMode::Repeat | WrapMode::Mirror => (x, y),
+	};
+	let (x0, y0) = (x.floor(), y.floor());
+	let (fx, fy) = (x - x0, y - y0);
+
+	// The texels between which is interpolated may be on opposite edges of the texture when it is wrapped
+	let texel = |tx: f64, ty: f64| texel(wrap.texel(tx, width), wrap.texel(ty, height));
+	let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+	let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+	top.lerp(bottom, fy)
+}
+
+/** Describes where a ray hits an object, as computed by the object itself. */
+pub struct Hit {
+	pub distance: f64,
+
+	/** Unit surface normal at the hit point */
+	pub normal: Vector,
+
+	/** Texture coordinates at the hit point */
+	pub uv: (f64, f64),
+
+	/** Unit directions along the surface in which the u and v texture coordinates increase, if defined */
+	pub tangents: Option<(Vector, Vector)>,
+	pub material: Arc<Material>,
+
+	/** Identifier of the object that was hit, if it was given one */
+	pub object_id: Option<u32>,
+}
+
+impl Hit {
+	/** Normal used for shading, perturbed by the normal map of the material when it has one and tangents are known */
+	pub fn shading_normal(&self) -> Vector {
+		match (&self.material.normal_map, self.tangents) {
+			(Some(normal_map), Some((tangent, bitangent))) => {
+				// Colors map to tangent space components in [-1, 1]; +Y points up in the image, towards decreasing v
+				let color = sample_bilinear(
+					normal_map.width(),
+					normal_map.height(),
+					self.material.texture_uv(self.uv),
+					self.material.texture_wrap,
+					|x, y| ldr_color(normal_map.get_pixel(x, y)),
+				);
+				let local = color * 2.0 - Vector::new(1.0, 1.0, 1.0);
+				(tangent * local.x - bitangent * local.y + self.normal * local.z).normalize()
+			}
+			_ => self.normal,
+		}
+	}
+
+	/** The hit with its normal flipped to face the ray when the ray hits the back of the surface */
+	pub fn facing(mut self, ray_direction: Vector) -> Hit {
+		if (self.normal ^ ray_direction) > 0.0 {
+			self.normal = -self.normal;
+			self.tangents = self
+				.tangents
+				.map(|(tangent, bitangent)| (tangent, -bitangent));
+		}
+		self
+	}
+}
+
+pub trait Traceable: Send + Sync {
+	/** Find the nearest point in front of the ray where it hits this object */
+	fn intersect(&self, ray: &Ray) -> Option<Hit>;
+	fn aabb(&self) -> Aabb;
+
+	/** For solid objects, the parts of the line along the ray inside the object as entry and exit hits, ordered by distance (which is negative behind the ray origin); None for objects that do not enclose a volume */
+	fn intersect_intervals(&self, _ray: &Ray) -> Option<Vec<(Hit, Hit)>> {
+		None
+	}
+
+	/** A sphere containing the object; rays missing it are not tested against the object itself */
+	fn bounding_sphere(&self) -> BoundingSphere {
+		BoundingSphere::from_aabb(&self.aabb())
+	}
+
+	/** Whether the object is seen by camera, reflected and refracted rays */
+	fn visible(&self) -> bool {
+		true
+	}
+
+	/** Whether the object blocks light from reaching other objects */
+	fn casts_shadows(&self) -> bool {
+		true
+	}
+
+	/** Check that the shape of the object can be rendered (finite, with positive sizes and not degenerate) */
+	fn validate(&self) -> Result<(), GeometryError> {
+		Ok(())
+	}
+}
+
+impl Scene {
+	/** Check all objects and lights, listing those that cannot be rendered (objects and lights are numbered from zero). */
+	pub fn validate(&self) -> Result<(), InvalidScene> {
+		let objects = self
+			.objects
+			.objects()
+			.iter()
+			.enumerate()
+			.filter_map(|(index, object)| {
+				object
+					.validate()
+					.err()
+					.map(|error| (format!("object {}", index), error))
+			});
+		let lights = self.lights.iter().enumerate().filter_map(|(index, light)| {
+			light
+				.validate()
+				.err()
+				.map(|error| (format!("light {}", index), error))
+		});
+
+		let problems: Vec<(String, GeometryError)> = objects.chain(lights).collect();
+		if problems.is_empty() {
+			Ok(())
+		} else {
+			Err(InvalidScene { problems })
+		}
+	}
+
+	/** Add objects to the scene, rebuilding the bounding volume hierarchy (and grid, if used). */
+	pub fn add_objects(&mut self, objects: Vec<Arc<dyn Traceable>>) {
+		let mut all_objects = self.objects.objects().to_vec();
+		all_objects.extend(objects);
+		self.objects = Bvh::new(all_objects);
+		if let Some(grid) = &self.grid {
+			self.use_grid(grid.resolution())
+				.expect("resolution of the existing grid is valid");
+		}
+	}
+
+	/** Find objects hit by rays using a uniform grid with this many cells along each axis, instead of the hierarchy */
+	pub fn use_grid(&mut self, resolution: usize) -> Result<(), String> {
+		self.grid = Some(Grid::new(self.objects.objects().to_vec(), resolution)?);
+		Ok(())
+	}
+
+	/** Box containing all objects of finite size (unbounded objects such as planes are ignored), if there are any */
+	pub fn bounds(&self) -> Option<Aabb> {
+		self.objects
+			.objects()
+			.iter()
+			.map(|object| object.aabb())
+			.filter(Aabb::is_finite)
+			.reduce(|a, b| a.union(&b))
+	}
+
+	fn intersect(self: &Scene, ray: &Ray) -> Option<Hit> {
+		match &self.grid {
+			Some(grid) => grid.intersect(ray, self.max_distance),
+			None => self.intersect_hierarchy(ray, false),
+		}
+	}
+
+	fn intersect_shadow(self: &Scene, ray: &Ray) -> Option<Hit> {
+		self.count_ray(RayKind::Shadow);
+		match &self.grid {
+			Some(grid) => grid.intersect_shadow(ray, self.max_distance),
+			None => self.intersect_hierarchy(ray, true),
+		}
+	}
+
+	/** Find the nearest hit using the bounding volume hierarchy, counting the nodes visited when keeping statistics */
+	fn intersect_hierarchy(&self, ray: &Ray, shadow: bool) -> Option<Hit> {
+		let mut visits = 0;
+		let hit = self
+			.objects
+			.intersect_counting(ray, shadow, self.max_distance, &mut visits);
+		if let Some(statistics) = &self.statistics {
+			statistics.nodes.fetch_add(visits, Ordering::Relaxed);
+		}
+		hit
+	}
+
+	/** Size in texture coordinates of the area of a textured surface covered by a camera ray, for filtering the texture */
+	fn texture_footprint(&self, ray: &Ray, hit: &Hit) -> Option<(f64, f64)> {
+		// The size follows from the texture coordinates where the neighbouring rays hit the same surface, so it is not
+		// known at the edges of objects
+		hit.material.diffuse_texture.as_ref()?;
+		let (right, down) = ray.neighbours()?;
+		let right = self.intersect(&right)?;
+		let down = self.intersect(&down)?;
+		if !Arc::ptr_eq(&right.material, &hit.material)
+			|| !Arc::ptr_eq(&down.material, &hit.material)
+		{
+			return None;
+		}
+
+		let du = (right.uv.0 - hit.uv.0)
+			.abs()
+			.max((down.uv.0 - hit.uv.0).abs());
+		let dv = (right.uv.1 - hit.uv.1)
+			.abs()
+			.max((down.uv.1 - hit.uv.1).abs());
+		Some((du, dv))
+	}
+
+	/** Move the origin of a ray leaving the surface at point off the surface, to prevent it from hitting it again */
 	fn offset_orig(dir: Vector, point: Vector, n: Vector) -> Vector {
+		// Rounding errors grow with the magnitude of the coordinates, so the offset does as well
+		let magnitude = point.x.abs().max(point.y.abs()).max(point.z.abs()).max(1.0);
+		let offset = n * (OFFSET_EPSILON * magnitude);
 		if (dir ^ n) < 0.0 {
-			point - (n * 1e-3)
+			point - offset
 		} else {
-			point + (n * 1e-3)
+			point + offset
+		}
+	}
+
+	fn count_ray(&self, kind: RayKind) {
+		if let Some(statistics) = &self.statistics {
+			statistics.count(kind);
 		}
 	}
 
 	pub fn cast_ray(self: &Scene, ray: &Ray, depth: i32) -> Vector {
-		if depth > 0 {
-			let (min_dist, hit_object) = self.intersect(ray);
+		self.apply_fog(&self.cast_ray_components(ray, depth))
+	}
+
+	/** Components of the color seen along a camera ray (without fog, see `apply_fog`) */
+	pub fn cast_ray_components(self: &Scene, ray: &Ray, depth: i32) -> Components {
+		self.count_ray(RayKind::Primary);
+		self.trace(ray, depth, 1.0, &[])
+	}
+
+	/** The color seen along a camera ray with the given components, faded into the fog by the distance to the hit */
+	pub fn apply_fog(&self, components: &Components) -> Vector {
+		let color = components.total();
+		if self.fog_density <= 0.0 {
+			return color;
+		}
+
+		// Blend towards the fog color by the fraction of light scattered over the distance to the hit. Rays that do
+		// not hit anything go on indefinitely, and end up showing only fog.
+		self.fog_color
+			.lerp(color, (-components.distance * self.fog_density).exp())
+	}
+
+	/** Visualize a property of the first surface hit by the ray (None when nothing is hit) */
+	pub fn debug_ray(&self, ray: &Ray, mode: DebugMode) -> Option<Vector> {
+		self.count_ray(RayKind::Primary);
+		let hit = self.intersect(ray)?;
+
+		Some(match mode {
+			DebugMode::Normals => (hit.normal + Vector::new(1.0, 1.0, 1.0)) * 0.5,
+			DebugMode::Depth => {
+				let brightness = 1.0 / (1.0 + hit.distance / DEBUG_DEPTH_SCALE);
+				Vector::new(brightness, brightness, brightness)
+			}
+			DebugMode::Albedo => hit.material.diffuse_at(
+				&ray.extend(hit.distance),
+				hit.uv,
+				self.texture_footprint(ray, &hit),
+			),
+			DebugMode::ObjectId => hit.object_id.map_or(Vector::new(1.0, 1.0, 1.0), id_color),
+		})
+	}
+
+	/** Color seen along a secondary ray whose contribution to the pixel is scaled by throughput */
+	fn trace_secondary(
+		&self,
+		ray: &Ray,
+		kind: RayKind,
+		depth: i32,
+		throughput: f64,
+		media: &[&Material],
+	) -> Vector {
+		// Rays that do not contribute to the pixel (e.g. reflections off non-reflective surfaces) are not traced
+		if throughput <= 0.0 {
+			return Vector::default();
+		}
+		if self.russian_roulette && throughput < ROULETTE_THRESHOLD {
+			// Survivors are weighted by the inverse of their survival probability to keep the result unbiased
+			let survival = throughput / ROULETTE_THRESHOLD;
+			if survival <= 0.0 || sampling::random() >= survival {
+				return Vector::default();
+			}
+			self.count_ray(kind);
+			return self.trace(ray, depth, ROULETTE_THRESHOLD, media).total() / survival;
+		}
+		self.count_ray(kind);
+		self.trace(ray, depth, throughput, media).total()
+	}
 
+	/** Color seen along the ray, given the product of the albedos on the path and the materials of enclosing media */
+	fn trace(
+		self: &Scene,
+		ray: &Ray,
+		depth: i32,
+		throughput: f64,
+		media: &[&Material],
+	) -> Components {
+		if depth > 0 {
 			// Render pixel
-			if let Some(object) = hit_object {
-				let material = object.material();
-				let point = ray.extend(min_dist);
-				let normal = object.normal_at(&point).normalize();
+			if let Some(hit) = self.intersect(ray) {
+				// The geometric normal determines which side of the surface rays are on, the shading normal how it is lit
+				let shading_normal = hit.shading_normal();
+				let footprint = self.texture_footprint(ray, &hit);
+				let material = hit.material;
+				let point = ray.extend(hit.distance);
+				let normal = hit.normal;
 				let mut diffuse_intensity = 0.0;
 				let mut specular_intensity = 0.0;
 
 				// Determine total light intensity
 				for light in &self.lights {
-					let light_direction = (light.position - point).normalize();
-
-					// Shadow
-					let light_distance = (light.position - point).norm();
-					let shadow_origin = Scene::offset_orig(light_direction, point, normal);
-
-					let (shadow_distance, shadow_obstacle) =
-						self.intersect(&Ray::new(shadow_origin, light_direction));
-					if shadow_obstacle.is_none() || shadow_distance > light_distance {
-						// Light is not occluded
-						diffuse_intensity += light.intensity * (light_direction ^ normal).max(0.0);
-						let specularity = (((light_direction * -1.0).reflect(normal) * -1.0)
-							^ ray.direction())
-						.max(0.0)
-						.powf(material.specular_exponent);
-						specular_intensity += specularity * light.intensity;
+					// Each sample on the light contributes an equal part of its intensity
+					let samples = light.samples_from(point);
+					let sample_intensity = light.intensity() / samples.len() as f64;
+
+					for (light_direction, light_distance_squared) in samples {
+						let intensity = sample_intensity
+							* light.attenuation(light_direction, light_distance_squared);
+						if intensity <= 0.0 {
+							continue;
+						}
+
+						// Shadow
+						let shadow_origin = Scene::offset_orig(light_direction, point, normal);
+
+						let shadow_hit = self.intersect_shadow(
+							&Ray::new(shadow_origin, light_direction).at_time(ray.time()),
+						);
+						if shadow_hit
+							.is_none_or(|h| h.distance * h.distance > light_distance_squared)
+						{
+							// Light is not occluded
+							diffuse_intensity +=
+								intensity * (light_direction ^ shading_normal).max(0.0);
+							let specularity = material
+								.specular_model
+								.cosine(light_direction, ray.direction(), shading_normal)
+								.max(0.0)
+								.powf(material.specular_exponent);
+							specular_intensity += specularity * intensity;
+						}
+					}
+				}
+				let surface_color = material.diffuse_at(&point, hit.uv, footprint);
+				let diffuse_color = surface_color * diffuse_intensity * material.albedo_diffuse;
+				let ambient_color = self.ambient.mul_components(&surface_color);
+				let specular_color =
+					material.specular_color * specular_intensity * material.albedo_specular;
+
+				let facing_normal = if (ray.direction() ^ normal) > 0.0 {
+					-normal
+				} else {
+					normal
+				};
+
+				// Global illumination: follow a single path in a random direction, sampled proportionally to the
+				// cosine factor, which reaches the environment when nothing is hit (so it replaces image based lighting)
+				let indirect_color = if self.global_illumination && material.albedo_diffuse > 0.0 {
+					let direction = sampling::cosine_hemisphere(facing_normal);
+					let origin = Scene::offset_orig(facing_normal, point, facing_normal);
+					let radiance = self.trace_secondary(
+						&Ray::new(origin, direction).at_time(ray.time()),
+						RayKind::Indirect,
+						depth - 1,
+						throughput * material.albedo_diffuse,
+						media,
+					);
+					radiance.mul_components(&surface_color) * material.albedo_diffuse
+				} else if self.environment_samples > 0 && material.albedo_diffuse > 0.0 {
+					// Image based lighting: light from the environment reaching the surface unoccluded
+					let origin = Scene::offset_orig(facing_normal, point, facing_normal);
+
+					// Directions are sampled proportionally to the cosine factor, so samples are weighed equally
+					let mut radiance = Vector::default();
+					for _ in 0..self.environment_samples {
+						let direction = sampling::cosine_hemisphere(facing_normal);
+						if self
+							.intersect_shadow(&Ray::new(origin, direction).at_time(ray.time()))
+							.is_none()
+						{
+							radiance += self.env_sample(direction);
+						}
+					}
+					radiance = radiance / f64::from(self.environment_samples);
+
+					radiance.mul_components(&surface_color) * material.albedo_diffuse
+				} else {
+					Vector::default()
+				};
+
+				// A ray traveling along the normal leaves the object, returning to the medium surrounding it. Media
+				// are listed from outer to inner, so the current medium is the last
+				let exiting = (ray.direction() ^ normal) > 0.0;
+				let outer_media = &media[..media.len().saturating_sub(1)];
+				let (eta_incident, eta_transmitted) = if exiting {
+					(
+						material.refractive_index,
+						outer_media
+							.last()
+							.map_or(1.0, |medium| medium.refractive_index),
+					)
+				} else {
+					(
+						media.last().map_or(1.0, |medium| medium.refractive_index),
+						material.refractive_index,
+					)
+				};
+
+				// Part of the light that would be refracted is reflected instead, depending on the angle of incidence
+				let reflectance = if material.albedo_refract > 0.0 {
+					fresnel(ray.direction(), normal, eta_incident, eta_transmitted)
+				} else {
+					0.0
+				};
+				let albedo_reflect =
+					material.albedo_reflect + material.albedo_refract * reflectance;
+				let albedo_refract = material.albedo_refract * (1.0 - reflectance);
+
+				// Reflection, in a direction spread randomly around the mirrored direction on rough surfaces
+				let mut reflect_direction = ray.direction().reflect(shading_normal).normalize();
+				if material.roughness > 0.0 && albedo_reflect > 0.0 {
+					let glossy = (reflect_direction
+						+ sampling::in_unit_sphere() * material.roughness)
+						.normalize();
+
+					// Directions into the surface are not spread
+					if (glossy ^ normal) * (reflect_direction ^ normal) > 0.0 {
+						reflect_direction = glossy;
 					}
 				}
-				let diffuse_color =
-					material.diffuse_color * diffuse_intensity * material.albedo_diffuse;
-				let specular_color = Vector {
-					x: 1.0,
-					y: 1.0,
-					z: 1.0,
-				} * specular_intensity
-					* material.albedo_specular;
-
-				// Reflection
-				let reflect_direction = ray.direction().reflect(normal).normalize();
 				let reflect_origin = Scene::offset_orig(reflect_direction, point, normal);
-				let reflect_color = self
-					.cast_ray(&Ray::new(reflect_origin, reflect_direction), depth - 1)
-					* material.albedo_reflect;
+				let reflect_color = self.trace_secondary(
+					&Ray::new(reflect_origin, reflect_direction).at_time(ray.time()),
+					RayKind::Reflection,
+					depth - 1,
+					throughput * albedo_reflect,
+					media,
+				) * albedo_reflect;
 
 				// Refraction
 				let refract_direction = ray
 					.direction()
-					.refract(normal, material.refractive_index)
+					.refract(normal, eta_incident, eta_transmitted)
 					.normalize();
 				let refract_origin = Scene::offset_orig(refract_direction, point, normal);
-				let refract_color = self
-					.cast_ray(&Ray::new(refract_origin, refract_direction), depth - 1)
-					* material.albedo_refract;
+
+				// The refracted ray enters or leaves the object, unless it was reflected internally
+				let inner_media;
+				let refract_media = if (refract_direction ^ normal > 0.0) != exiting {
+					media
+				} else if exiting {
+					outer_media
+				} else {
+					inner_media = [media, &[material.as_ref()]].concat();
+					&inner_media
+				};
+				let refract_color = self.trace_secondary(
+					&Ray::new(refract_origin, refract_direction).at_time(ray.time()),
+					RayKind::Refraction,
+					depth - 1,
+					throughput * albedo_refract,
+					refract_media,
+				) * albedo_refract;
 
 				// Determine lit pixel color
-				return diffuse_color + specular_color + reflect_color + refract_color;
+				let components = Components {
+					diffuse: ambient_color + diffuse_color + indirect_color,
+					specular: specular_color,
+					reflection: reflect_color + refract_color,
+					emission: material.emission,
+					distance: hit.distance,
+				};
+
+				// The ray travels to the hit through the innermost medium, which absorbs part of the light (Beer's law)
+				return match media.last() {
+					Some(medium) if medium.absorption.norm_squared() > 0.0 => {
+						let absorption = medium.absorption * -hit.distance;
+						components.attenuated(Vector::new(
+							absorption.x.exp(),
+							absorption.y.exp(),
+							absorption.z.exp(),
+						))
+					}
+					_ => components,
+				};
 			}
 		}
 
 		// Environment
-		let env_dir = ray.direction();
+		Components {
+			emission: self.env_sample(ray.direction()),
+			distance: f64::INFINITY,
+			..Components::default()
+		}
+	}
+
+	/** Color of the environment in the specified direction, bilinearly filtered from the environment map if present. */
+	pub fn env_sample(&self, direction: Vector) -> Vector {
 		match &self.environment_map {
-			Some(image) => {
-				let ew = f64::from(image.width());
-				let eh = f64::from(image.height());
+			Some(map) => map.sample(direction, self.environment_mapping),
+			None => self.background.color_in(direction),
+		}
+	}
+}
 
-				// Spherical
-				/*let m = env_dir.x.powf(2.0) + env_dir.y.powf(2.0) + (env_dir.z + 1.0).powf(2.0);
-				let ex = (((env_dir.x / m) / 2.0 + 0.5) * ew) as u32;
-				let ey = (((-env_dir.y / m) / 2.0 + 0.5) * eh) as u32;*/
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
 
-				// https://stackoverflow.com/questions/39283698/direction-to-environment-map-uv-coordinates
-				let m = env_dir.norm() * 2.0;
-				let ex = ((-env_dir.z / m + 0.5) * ew) as u32;
-				let ey = ((-env_dir.y / m + 0.5) * eh) as u32;
-				let color = image.get_pixel(
-					ex.min(image.width() - 1).max(0),
-					ey.min(image.height() - 1).max(0),
-				);
-				Vector {
-					x: f64::from(color[0]) / 255.0,
-					y: f64::from(color[1]) / 255.0,
-					z: f64::from(color[2]) / 255.0,
+	/** A diffuse material of the given color, without highlights, reflection, refraction or emission */
+	pub(crate) fn diffuse(color: Vector) -> Material {
+		Material {
+			diffuse_color: Texture::Solid(color),
+			..Default::default()
+		}
+	}
+
+	/** Load one of the scene files in the scenes directory */
+	pub(crate) fn scene_file(name: &str) -> Scene {
+		let path = format!("{}/scenes/{}", env!("CARGO_MANIFEST_DIR"), name);
+		crate::description::load_scene(&path).expect("could not load scene")
+	}
+
+	/** Number of points in the penumbra along a line on the floor through the shadow of the sphere above it */
+	fn penumbra_width(light_radius: f64) -> usize {
+		let mut scene = scene_file("soft_shadows.json");
+		if let Light::Area { radius, .. } = &mut scene.lights[0] {
+			*radius = light_radius;
+		}
+
+		// Brightness of floor points seen from straight above, between the floor and the sphere
+		let brightness: Vec<f64> = (0..600)
+			.map(|i| {
+				let origin = Vector::new(-3.0 + f64::from(i) * 0.01, 0.5, -6.0);
+				scene
+					.cast_ray(&Ray::new(origin, Vector::new(0.0, -1.0, 0.0)), 1)
+					.x
+			})
+			.collect();
+		let lit = brightness.iter().cloned().fold(0.0, f64::max);
+		brightness
+			.iter()
+			.filter(|&&b| b > 0.05 * lit && b < 0.95 * lit)
+			.count()
+	}
+
+	#[test]
+	fn shadow_softness_grows_with_light_size() {
+		let small = penumbra_width(0.25);
+		let large = penumbra_width(1.5);
+		assert!(small > 0);
+		assert!(large > 2 * small);
+	}
+
+	/** A scene with the objects and lights, without ambient light and with a black background */
+	pub(crate) fn scene(objects: Vec<Arc<dyn Traceable>>, lights: Vec<Light>) -> Scene {
+		Scene {
+			objects: Bvh::new(objects),
+			grid: None,
+			lights,
+			background: Background::Solid(Vector::default()),
+			environment_map: None,
+			environment_mapping: EnvironmentMapping::Planar,
+			ambient: Vector::default(),
+			russian_roulette: false,
+			environment_samples: 0,
+			global_illumination: false,
+			max_distance: f64::INFINITY,
+			fog_color: Vector::default(),
+			fog_density: 0.0,
+			statistics: None,
+		}
+	}
+
+	/** A white floor facing upwards at y = 0 */
+	pub(crate) fn floor() -> Arc<dyn Traceable> {
+		Arc::new(crate::primitives::Plane {
+			point: Vector::default(),
+			normal: Vector::new(0.0, 1.0, 0.0),
+			double_sided: false,
+			material: Arc::new(diffuse(white())),
+		})
+	}
+
+	/** Brightness (of the red channel) of the point on the floor at (x, z), seen from straight above */
+	pub(crate) fn floor_brightness(scene: &Scene, x: f64, z: f64) -> f64 {
+		let ray = Ray::new(Vector::new(x, 1.0, z), Vector::new(0.0, -1.0, 0.0));
+		scene.cast_ray(&ray, 1).x
+	}
+
+	fn spot(inner_angle: f64, outer_angle: f64) -> Light {
+		Light::Spot {
+			position: Vector::new(0.0, 5.0, 0.0),
+			direction: Vector::new(0.0, -1.0, 0.0),
+			inner_angle,
+			outer_angle,
+			intensity: 1.0,
+			falloff: false,
+		}
+	}
+
+	#[test]
+	fn spot_light_falls_off_at_cone_boundary() {
+		let (inner, outer) = (20.0_f64.to_radians(), 30.0_f64.to_radians());
+		let scene = scene(vec![floor()], vec![spot(inner, outer)]);
+
+		// Brightness on the floor at an angle from the spot direction, relative to that of an unrestricted light
+		let relative = |degrees: f64| {
+			let angle = f64::to_radians(degrees);
+			floor_brightness(&scene, 5.0 * angle.tan(), 0.0) / angle.cos()
+		};
+		assert!((relative(0.0) - 1.0).abs() < 1e-9);
+		assert!((relative(19.0) - 1.0).abs() < 1e-9);
+		let middle = relative(25.0);
+		assert!(middle > 0.1 && middle < 0.9);
+		assert!(relative(29.0) < middle);
+		assert_eq!(relative(31.0), 0.0);
+	}
+
+	#[test]
+	fn spot_light_cone_angles_are_validated() {
+		assert!(spot(0.3, 0.5).validate().is_ok());
+		assert!(matches!(
+			spot(0.5, 0.3).validate(),
+			Err(GeometryError::InvalidConeAngles { .. })
+		));
+	}
+
+	#[test]
+	fn emissive_object_is_seen_without_lights() {
+		let sphere = |material: Material| -> Arc<dyn Traceable> {
+			Arc::new(crate::primitives::Sphere {
+				center: Vector::new(0.0, 0.0, -5.0),
+				radius: 1.0,
+				material: Arc::new(material),
+			})
+		};
+		let ray = Ray::new(Vector::default(), Vector::new(0.0, 0.0, -1.0));
+		let lamp = Material {
+			emission: Vector::new(2.0, 1.0, 0.5),
+			..diffuse(white())
+		};
+		let color = scene(vec![sphere(lamp)], vec![]).cast_ray(&ray, 4);
+		assert_eq!(color, Vector::new(2.0, 1.0, 0.5));
+
+		// Objects that do not emit light are black without lights
+		let color = scene(vec![sphere(diffuse(white()))], vec![]).cast_ray(&ray, 4);
+		assert_eq!(color, Vector::default());
+	}
+
+	#[test]
+	fn environment_is_interpolated_between_texels() {
+		let image = RadianceImage {
+			width: 2,
+			height: 1,
+			pixels: vec![Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0)],
+		};
+
+		// At the texel centers, the colors themselves are found
+		assert_eq!(image.sample((0.25, 0.5)), Vector::new(1.0, 0.0, 0.0));
+		assert_eq!(image.sample((0.75, 0.5)), Vector::new(0.0, 0.0, 1.0));
+
+		// Straight ahead lies halfway between the texel centers
+		let mut scene = scene(vec![], vec![]);
+		scene.environment_map = Some(EnvironmentMap::Image(image));
+		scene.environment_mapping = EnvironmentMapping::Equirectangular;
+		let color = scene.env_sample(Vector::new(0.0, 0.0, -1.0));
+		assert!(color.distance(&Vector::new(0.5, 0.0, 0.5)) < 1e-9);
+	}
+
+	/** A clear material refracting all light, with the given refractive index */
+	pub(crate) fn glass(refractive_index: f64) -> Material {
+		Material {
+			albedo_diffuse: 0.0,
+			albedo_refract: 1.0,
+			refractive_index,
+			..diffuse(white())
+		}
+	}
+
+	fn sphere(radius: f64, material: Material) -> Arc<dyn Traceable> {
+		Arc::new(crate::primitives::Sphere {
+			center: Vector::new(0.0, 0.0, -5.0),
+			radius,
+			material: Arc::new(material),
+		})
+	}
+
+	#[test]
+	fn concentric_glass_spheres_track_the_medium() {
+		// The background varies with the direction, so that bending of the rays changes the color seen
+		let color_through = |objects: Vec<Arc<dyn Traceable>>| {
+			let mut scene = scene(objects, vec![]);
+			scene.background = Background::Gradient {
+				bottom: Vector::default(),
+				top: white(),
+			};
+			let ray = Ray::new(Vector::default(), Vector::new(0.0, 0.25, -1.0).normalize());
+			scene.cast_ray(&ray, 10)
+		};
+		let single = color_through(vec![sphere(2.0, glass(1.5))]);
+
+		// Inside glass, the surface of a sphere of the same glass does not bend rays, as there is no change in index
+		let nested = color_through(vec![sphere(2.0, glass(1.5)), sphere(1.0, glass(1.5))]);
+		assert!(single.distance(&nested) < 1e-3);
+
+		// A sphere of air inside the glass does bend them
+		let bubble = color_through(vec![sphere(2.0, glass(1.5)), sphere(1.0, glass(1.0))]);
+		assert!(single.distance(&bubble) > 0.01);
+	}
+
+	#[test]
+	fn far_away_sphere_has_no_shadow_acne() {
+		// The sphere is so far from the origin that rounding errors in hit points exceed any fixed offset of 1e-3
+		let scene = scene_file("far_sphere.json");
+		let origin = Vector::new(1.0e14, 0.0, -1.0e14 + 40.0);
+		let light = Vector::new(1.0, 1.0, 1.0).normalize();
+
+		// Every lit point on the sphere should show plain Lambertian shading, without speckles of self-shadowing
+		let mut lit = 0;
+		for i in -20..=20 {
+			for j in -20..=20 {
+				let target = Vector::new(1.0e14 + f64::from(i) * 0.5, f64::from(j) * 0.5, -1.0e14);
+				let ray = Ray::new(origin, (target - origin).normalize());
+				let Some(hit) = scene.objects.intersect(&ray, f64::INFINITY) else {
+					continue;
+				};
+				let cosine = hit.normal ^ light;
+				if cosine > 0.05 {
+					lit += 1;
+					assert!((scene.cast_ray(&ray, 1).x - 0.8 * cosine).abs() < 1e-6);
 				}
 			}
-			None => self.environment_color,
 		}
+		assert!(lit > 100);
+	}
+
+	#[test]
+	fn global_illumination_bleeds_wall_colors() {
+		let mut scene = scene_file("cornell.json");
+
+		// Average color of the white floor near one of the walls, seen from straight above
+		let floor_color = |scene: &Scene, x: f64| {
+			let ray = Ray::new(Vector::new(x, 0.0, -8.0), Vector::new(0.0, -1.0, 0.0));
+			(0..4000).fold(Vector::default(), |sum, _| sum + scene.cast_ray(&ray, 4)) / 4000.0
+		};
+
+		// Direct light leaves the floor gray
+		let direct = [floor_color(&scene, -4.5), floor_color(&scene, 4.5)];
+		assert!(direct.iter().all(|c| (c.x - c.y).abs() < 0.01 * c.x));
+
+		// Light reflected off the red (left) and green (right) walls brightens and tints the floor next to them
+		scene.global_illumination = true;
+		let (left, right) = (floor_color(&scene, -4.5), floor_color(&scene, 4.5));
+		assert!(left.x > 1.1 * left.y && left.x > direct[0].x);
+		assert!(right.y > 1.1 * right.x && right.y > direct[1].y);
+	}
+
+	#[test]
+	fn normal_map_changes_diffuse_shading() {
+		// Brightness of a quad lit from straight above, with a normal map of a single color
+		let brightness = |normal_color: [u8; 3]| {
+			let normal_map = image::RgbImage::from_pixel(2, 2, image::Rgb(normal_color));
+			let material = Material {
+				normal_map: Some(Arc::new(DynamicImage::ImageRgb8(normal_map))),
+				..diffuse(white())
+			};
+			let quad = crate::primitives::Quad {
+				origin: Vector::new(-1.0, 0.0, 1.0),
+				u: Vector::new(2.0, 0.0, 0.0),
+				v: Vector::new(0.0, 0.0, -2.0),
+				double_sided: false,
+				material: Arc::new(material),
+			};
+			let light = Light::Directional {
+				direction: Vector::new(0.0, -1.0, 0.0),
+				intensity: 1.0,
+			};
+			floor_brightness(&scene(vec![Arc::new(quad)], vec![light]), 0.0, 0.0)
+		};
+
+		// A normal map pointing straight out of the surface leaves the shading as it is
+		assert!((brightness([128, 128, 255]) - 1.0).abs() < 1e-3);
+
+		// One tilted by 45 degrees towards the tangent receives less light
+		let tilted = brightness([218, 128, 218]);
+		assert!((tilted - 0.5_f64.sqrt()).abs() < 0.01);
+	}
+
+	#[test]
+	fn cube_map_faces_are_centered_on_the_axes() {
+		// Faces of 3x3 pixels of a single color, except for the +X face, where the color varies with the position
+		let face = |color: Vector| RadianceImage {
+			width: 3,
+			height: 3,
+			pixels: vec![color; 9],
+		};
+		let px = RadianceImage {
+			width: 3,
+			height: 3,
+			pixels: (0..9)
+				.map(|i| Vector::new(f64::from(i % 3), f64::from(i / 3), 1.0) * 0.5)
+				.collect(),
+		};
+		let map = EnvironmentMap::CubeMap {
+			px,
+			nx: face(Vector::new(0.0, 0.0, 1.0)),
+			py: face(Vector::new(0.0, 1.0, 0.0)),
+			ny: face(Vector::new(0.0, 1.0, 1.0)),
+			pz: face(Vector::new(1.0, 0.0, 0.0)),
+			nz: face(Vector::new(1.0, 0.0, 1.0)),
+		};
+		let sample =
+			|x: f64, y: f64, z: f64| map.sample(Vector::new(x, y, z), EnvironmentMapping::Planar);
+
+		// The center of the +X face is its center pixel
+		assert_eq!(sample(1.0, 0.0, 0.0), Vector::new(0.5, 0.5, 0.5));
+
+		// Seen from inside the cube looking along +X, -Z is to the right and -Y is down in the image
+		assert!(sample(1.0, 0.0, -0.3).x > 0.5 && sample(1.0, 0.0, 0.3).x < 0.5);
+		assert!(sample(1.0, -0.3, 0.0).y > 0.5 && sample(1.0, 0.3, 0.0).y < 0.5);
+
+		assert_eq!(sample(-1.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+		assert_eq!(sample(0.0, 1.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+		assert_eq!(sample(0.0, -1.0, 0.0), Vector::new(0.0, 1.0, 1.0));
+		assert_eq!(sample(0.0, 0.0, 1.0), Vector::new(1.0, 0.0, 0.0));
+		assert_eq!(sample(0.0, 0.0, -1.0), Vector::new(1.0, 0.0, 1.0));
+	}
+
+	#[test]
+	fn specular_highlight_takes_specular_color() {
+		let material = Material {
+			specular_color: Vector::new(1.0, 0.2, 0.0),
+			specular_exponent: 50.0,
+			albedo_diffuse: 0.5,
+			albedo_specular: 1.0,
+			..diffuse(white())
+		};
+		let floor = crate::primitives::Plane {
+			point: Vector::default(),
+			normal: Vector::new(0.0, 1.0, 0.0),
+			double_sided: false,
+			material: Arc::new(material),
+		};
+		let light = Light::Directional {
+			direction: Vector::new(0.0, -1.0, 0.0),
+			intensity: 1.0,
+		};
+		let scene = scene(vec![Arc::new(floor)], vec![light]);
+
+		// Seen from straight above, the light is reflected towards the eye: a white diffuse and a tinted highlight
+		let ray = Ray::new(Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+		let color = scene.cast_ray(&ray, 1);
+		assert!(color.distance(&Vector::new(1.5, 0.7, 0.5)) < 1e-9);
+	}
+
+	#[test]
+	fn fog_hides_far_objects_more_than_near_ones() {
+		let lit = Material {
+			emission: white(),
+			..diffuse(white())
+		};
+		let near = crate::primitives::Sphere {
+			center: Vector::new(-1.0, 0.0, -3.0),
+			radius: 1.0,
+			material: Arc::new(lit.clone()),
+		};
+		let far = crate::primitives::Sphere {
+			center: Vector::new(10.0, 0.0, -31.0),
+			radius: 1.0,
+			material: Arc::new(lit),
+		};
+		let mut scene = scene(vec![Arc::new(near), Arc::new(far)], vec![]);
+		scene.fog_color = Vector::new(0.5, 0.5, 0.5);
+		scene.fog_density = 0.05;
+
+		let seen = |target: Vector| {
+			let ray = Ray::new(Vector::default(), target.normalize());
+			let components = scene.cast_ray_components(&ray, 1);
+			(components.distance, scene.apply_fog(&components).x)
+		};
+
+		// Colors fade from white to the fog color exponentially with the distance
+		let (near_distance, near) = seen(Vector::new(-1.0, 0.0, -3.0));
+		let (far_distance, far) = seen(Vector::new(10.0, 0.0, -31.0));
+		assert!(far_distance > 5.0 * near_distance);
+		assert!((near - (0.5 + 0.5 * (-0.05 * near_distance).exp())).abs() < 1e-9);
+		assert!((far - (0.5 + 0.5 * (-0.05 * far_distance).exp())).abs() < 1e-9);
+		assert!(near > far && far > 0.5);
+
+		// Nothing is hit, so only fog is seen
+		assert_eq!(seen(Vector::new(0.0, 1.0, 0.0)).1, 0.5);
+	}
+
+	#[test]
+	fn blinn_phong_highlight_is_wider_than_phong() {
+		// Light 30 degrees from the normal, seen from 50 degrees on the other side, 20 degrees from the mirror direction
+		let normal = Vector::new(0.0, 1.0, 0.0);
+		let (light, view) = (30.0_f64.to_radians(), 50.0_f64.to_radians());
+		let light_direction = Vector::new(-light.sin(), light.cos(), 0.0);
+		let ray_direction = -Vector::new(view.sin(), view.cos(), 0.0);
+
+		// The half vector is off the normal by half the angle between the view and mirror directions
+		let phong = SpecularModel::Phong.cosine(light_direction, ray_direction, normal);
+		let blinn = SpecularModel::BlinnPhong.cosine(light_direction, ray_direction, normal);
+		assert!((phong - 20.0_f64.to_radians().cos()).abs() < 1e-9);
+		assert!((blinn - 10.0_f64.to_radians().cos()).abs() < 1e-9);
+
+		// In the mirror direction, both are at their maximum
+		let mirror = -Vector::new(light.sin(), light.cos(), 0.0);
+		assert!((SpecularModel::Phong.cosine(light_direction, mirror, normal) - 1.0).abs() < 1e-9);
+		assert!(
+			(SpecularModel::BlinnPhong.cosine(light_direction, mirror, normal) - 1.0).abs() < 1e-9
+		);
+	}
+
+	#[test]
+	fn srgb_textures_are_linearized_before_shading() {
+		let gray = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([128; 3])));
+		let linear = RadianceImage::from_image(&gray, true);
+		assert!((linear.sample((0.5, 0.5)).x - 0.2158).abs() < 1e-4);
+		let unconverted = RadianceImage::from_image(&gray, false);
+		assert!((unconverted.sample((0.5, 0.5)).x - 128.0 / 255.0).abs() < 1e-9);
+
+		// A white light from straight above shows the linear color of the texture
+		let material = Material {
+			diffuse_texture: Some(Arc::new(MipMap::new(linear))),
+			..diffuse(white())
+		};
+		let quad = crate::primitives::Quad {
+			origin: Vector::new(-1.0, 0.0, 1.0),
+			u: Vector::new(2.0, 0.0, 0.0),
+			v: Vector::new(0.0, 0.0, -2.0),
+			double_sided: false,
+			material: Arc::new(material),
+		};
+		let light = Light::Directional {
+			direction: Vector::new(0.0, -1.0, 0.0),
+			intensity: 1.0,
+		};
+		let scene = scene(vec![Arc::new(quad)], vec![light]);
+		assert!((floor_brightness(&scene, 0.0, 0.0) - 0.2158).abs() < 1e-4);
+	}
+
+	#[test]
+	fn point_light_falls_off_with_squared_distance() {
+		// Brightness of the floor straight below a point light at the given height
+		let brightness = |height: f64, falloff: bool| {
+			let light = Light::Point {
+				position: Vector::new(0.0, height, 0.0),
+				intensity: 100.0,
+				falloff,
+			};
+			floor_brightness(&scene(vec![floor()], vec![light]), 0.0, 0.0)
+		};
+		assert!((brightness(4.0, true) / brightness(2.0, true) - 0.25).abs() < 1e-6);
+		assert!((brightness(2.0, true) - 25.0).abs() < 1e-6);
+
+		// Without falloff, the light reaches all distances undimmed
+		assert_eq!(brightness(4.0, false), brightness(2.0, false));
+	}
+
+	#[test]
+	fn roughness_spreads_reflections() {
+		// Spread of the colors reflected by a mirror floor of the given roughness, of a background varying with height
+		let spread = |roughness: f64| {
+			let mirror = Material {
+				albedo_diffuse: 0.0,
+				albedo_reflect: 1.0,
+				roughness,
+				..diffuse(white())
+			};
+			let floor = crate::primitives::Plane {
+				point: Vector::default(),
+				normal: Vector::new(0.0, 1.0, 0.0),
+				double_sided: false,
+				material: Arc::new(mirror),
+			};
+			let mut scene = scene(vec![Arc::new(floor)], vec![]);
+			scene.background = Background::Gradient {
+				bottom: Vector::default(),
+				top: white(),
+			};
+			let ray = Ray::new(
+				Vector::new(0.0, 1.0, 0.0),
+				Vector::new(0.0, -1.0, -1.0).normalize(),
+			);
+			let colors: Vec<f64> = (0..1000).map(|_| scene.cast_ray(&ray, 2).x).collect();
+			let mean = colors.iter().sum::<f64>() / 1000.0;
+			let (min, max) = colors
+				.iter()
+				.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &c| {
+					(min.min(c), max.max(c))
+				});
+			(mean, max - min)
+		};
+
+		// A smooth mirror reflects a single direction; rougher ones reflect more widely spread directions
+		let (sharp, none) = spread(0.0);
+		assert_eq!(none, 0.0);
+		let (_, slight) = spread(0.1);
+		let (glossy, wide) = spread(0.4);
+		assert!(slight > 0.01 && wide > 2.0 * slight);
+		assert!((glossy - sharp).abs() < 0.1);
+	}
+
+	#[test]
+	fn validation_reports_each_kind_of_problem() {
+		use crate::primitives::{Sphere, Triangle};
+		let material = Arc::new(diffuse(white()));
+		let sphere = |center: Vector, radius: f64| -> Arc<dyn Traceable> {
+			Arc::new(Sphere {
+				center,
+				radius,
+				material: material.clone(),
+			})
+		};
+		let triangle = |c: Vector| -> Arc<dyn Traceable> {
+			Arc::new(Triangle {
+				a: Vector::default(),
+				b: Vector::new(1.0, 0.0, 0.0),
+				c,
+				normals: None,
+				double_sided: false,
+				material: material.clone(),
+			})
+		};
+		let spot = |direction: Vector, inner_angle: f64, outer_angle: f64| Light::Spot {
+			position: Vector::default(),
+			direction,
+			inner_angle,
+			outer_angle,
+			intensity: 1.0,
+			falloff: false,
+		};
+
+		let scene = scene(
+			vec![
+				sphere(Vector::default(), 1.0),
+				sphere(Vector::new(f64::NAN, 0.0, 0.0), 1.0),
+				sphere(Vector::default(), 0.0),
+				triangle(Vector::new(2.0, 0.0, 0.0)),
+				triangle(Vector::new(0.0, 1.0, 0.0)),
+			],
+			vec![
+				spot(Vector::new(0.0, -1.0, 0.0), 0.2, 0.4),
+				spot(Vector::default(), 0.2, 0.4),
+				spot(Vector::new(0.0, -1.0, 0.0), 0.4, 0.2),
+				Light::Point {
+					position: Vector::default(),
+					intensity: f64::INFINITY,
+					falloff: false,
+				},
+				Light::Area {
+					position: Vector::default(),
+					radius: -1.0,
+					intensity: 1.0,
+					samples: 4,
+				},
+			],
+		);
+
+		// Only the invalid objects and lights are listed, in order
+		let problems = scene.validate().unwrap_err().problems;
+		let items: Vec<&str> = problems.iter().map(|(item, _)| item.as_str()).collect();
+		assert_eq!(
+			items,
+			["object 1", "object 2", "object 3", "light 1", "light 2", "light 3", "light 4"]
+		);
+		assert!(matches!(problems[0].1, GeometryError::NonFinite));
+		assert!(matches!(problems[1].1, GeometryError::NonPositiveSize(size) if size == 0.0));
+		assert!(matches!(problems[2].1, GeometryError::Degenerate));
+		assert!(matches!(problems[3].1, GeometryError::Degenerate));
+		assert!(matches!(
+			problems[4].1,
+			GeometryError::InvalidConeAngles { inner, outer } if inner == 0.4 && outer == 0.2
+		));
+		assert!(matches!(problems[5].1, GeometryError::NonFinite));
+		assert!(matches!(problems[6].1, GeometryError::NonPositiveSize(size) if size == -1.0));
+	}
+
+	#[test]
+	fn wrap_modes_outside_the_texture() {
+		// A row of texels with values 0 to 3, sampled a quarter and one and three quarters across
+		let image = RadianceImage {
+			width: 4,
+			height: 1,
+			pixels: (0..4).map(|x| Vector::new(x as f64, 0.0, 0.0)).collect(),
+		};
+		let sample = |u: f64, wrap: WrapMode| image.sample_wrapped((u, 0.5), wrap).x;
+
+		// Clamped coordinates take the edge texels
+		assert_eq!(sample(-0.25, WrapMode::Clamp), 0.0);
+		assert_eq!(sample(1.75, WrapMode::Clamp), 3.0);
+
+		// Repeated, both sample three quarters across, between the last two texels
+		assert_eq!(sample(-0.25, WrapMode::Repeat), 2.5);
+		assert_eq!(sample(1.75, WrapMode::Repeat), 2.5);
+		assert_eq!(sample(0.75, WrapMode::Repeat), 2.5);
+
+		// Mirrored, both sample a quarter across, between the first two texels
+		assert_eq!(sample(-0.25, WrapMode::Mirror), 0.5);
+		assert_eq!(sample(1.75, WrapMode::Mirror), 0.5);
+		assert_eq!(sample(0.25, WrapMode::Mirror), 0.5);
+	}
+
+	#[test]
+	fn thick_glass_absorbs_more_than_thin_glass() {
+		// Without refraction the ray passes straight through the center, over a distance of twice the radius (less the
+		// offsets of the ray origins from the surface)
+		let color_through = |radius: f64| {
+			let tinted = Material {
+				absorption: Vector::new(0.1, 0.5, 1.0),
+				..glass(1.0)
+			};
+			let mut scene = scene(vec![sphere(radius, tinted)], vec![]);
+			scene.background = Background::Solid(white());
+			let ray = Ray::new(Vector::default(), Vector::new(0.0, 0.0, -1.0));
+			scene.cast_ray(&ray, 4)
+		};
+
+		for radius in [0.5, 1.0] {
+			let color = color_through(radius);
+			let expected = |absorption: f64| (-absorption * 2.0 * radius).exp();
+			assert!((color.x - expected(0.1)).abs() < 1e-3);
+			assert!((color.y - expected(0.5)).abs() < 1e-3);
+			assert!((color.z - expected(1.0)).abs() < 1e-3);
+		}
+		let (thin, thick) = (color_through(0.5), color_through(1.0));
+		assert!(thick.x < thin.x && thick.y < thin.y && thick.z < thin.z);
 	}
 }
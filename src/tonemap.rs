@@ -0,0 +1,72 @@
+use super::geometry::Vector;
+use std::str::FromStr;
+
+/** Operator used to map linear radiance values to displayable colors in [0, 1]. */
+#[derive(Clone, Copy)]
+pub enum ToneMapping {
+	/** Scale down the color so that its brightest channel is at most 1.0 */
+	Clamp,
+
+	/** Reinhard operator, c / (1 + c) per channel */
+	Reinhard,
+
+	/** Narkowicz' approximation of the ACES filmic curve */
+	Aces,
+}
+
+impl ToneMapping {
+	pub fn apply(self, color: Vector) -> Vector {
+		match self {
+			ToneMapping::Clamp => {
+				let max = color.x.max(color.y.max(color.z));
+				if max > 1.0 {
+					color / max
+				} else {
+					color
+				}
+			}
+			ToneMapping::Reinhard => Vector {
+				x: color.x / (1.0 + color.x),
+				y: color.y / (1.0 + color.y),
+				z: color.z / (1.0 + color.z),
+			},
+			ToneMapping::Aces => {
+				let aces = |c: f64| {
+					((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+				};
+				Vector {
+					x: aces(color.x),
+					y: aces(color.y),
+					z: aces(color.z),
+				}
+			}
+		}
+	}
+}
+
+impl FromStr for ToneMapping {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<ToneMapping, String> {
+		match s {
+			"clamp" => Ok(ToneMapping::Clamp),
+			"reinhard" => Ok(ToneMapping::Reinhard),
+			"aces" => Ok(ToneMapping::Aces),
+			_ => Err(format!("unknown tone mapping operator '{}'", s)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reinhard_maps_bright_colors_below_one() {
+		let color = ToneMapping::Reinhard.apply(Vector::new(10.0, 10.0, 10.0));
+		for channel in [color.x, color.y, color.z] {
+			assert!(channel < 1.0);
+			assert!((channel - 10.0 / 11.0).abs() < 1e-12);
+		}
+	}
+}
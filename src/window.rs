@@ -0,0 +1,42 @@
+use super::geometry::Vector;
+use super::output::RenderBuffer;
+use log::warn;
+use minifb::{Key, Window, WindowOptions};
+use std::sync::Mutex;
+
+/** Number of times per second the window shows the current state of the buffer */
+const REFRESH_RATE: usize = 10;
+
+/** Show the image in the buffer in a window while it is being rendered into it by other threads, until the window is
+closed (or Escape is pressed). Pixels are converted to display colors (red, green and blue) with the function. As some
+platforms (such as macOS) only allow windows on the main thread, this should be called by the main thread. */
+pub fn show<F: Fn(Vector) -> [u8; 3]>(title: &str, buffer: &Mutex<RenderBuffer>, display_color: F) {
+	let (width, height) = {
+		let buffer = buffer.lock().unwrap();
+		(buffer.width as usize, buffer.height as usize)
+	};
+	let mut window = match Window::new(title, width, height, WindowOptions::default()) {
+		Ok(window) => window,
+		Err(e) => {
+			warn!("Could not open window: {}", e);
+			return;
+		}
+	};
+	window.set_target_fps(REFRESH_RATE);
+
+	while window.is_open() && !window.is_key_down(Key::Escape) {
+		// The buffer is copied so that the renderer is not held up while converting colors
+		let pixels = buffer.lock().unwrap().pixels.clone();
+		let pixels: Vec<u32> = pixels
+			.into_iter()
+			.map(|color| {
+				let [r, g, b] = display_color(color);
+				(u32::from(r) << 16) | (u32::from(g) << 8) | u32::from(b)
+			})
+			.collect();
+		if let Err(e) = window.update_with_buffer(&pixels, width, height) {
+			warn!("Could not update window: {}", e);
+			return;
+		}
+	}
+}